@@ -4,61 +4,248 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use futures::{sink::SinkExt, stream::StreamExt};
 use ratatui::{
     prelude::*,
-    prelude::*,
-    widgets::{Block, Borders, Clear, List, ListItem, Padding, Paragraph, Wrap},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Padding, Paragraph, Wrap},
+};
+use sdk::{ChatClient, Session};
+use shared::{
+    ChatHistory, ClientMessage, ConversationInfo, ExportFormat, GenerationStats, GgufMetadata, Message as SharedMessage, MessageId,
+    format_bytes, ModelDescriptor, ModelState, ProcessUsage, Role, SearchHit, SearchQuery, ServerMessage, ToolCall,
 };
-use shared::{Role, ServerMessage, Message as SharedMessage, ClientMessage};
 use std::io;
 use tokio::sync::mpsc;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message as WsMessage};
 
 struct App {
+    /// Mirror of the server's conversation tree, used for branch navigation.
+    history: ChatHistory,
     messages: Vec<SharedMessage>,
     current_response: String,
+    /// Stats of the reply being streamed, received right before it ends.
+    current_stats: Option<GenerationStats>,
     current_model: String,
+    /// Latest resources of the llama-server process, if the server runs one.
+    usage: Option<ProcessUsage>,
+    /// Tool call waiting for the user to allow it (`y`) or refuse it (`n`).
+    approval: Option<ToolCall>,
     input: String,
-    tx: mpsc::Sender<String>,
+    client: ChatClient,
     // Modal State
     show_model_selector: bool,
-    available_models: Vec<String>,
+    available_models: Vec<ModelDescriptor>,
     selected_model_index: usize,
+    show_search_results: bool,
+    search_hits: Vec<SearchHit>,
+    selected_hit_index: usize,
+    show_conversations: bool,
+    conversations: Vec<ConversationInfo>,
+    selected_conversation_index: usize,
 }
 
 impl App {
-    fn new(tx: mpsc::Sender<String>) -> Self {
+    fn new(client: ChatClient, session: Session) -> Self {
+        let mut available_models = session.models;
+        sort_models(&mut available_models);
         Self {
-            messages: Vec::new(),
+            messages: session.history.active_path().into_iter().cloned().collect(),
+            current_model: session.history.current_model.clone(),
+            history: session.history,
             current_response: String::new(),
-            current_model: "Unknown".to_string(),
+            current_stats: None,
+            usage: None,
+            approval: None,
             input: String::new(),
-            tx,
+            client,
             show_model_selector: false,
-            available_models: Vec::new(),
+            available_models,
             selected_model_index: 0,
+            show_search_results: false,
+            search_hits: Vec::new(),
+            selected_hit_index: 0,
+            show_conversations: false,
+            conversations: Vec::new(),
+            selected_conversation_index: 0,
+        }
+    }
+
+    fn handle_server_message(&mut self, msg: ServerMessage) {
+        match msg {
+            // Only sent during the handshake, which the SDK handles.
+            ServerMessage::Welcome { .. } => {}
+            ServerMessage::History(history) => {
+                self.messages = history.active_path().into_iter().cloned().collect();
+                self.current_model = history.current_model.clone();
+                self.history = history;
+            }
+            ServerMessage::Token(token) => {
+                self.current_response.push_str(&token);
+            }
+            ServerMessage::Stats(stats) => {
+                self.current_stats = Some(stats);
+            }
+            ServerMessage::ResourceUsage(usage) => {
+                self.usage = Some(usage);
+            }
+            ServerMessage::EndOfMessage => {
+                self.approval = None;
+                self.finish_reply(Vec::new());
+            }
+            ServerMessage::ToolCalls(calls) => {
+                self.finish_reply(calls);
+            }
+            ServerMessage::ToolApproval(call) => {
+                self.approval = Some(call);
+            }
+            ServerMessage::ToolResult { call_id, content } => {
+                self.approval = None;
+                let id = self.history.push_tool_result(call_id, content);
+                if let Some(message) = self.history.get(id) {
+                    self.messages.push(message.clone());
+                }
+            }
+            ServerMessage::ModelChanged(new_model) => {
+                 for model in &mut self.available_models {
+                     if model.name == new_model {
+                         model.state = ModelState::Loaded;
+                         model.error = None;
+                     } else if model.state == ModelState::Loaded {
+                         model.state = ModelState::Available;
+                     }
+                 }
+                 self.current_model = new_model;
+                 self.push_notice(format!("System: Model switched to {}", self.current_model));
+            }
+            ServerMessage::AvailableModels(mut models) => {
+                sort_models(&mut models);
+                // Keep the selection on the same model.
+                let selected = self.available_models.get(self.selected_model_index).map(|m| m.name.clone());
+                self.selected_model_index = selected
+                    .and_then(|name| models.iter().position(|m| m.name == name))
+                    .unwrap_or(0);
+                self.available_models = models;
+            }
+            ServerMessage::Branches { .. } => {
+                // Branches are derived from the mirrored history instead.
+            }
+            ServerMessage::Exported { path, content, .. } => {
+                let notice = match (path, content) {
+                    (Some(path), _) => format!("System: Exported to {}", path),
+                    (None, Some(content)) => format!("System: Export ({} bytes)", content.len()),
+                    (None, None) => "System: Export returned nothing".to_string(),
+                };
+                self.push_notice(notice);
+            }
+            ServerMessage::SearchResults(hits) => {
+                if hits.is_empty() {
+                    self.push_notice("System: No matches".to_string());
+                } else {
+                    self.search_hits = hits;
+                    self.selected_hit_index = 0;
+                    self.show_search_results = true;
+                }
+            }
+            ServerMessage::Conversations(conversations) => {
+                self.conversations = conversations;
+                self.selected_conversation_index = 0;
+                self.show_conversations = true;
+            }
+            ServerMessage::ConversationUpdated(info) => {
+                if info.id == self.history.id {
+                    self.history.title = info.title.clone();
+                    self.history.summary = info.summary.clone();
+                }
+                if let Some(entry) = self.conversations.iter_mut().find(|c| c.id == info.id) {
+                    *entry = info;
+                }
+            }
+            ServerMessage::Error(err) => {
+                self.push_notice(format!("System Error: {}", err));
+            }
         }
     }
+
+    fn modal_open(&self) -> bool {
+        self.show_model_selector || self.show_search_results || self.show_conversations
+    }
+
+    /// Appends a message to the active branch and to the visible transcript.
+    fn push_message(&mut self, role: Role, content: String) {
+        let id = self.history.push(role, content);
+        if let Some(message) = self.history.get(id) {
+            self.messages.push(message.clone());
+        }
+    }
+
+    /// Appends the streamed reply, with the tools it calls and its stats.
+    fn finish_reply(&mut self, tool_calls: Vec<ToolCall>) {
+        let content = std::mem::take(&mut self.current_response);
+        self.push_message(Role::Assistant, content);
+        let stats = self.current_stats.take();
+        if let Some(id) = self.history.active_leaf {
+            if let Some(message) = self.history.get_mut(id) {
+                message.stats = stats.clone();
+                message.tool_calls = tool_calls.clone();
+            }
+        }
+        if let Some(message) = self.messages.last_mut() {
+            message.stats = stats;
+            message.tool_calls = tool_calls;
+        }
+    }
+
+    /// Appends a local notice that is shown but not part of the conversation.
+    fn push_notice(&mut self, content: String) {
+        self.messages.push(SharedMessage {
+            id: 0,
+            parent: None,
+            role: Role::Assistant,
+            content,
+            model: None,
+            timestamp: None,
+            stats: None,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        });
+    }
+
+    /// Returns the last user message of the active branch.
+    fn last_user_message(&self) -> Option<MessageId> {
+        self.history
+            .active_path()
+            .iter()
+            .rev()
+            .find(|m| matches!(m.role, Role::User))
+            .map(|m| m.id)
+    }
+
+    /// Picks the sibling `step` positions away from the deepest message on the
+    /// active branch that has alternatives.
+    fn neighbour_branch(&self, step: isize) -> Option<MessageId> {
+        self.history.active_path().iter().rev().find_map(|m| {
+            let siblings = self.history.siblings(m.id);
+            if siblings.len() < 2 {
+                return None;
+            }
+            let pos = siblings.iter().position(|s| s.id == m.id)? as isize + step;
+            siblings.get(usize::try_from(pos).ok()?).map(|s| s.id)
+        })
+    }
+
+    /// Returns the "[n/m]" position of a message among its siblings, if it has any.
+    fn branch_marker(&self, message: &SharedMessage) -> Option<String> {
+        let siblings = self.history.siblings(message.id);
+        if siblings.len() < 2 {
+            return None;
+        }
+        let pos = siblings.iter().position(|s| s.id == message.id)?;
+        Some(format!("[{}/{}] ", pos + 1, siblings.len()))
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Setup WebSocket
-    let (ws_stream, _) = connect_async("ws://127.0.0.1:3001/ws").await?;
-    let (mut write, mut read) = ws_stream.split();
-
-    // Channel for sending messages from UI to WS
-    let (tx, mut rx) = mpsc::channel::<String>(32);
-
-    // Forward channel messages to WebSocket
-    tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if write.send(WsMessage::Text(msg.into())).await.is_err() {
-                break;
-            }
-        }
-    });
+    // Connect before touching the terminal so errors are readable.
+    let (client, session, mut events) = ChatClient::connect(sdk::DEFAULT_URL).await?;
 
     // Event Channel
     let (tx_event, mut rx_event) = mpsc::channel(100);
@@ -85,7 +272,7 @@ async fn main() -> Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     // App State
-    let mut app = App::new(tx);
+    let mut app = App::new(client, session);
     let mut running = true;
     let mut tick_rate = tokio::time::interval(std::time::Duration::from_millis(30));
 
@@ -97,54 +284,13 @@ async fn main() -> Result<()> {
              // Tick for smooth UI (optional but good practice)
             _ = tick_rate.tick() => {}
 
-            // Handle Incoming WS Messages
-            val = read.next() => {
-                if let Some(Ok(msg)) = val {
-                    match msg {
-                        WsMessage::Text(text) => {
-                             if let Ok(server_msg) = serde_json::from_str::<ServerMessage>(&text) {
-                                match server_msg {
-                                    ServerMessage::History(history) => {
-                                        app.messages = history.messages;
-                                        app.current_model = history.current_model;
-                                    }
-                                    ServerMessage::Token(token) => {
-                                        app.current_response.push_str(&token);
-                                    }
-                                    ServerMessage::EndOfMessage => {
-                                        app.messages.push(SharedMessage {
-                                            role: Role::Assistant,
-                                            content: app.current_response.clone(),
-                                        });
-                                        app.current_response.clear();
-                                    }
-                                    ServerMessage::ModelChanged(new_model) => {
-                                         app.current_model = new_model;
-                                         app.messages.push(SharedMessage {
-                                             role: Role::Assistant,
-                                             content: format!("System: Model switched to {}", app.current_model),
-                                         });
-                                    }
-                                    ServerMessage::AvailableModels(models) => {
-                                        app.available_models = models;
-                                        app.available_models.sort();
-                                    }
-                                    ServerMessage::Error(err) => {
-                                        app.messages.push(SharedMessage {
-                                            role: Role::Assistant,
-                                            content: format!("System Error: {}", err),
-                                        });
-                                    }
-                                }
-                             }
-                        }
-                        _ => {}
-                    }
-                } else {
-                    break;
-                }
-            }
-            
+            // Handle Incoming Server Messages
+            event = events.next() => match event {
+                Some(Ok(frame)) => app.handle_server_message(frame.message),
+                Some(Err(e)) => app.push_notice(format!("System Error: {}", e)),
+                None => break,
+            },
+
             // Handle User Input
             Some(evt) = rx_event.recv() => {
                 if let Event::Key(key) = evt {
@@ -153,24 +299,63 @@ async fn main() -> Result<()> {
                             KeyCode::Char('s') if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) => {
                                 app.show_model_selector = !app.show_model_selector;
                             }
+                            // Conversation List Handling
+                            KeyCode::Up if app.show_conversations => {
+                                app.selected_conversation_index = app.selected_conversation_index.saturating_sub(1);
+                            }
+                            KeyCode::Down if app.show_conversations => {
+                                let last = app.conversations.len().saturating_sub(1);
+                                app.selected_conversation_index = (app.selected_conversation_index + 1).min(last);
+                            }
+                            KeyCode::Enter if app.show_conversations => {
+                                if let Some(conversation) = app.conversations.get(app.selected_conversation_index) {
+                                    let open = ClientMessage::OpenConversation(conversation.id.clone());
+                                    if app.client.send(open).await.is_err() {
+                                        break;
+                                    }
+                                }
+                                app.show_conversations = false;
+                            }
+                            KeyCode::Esc if app.show_conversations => {
+                                app.show_conversations = false;
+                            }
+                            // Search Results Handling
+                            KeyCode::Up if app.show_search_results => {
+                                app.selected_hit_index = app.selected_hit_index.saturating_sub(1);
+                            }
+                            KeyCode::Down if app.show_search_results => {
+                                let last = app.search_hits.len().saturating_sub(1);
+                                app.selected_hit_index = (app.selected_hit_index + 1).min(last);
+                            }
+                            KeyCode::Enter if app.show_search_results => {
+                                // Open the hit's conversation, then make its branch active.
+                                if let Some(hit) = app.search_hits.get(app.selected_hit_index) {
+                                    let open = ClientMessage::OpenConversation(hit.conversation_id.clone());
+                                    let switch = ClientMessage::SwitchBranch(hit.message_id);
+                                    if app.client.send(open).await.is_err()
+                                        || app.client.send(switch).await.is_err()
+                                    {
+                                        break;
+                                    }
+                                }
+                                app.show_search_results = false;
+                            }
+                            KeyCode::Esc if app.show_search_results => {
+                                app.show_search_results = false;
+                            }
                             // Modal Handling
                             KeyCode::Up if app.show_model_selector => {
-                                if app.selected_model_index > 0 {
-                                    app.selected_model_index -= 1;
-                                }
+                                app.selected_model_index = app.selected_model_index.saturating_sub(1);
                             }
                             KeyCode::Down if app.show_model_selector => {
-                                if !app.available_models.is_empty() && app.selected_model_index < app.available_models.len() - 1 {
-                                    app.selected_model_index += 1;
-                                }
+                                let last = app.available_models.len().saturating_sub(1);
+                                app.selected_model_index = (app.selected_model_index + 1).min(last);
                             }
                             KeyCode::Enter if app.show_model_selector => {
                                 if let Some(model) = app.available_models.get(app.selected_model_index) {
-                                    let client_msg = ClientMessage::SetModel(model.clone());
-                                     if let Ok(json) = serde_json::to_string(&client_msg) {
-                                        if app.tx.send(json).await.is_err() {
-                                            break;
-                                        }
+                                    let client_msg = ClientMessage::SetModel(model.name.clone());
+                                    if app.client.send(client_msg).await.is_err() {
+                                        break;
                                     }
                                     app.show_model_selector = false;
                                 }
@@ -179,36 +364,102 @@ async fn main() -> Result<()> {
                                 app.show_model_selector = false;
                             }
                             
+                            // Tool approval, while nothing is typed
+                            KeyCode::Char(c @ ('y' | 'n')) if app.approval.is_some() && app.input.is_empty() && !app.modal_open() => {
+                                if let Some(call) = app.approval.take() {
+                                    if app.client.approve_tool(call.id, c == 'y').await.is_err() {
+                                        break;
+                                    }
+                                }
+                            }
+
                             // Normal Handling
                             KeyCode::Esc => running = false,
-                            KeyCode::Char(c) if !app.show_model_selector => app.input.push(c),
-                            KeyCode::Backspace if !app.show_model_selector => { app.input.pop(); },
-                            KeyCode::Enter if !app.show_model_selector => {
+                            KeyCode::Char(c) if !app.modal_open() => app.input.push(c),
+                            KeyCode::Backspace if !app.modal_open() => { app.input.pop(); },
+                            KeyCode::Enter if !app.modal_open() => {
                                 let msg = app.input.drain(..).collect::<String>();
                                 
                                 // Check for slash commands
-                                if msg.starts_with("/model ") {
-                                    let model_name = msg.trim_start_matches("/model ").to_string();
-                                    let client_msg = ClientMessage::SetModel(model_name);
-                                    if let Ok(json) = serde_json::to_string(&client_msg) {
-                                        if let Err(_) = app.tx.send(json).await {
-                                            break;
+                                let client_msg = if let Some(model_name) = msg.strip_prefix("/model ") {
+                                    Some(ClientMessage::SetModel(model_name.to_string()))
+                                } else if msg == "/regen" {
+                                    Some(ClientMessage::Regenerate)
+                                } else if msg == "/stop" {
+                                    Some(ClientMessage::Stop)
+                                } else if msg == "/rescan" {
+                                    Some(ClientMessage::RescanModels)
+                                } else if let Some(setting) = msg.strip_prefix("/auto-approve") {
+                                    match setting.trim() {
+                                        "on" => Some(ClientMessage::SetAutoApprove(true)),
+                                        "off" => Some(ClientMessage::SetAutoApprove(false)),
+                                        _ => {
+                                            app.push_notice("System: Usage: /auto-approve on|off".to_string());
+                                            None
                                         }
                                     }
+                                } else if let Some(content) = msg.strip_prefix("/edit ") {
+                                    match app.last_user_message() {
+                                        Some(id) => Some(ClientMessage::Edit { id, content: content.to_string() }),
+                                        None => {
+                                            app.push_notice("System: Nothing to edit".to_string());
+                                            None
+                                        }
+                                    }
+                                } else if msg == "/prev" || msg == "/next" {
+                                    let step = if msg == "/prev" { -1 } else { 1 };
+                                    match app.neighbour_branch(step) {
+                                        Some(id) => Some(ClientMessage::SwitchBranch(id)),
+                                        None => {
+                                            app.push_notice("System: No other branch".to_string());
+                                            None
+                                        }
+                                    }
+                                } else if let Some(args) = msg.strip_prefix("/export") {
+                                    // "/export [md|html|json] [all]"
+                                    let mut format = Some(ExportFormat::Markdown);
+                                    let mut all = false;
+                                    for arg in args.split_whitespace() {
+                                        match arg {
+                                            "md" | "markdown" => format = Some(ExportFormat::Markdown),
+                                            "html" => format = Some(ExportFormat::Html),
+                                            "json" => format = Some(ExportFormat::Json),
+                                            "all" => all = true,
+                                            _ => format = None,
+                                        }
+                                    }
+                                    match format {
+                                        Some(format) => Some(ClientMessage::Export { format, all, save: true }),
+                                        None => {
+                                            app.push_notice("System: Usage: /export [md|html|json] [all]".to_string());
+                                            None
+                                        }
+                                    }
+                                } else if msg == "/new" {
+                                    Some(ClientMessage::NewConversation)
+                                } else if msg == "/conversations" {
+                                    Some(ClientMessage::ListConversations)
+                                } else if let Some(title) = msg.strip_prefix("/title ") {
+                                    Some(ClientMessage::SetConversationInfo {
+                                        id: app.history.id.clone(),
+                                        title: Some(title.trim().to_string()),
+                                        summary: None,
+                                    })
+                                } else if let Some(query) = msg.strip_prefix("/search ") {
+                                    Some(ClientMessage::Search(SearchQuery::parse(query)))
                                 } else if !msg.is_empty() {
                                     // Normal message
-                                     // Optimistic update
-                                    app.messages.push(SharedMessage {
-                                        role: Role::User,
-                                        content: msg.clone(),
-                                    });
-                                    
-                                    let client_msg = ClientMessage::Text(msg);
-                                     if let Ok(json) = serde_json::to_string(&client_msg) {
-                                        if let Err(_) = app.tx.send(json).await {
-                                            break;
-                                        }
-                                     }
+                                    // Optimistic update
+                                    app.push_message(Role::User, msg.clone());
+                                    Some(ClientMessage::Text(msg))
+                                } else {
+                                    None
+                                };
+
+                                if let Some(client_msg) = client_msg {
+                                    if app.client.send(client_msg).await.is_err() {
+                                        break;
+                                    }
                                 }
                             }
                             _ => {}
@@ -233,6 +484,7 @@ fn ui(f: &mut Frame, app: &App) {
         .constraints([
             Constraint::Min(1),
             Constraint::Length(3),
+            Constraint::Length(1),
         ])
         .split(f.area());
 
@@ -240,12 +492,23 @@ fn ui(f: &mut Frame, app: &App) {
         .messages
         .iter()
         .map(|m| {
-            let prefix = match m.role {
-                Role::User => "You: ",
-                Role::Assistant => "Assistant: ",
+            let tool = Style::default().fg(Color::Cyan);
+            let marker = app.branch_marker(m).unwrap_or_default();
+            let mut lines = match m.role {
+                Role::User => vec![Line::from(format!("{}You: {}", marker, m.content))],
+                // A reply asking for tools only has no text to show.
+                Role::Assistant if m.content.is_empty() && !m.tool_calls.is_empty() => Vec::new(),
+                Role::Assistant => vec![Line::from(format!("{}Assistant: {}", marker, m.content))],
+                Role::Tool => {
+                    let call = m.tool_call_id.as_deref().and_then(|id| app.history.tool_call(id));
+                    let name = call.map_or("tool", |call| call.name.as_str());
+                    tool_result_lines(&format!("{}{}", marker, name), &m.content, tool)
+                }
             };
-            let content = format!("{}{}", prefix, m.content);
-            ListItem::new(Line::from(vec![Span::raw(content)]))
+            for call in &m.tool_calls {
+                lines.push(Line::from(Span::styled(format!("Calling {}", call.summary()), tool)));
+            }
+            ListItem::new(lines)
         })
         .collect();
     
@@ -256,7 +519,16 @@ fn ui(f: &mut Frame, app: &App) {
     }
 
     let messages_widget = List::new(list_items)
-        .block(Block::default().borders(Borders::ALL).title(format!("Chat - Model: {}", app.current_model)));
+        .block(Block::default().borders(Borders::ALL).title({
+            let mut title = match &app.history.title {
+                Some(title) => format!("Chat - {} - Model: {}", title, app.current_model),
+                None => format!("Chat - Model: {}", app.current_model),
+            };
+            if app.history.auto_approve {
+                title.push_str(" - Tools run without asking");
+            }
+            title
+        }));
     
     f.render_widget(messages_widget, chunks[0]);
 
@@ -266,28 +538,252 @@ fn ui(f: &mut Frame, app: &App) {
     
     f.render_widget(input, chunks[1]);
 
+    // Status bar: stats of the last reply on the active branch, then the
+    // resources of the model's llama-server.
+    let mut status = match app.messages.iter().rev().find_map(|m| m.stats.as_ref()) {
+        _ if !app.current_response.is_empty() => "Generating… (/stop to cut it short)".to_string(),
+        Some(stats) => format!("Last reply: {}", stats.summary()),
+        None => String::new(),
+    };
+    if let Some(usage) = app.usage.as_ref().filter(|u| u.model == app.current_model) {
+        if !status.is_empty() {
+            status.push_str("  |  ");
+        }
+        status.push_str(&format!("llama-server: {}", usage.summary()));
+    }
+    match &app.approval {
+        Some(call) => {
+            let ask = format!("Allow {}? y = allow, n = refuse, /stop = refuse and stop", call.summary());
+            f.render_widget(Paragraph::new(ask).style(Style::default().fg(Color::Yellow)), chunks[2]);
+        }
+        None => f.render_widget(Paragraph::new(status).style(Style::default().fg(Color::DarkGray)), chunks[2]),
+    }
+
+    if app.show_search_results {
+        render_search_results(f, app);
+    }
+
+    if app.show_conversations {
+        render_conversations(f, app);
+    }
+
     // Render Modal
     if app.show_model_selector {
         let block = Block::default().title("Select Model").borders(Borders::ALL);
-        let area = centered_rect(60, 20, f.area());
+        let area = centered_rect(70, 50, f.area());
         f.render_widget(Clear, area); // Clear background
         f.render_widget(block.clone(), area);
 
-        let items: Vec<ListItem> = app.available_models.iter().enumerate().map(|(i, model)| {
-            let style = if i == app.selected_model_index {
-                Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
-            } else {
-                Style::default()
-            };
-            ListItem::new(Line::from(vec![Span::styled(model, style)]))
-        }).collect();
+        // Models under a heading per group, each with its state, tags and
+        // what is known about it, dimmed below the name.
+        let mut items: Vec<ListItem> = Vec::new();
+        let mut selected_item = 0;
+        let mut group = None;
+        for (i, model) in app.available_models.iter().enumerate() {
+            if group != Some(model_group(model)) {
+                group = Some(model_group(model));
+                items.push(ListItem::new(Line::from(Span::styled(
+                    model_group(model),
+                    Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                ))));
+            }
+            if i == app.selected_model_index {
+                selected_item = items.len();
+            }
+            items.push(model_item(model, i == app.selected_model_index));
+        }
 
         let list = List::new(items)
             .block(Block::default().borders(Borders::NONE).padding(Padding::new(1, 1, 1, 1)));
-        
+        let mut list_state = ListState::default().with_selected(Some(selected_item));
+
         let inner_area = block.inner(area);
-        f.render_widget(list, inner_area);
+        f.render_stateful_widget(list, inner_area, &mut list_state);
+    }
+}
+
+/// Lines of tool output shown in the transcript.
+const TOOL_RESULT_LINES: usize = 8;
+
+/// A tool result, headed by `label` and cut to its first lines.
+fn tool_result_lines<'a>(label: &str, content: &'a str, style: Style) -> Vec<Line<'a>> {
+    let mut lines = vec![Line::from(Span::styled(format!("{} returned:", label), style))];
+    lines.extend(content.lines().take(TOOL_RESULT_LINES).map(|line| Line::from(format!("  {}", line))));
+    let more = content.lines().count().saturating_sub(TOOL_RESULT_LINES);
+    if more > 0 {
+        lines.push(Line::from(Span::styled(format!("  … {} more lines", more), Style::default().fg(Color::DarkGray))));
+    }
+    lines
+}
+
+/// Heading a model is listed under in the selector.
+fn model_group(model: &ModelDescriptor) -> &'static str {
+    match model.backend.as_str() {
+        "managed" => "Local",
+        "external" | "openai" => "Remote",
+        _ => "Testing",
+    }
+}
+
+/// Groups in selector order, each sorted by display name.
+fn sort_models(models: &mut [ModelDescriptor]) {
+    let rank = |model: &ModelDescriptor| ["Local", "Remote", "Testing"].iter().position(|g| *g == model_group(model));
+    models.sort_by_cached_key(|model| (rank(model), model.display_name.to_lowercase(), model.name.clone()));
+}
+
+/// A model of the selector: a state marker, the display name and tags, then
+/// dimmed details and, if it failed to load, why.
+fn model_item(model: &ModelDescriptor, selected: bool) -> ListItem<'_> {
+    let (marker, marker_color) = match model.state {
+        ModelState::Loaded => ("● ", Color::Green),
+        ModelState::Loading => ("◌ ", Color::Yellow),
+        ModelState::Failed => ("✗ ", Color::Red),
+        ModelState::Available => ("  ", Color::Reset),
+    };
+    let name_style = if selected {
+        Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+    } else {
+        Style::default()
+    };
+    let mut title = vec![
+        Span::styled(marker, Style::default().fg(marker_color)),
+        Span::styled(model.display_name.as_str(), name_style),
+    ];
+    if model.display_name != model.name {
+        title.push(Span::styled(format!(" ({})", model.name), Style::default().fg(Color::DarkGray)));
+    }
+    for tag in &model.tags {
+        title.push(Span::styled(format!(" [{}]", tag), Style::default().fg(Color::Cyan)));
+    }
+
+    let mut details: Vec<String> = model.capabilities.iter().map(|c| c.as_str().to_string()).collect();
+    if let Some(context) = model.context_size {
+        details.push(format!("{} ctx", context));
+    }
+    if let Some(size) = model.file_size {
+        details.push(format_bytes(size));
+    }
+    if let Some(metadata) = model.metadata.as_ref().map(model_details).filter(|d| !d.is_empty()) {
+        details.push(metadata);
     }
+
+    let mut lines = vec![Line::from(title)];
+    if let Some(description) = &model.description {
+        lines.push(Line::from(format!("    {}", description)));
+    }
+    if !details.is_empty() {
+        lines.push(Line::from(Span::styled(format!("    {}", details.join(" · ")), Style::default().fg(Color::DarkGray))));
+    }
+    if let Some(error) = &model.error {
+        lines.push(Line::from(Span::styled(format!("    {}", error), Style::default().fg(Color::Red))));
+    }
+    ListItem::new(lines)
+}
+
+/// Metadata line of the model selector, e.g.
+/// `"llama · 6.7B · Q4_K_M · 4096 ctx · llama tokenizer, 32000 tokens · chat template"`.
+fn model_details(metadata: &GgufMetadata) -> String {
+    let mut details = metadata.summary();
+    if let Some(tokenizer) = &metadata.tokenizer {
+        details.push_str(&format!(" · {} tokenizer", tokenizer));
+        if let Some(vocab) = metadata.vocab_size {
+            details.push_str(&format!(", {} tokens", vocab));
+        }
+    }
+    if metadata.chat_template.is_some() {
+        details.push_str(" · chat template");
+    }
+    details
+}
+
+fn render_conversations(f: &mut Frame, app: &App) {
+    let block = Block::default()
+        .title("Conversations (Enter: open, Esc: close)")
+        .borders(Borders::ALL);
+    let area = centered_rect(80, 60, f.area());
+    f.render_widget(Clear, area);
+    f.render_widget(block.clone(), area);
+
+    let items: Vec<ListItem> = app
+        .conversations
+        .iter()
+        .enumerate()
+        .map(|(i, conversation)| {
+            let style = if i == app.selected_conversation_index {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+            let title = conversation.title.as_deref().unwrap_or(&conversation.id);
+            let mut lines = vec![Line::from(Span::styled(
+                format!("{} ({} messages)", title, conversation.message_count),
+                style.add_modifier(Modifier::BOLD),
+            ))];
+            if let Some(summary) = &conversation.summary {
+                lines.push(Line::from(Span::styled(summary.clone(), style.add_modifier(Modifier::DIM))));
+            }
+            ListItem::new(lines)
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::NONE).padding(Padding::new(1, 1, 1, 1)));
+    f.render_widget(list, block.inner(area));
+}
+
+fn render_search_results(f: &mut Frame, app: &App) {
+    let block = Block::default()
+        .title("Search Results (Enter: open, Esc: close)")
+        .borders(Borders::ALL);
+    let area = centered_rect(80, 60, f.area());
+    f.render_widget(Clear, area);
+    f.render_widget(block.clone(), area);
+
+    let items: Vec<ListItem> = app
+        .search_hits
+        .iter()
+        .enumerate()
+        .map(|(i, hit)| {
+            let selected = i == app.selected_hit_index;
+            let base = if selected {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+            let role = match hit.role {
+                Role::User => "You",
+                Role::Assistant => "Assistant",
+                Role::Tool => "Tool",
+            };
+            let title = hit.conversation_title.as_deref().unwrap_or(&hit.conversation_id);
+            let header = Line::from(Span::styled(
+                format!("{} · {}", title, role),
+                base.add_modifier(Modifier::BOLD),
+            ));
+
+            // Split the snippet into plain and highlighted spans.
+            let mut spans = Vec::new();
+            let mut pos = 0;
+            for &(start, end) in &hit.highlights {
+                if start < pos || end > hit.snippet.len() {
+                    continue;
+                }
+                spans.push(Span::styled(hit.snippet[pos..start].to_string(), base));
+                spans.push(Span::styled(
+                    hit.snippet[start..end].to_string(),
+                    base.fg(Color::Black).bg(Color::Yellow),
+                ));
+                pos = end;
+            }
+            spans.push(Span::styled(hit.snippet[pos..].to_string(), base));
+
+            ListItem::new(vec![header, Line::from(spans)])
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::NONE).padding(Padding::new(1, 1, 1, 1)));
+    f.render_widget(list, block.inner(area));
 }
 
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -0,0 +1,266 @@
+//! Async client for the chat server's WebSocket protocol.
+//!
+//! [`ChatClient::connect`] opens the connection and performs the handshake,
+//! returning the client, the initial [`Session`] state and the [`Events`]
+//! stream of everything the server sends afterwards. Each request gets an
+//! id, echoed in `reply_to` on the messages answering it:
+//!
+//! ```no_run
+//! # async fn demo() -> anyhow::Result<()> {
+//! use shared::ServerMessage;
+//!
+//! let (client, session, mut events) = sdk::ChatClient::connect(sdk::DEFAULT_URL).await?;
+//! println!("{} models available", session.models.len());
+//! let request = client.send_text("Hello!").await?;
+//! while let Some(frame) = events.next().await {
+//!     let frame = frame?;
+//!     if frame.reply_to != Some(request) {
+//!         continue;
+//!     }
+//!     match frame.message {
+//!         ServerMessage::Token(token) => print!("{}", token),
+//!         ServerMessage::EndOfMessage => break,
+//!         _ => {}
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Result};
+use futures::{SinkExt, Stream, StreamExt};
+use shared::{
+    ChatHistory, ClientFrame, ClientMessage, ExportFormat, MessageId, ModelDescriptor, RequestId, SearchQuery, ServerFrame,
+    ServerMessage, PROTOCOL_VERSION,
+};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+/// Where the server listens by default.
+pub const DEFAULT_URL: &str = "ws://127.0.0.1:3001/ws";
+
+/// How long the server may take to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// State sent by the server when connecting.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Server name and version.
+    pub server: String,
+    /// Optional protocol features the server supports, e.g. `"stop"`.
+    pub capabilities: Vec<String>,
+    /// The active conversation.
+    pub history: ChatHistory,
+    /// The configured models, sorted by name.
+    pub models: Vec<ModelDescriptor>,
+}
+
+/// Sends messages to the server. Cheap to clone; the connection is closed
+/// once every clone is dropped.
+#[derive(Clone)]
+pub struct ChatClient {
+    tx: mpsc::Sender<ClientFrame>,
+    next_id: Arc<AtomicU64>,
+}
+
+/// Messages from the server, in order. Ends when the connection closes;
+/// frames that are not valid protocol messages are reported as errors.
+pub struct Events {
+    rx: mpsc::Receiver<Result<ServerFrame>>,
+}
+
+impl ChatClient {
+    /// Connects to the server at `url` (e.g. [`DEFAULT_URL`]), says hello
+    /// and waits for the initial history and model list. Fails with the
+    /// server's reason if it rejects this client's protocol version.
+    pub async fn connect(url: &str) -> Result<(Self, Session, Events)> {
+        let (ws_stream, _) = connect_async(url)
+            .await
+            .map_err(|e| anyhow!("Failed to connect to {}: {}", url, e))?;
+        let (mut write, mut read) = ws_stream.split();
+
+        let hello = ClientFrame {
+            id: Some(0),
+            message: ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+                client: format!("sdk {}", env!("CARGO_PKG_VERSION")),
+                capabilities: Vec::new(),
+            },
+        };
+        write.send(WsMessage::Text(serde_json::to_string(&hello)?)).await?;
+
+        let handshake = async {
+            let mut welcome = None;
+            let mut history = None;
+            while let Some(frame) = read.next().await {
+                let WsMessage::Text(text) = frame? else { continue };
+                // Rejections are bare messages, whatever the version.
+                let message = match serde_json::from_str::<ServerFrame>(&text) {
+                    Ok(frame) => frame.message,
+                    Err(_) => serde_json::from_str(&text)?,
+                };
+                match message {
+                    ServerMessage::Welcome { version, server, capabilities } => {
+                        if version != PROTOCOL_VERSION {
+                            bail!("{} speaks protocol version {}, expected {}", server, version, PROTOCOL_VERSION);
+                        }
+                        welcome = Some((server, capabilities));
+                    }
+                    ServerMessage::History(h) => history = Some(h),
+                    ServerMessage::AvailableModels(models) => match (welcome.take(), history.take()) {
+                        (Some((server, capabilities)), Some(history)) => {
+                            return Ok(Session { server, capabilities, history, models })
+                        }
+                        _ => bail!("Server sent the model list before the welcome and history"),
+                    },
+                    ServerMessage::Error(e) => bail!("Server rejected the connection: {}", e),
+                    other => bail!("Unexpected message during handshake: {:?}", other),
+                }
+            }
+            bail!("Connection closed during handshake")
+        };
+        let session = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for the server at {}", url))??;
+
+        let (tx, mut outgoing) = mpsc::channel::<ClientFrame>(32);
+        tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                let json = match serde_json::to_string(&message) {
+                    Ok(json) => json,
+                    Err(_) => continue,
+                };
+                if write.send(WsMessage::Text(json)).await.is_err() {
+                    return;
+                }
+            }
+            let _ = write.close().await;
+        });
+
+        let (incoming, rx) = mpsc::channel::<Result<ServerFrame>>(256);
+        tokio::spawn(async move {
+            while let Some(frame) = read.next().await {
+                let event = match frame {
+                    Ok(WsMessage::Text(text)) => serde_json::from_str(&text)
+                        .map_err(|e| anyhow!("Invalid server message: {}", e)),
+                    Ok(WsMessage::Close(_)) => break,
+                    Ok(_) => continue,
+                    Err(e) => Err(anyhow!("Connection error: {}", e)),
+                };
+                let failed = event.is_err();
+                if incoming.send(event).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let client = Self { tx, next_id: Arc::new(AtomicU64::new(1)) };
+        Ok((client, session, Events { rx }))
+    }
+
+    /// Sends any protocol message, returning its request id.
+    pub async fn send(&self, message: ClientMessage) -> Result<RequestId> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let frame = ClientFrame { id: Some(id), message };
+        self.tx.send(frame).await.map_err(|_| anyhow!("Connection closed"))?;
+        Ok(id)
+    }
+
+    /// Sends a user message; the reply streams as `Token`s up to `EndOfMessage`.
+    pub async fn send_text(&self, text: impl Into<String>) -> Result<RequestId> {
+        self.send(ClientMessage::Text(text.into())).await
+    }
+
+    /// Switches the model; answered with `ModelChanged` or `Error`.
+    pub async fn set_model(&self, name: impl Into<String>) -> Result<RequestId> {
+        self.send(ClientMessage::SetModel(name.into())).await
+    }
+
+    /// Stops the reply being streamed, keeping what was generated so far.
+    pub async fn stop(&self) -> Result<RequestId> {
+        self.send(ClientMessage::Stop).await
+    }
+
+    /// Scans the server's models directories again; every client then gets
+    /// the new `AvailableModels`.
+    pub async fn rescan_models(&self) -> Result<RequestId> {
+        self.send(ClientMessage::RescanModels).await
+    }
+
+    /// Answers a `ToolApproval`, allowing or refusing the call.
+    pub async fn approve_tool(&self, call_id: impl Into<String>, approved: bool) -> Result<RequestId> {
+        self.send(ClientMessage::ApproveTool { call_id: call_id.into(), approved }).await
+    }
+
+    /// Lets tool calls of the current conversation run without approval.
+    pub async fn set_auto_approve(&self, enabled: bool) -> Result<RequestId> {
+        self.send(ClientMessage::SetAutoApprove(enabled)).await
+    }
+
+    /// Generates a new reply to the last prompt on a new branch.
+    pub async fn regenerate(&self) -> Result<RequestId> {
+        self.send(ClientMessage::Regenerate).await
+    }
+
+    /// Replaces user message `id` on a new branch and generates a reply.
+    pub async fn edit(&self, id: MessageId, content: impl Into<String>) -> Result<RequestId> {
+        self.send(ClientMessage::Edit { id, content: content.into() }).await
+    }
+
+    pub async fn list_branches(&self, id: MessageId) -> Result<RequestId> {
+        self.send(ClientMessage::ListBranches(id)).await
+    }
+
+    pub async fn switch_branch(&self, id: MessageId) -> Result<RequestId> {
+        self.send(ClientMessage::SwitchBranch(id)).await
+    }
+
+    pub async fn export(&self, format: ExportFormat, all: bool, save: bool) -> Result<RequestId> {
+        self.send(ClientMessage::Export { format, all, save }).await
+    }
+
+    pub async fn search(&self, query: SearchQuery) -> Result<RequestId> {
+        self.send(ClientMessage::Search(query)).await
+    }
+
+    pub async fn open_conversation(&self, id: impl Into<String>) -> Result<RequestId> {
+        self.send(ClientMessage::OpenConversation(id.into())).await
+    }
+
+    pub async fn new_conversation(&self) -> Result<RequestId> {
+        self.send(ClientMessage::NewConversation).await
+    }
+
+    pub async fn list_conversations(&self) -> Result<RequestId> {
+        self.send(ClientMessage::ListConversations).await
+    }
+
+    pub async fn set_conversation_info(
+        &self,
+        id: impl Into<String>,
+        title: Option<String>,
+        summary: Option<String>,
+    ) -> Result<RequestId> {
+        self.send(ClientMessage::SetConversationInfo { id: id.into(), title, summary }).await
+    }
+}
+
+impl Events {
+    /// The next server message, or `None` once the connection is closed.
+    pub async fn next(&mut self) -> Option<Result<ServerFrame>> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for Events {
+    type Item = Result<ServerFrame>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
@@ -0,0 +1,298 @@
+//! JSON REST API for automation.
+//!
+//! Offers the same operations as the WebSocket protocol for conversations,
+//! models and the backend, on top of the same [`AppState`]. The OpenAPI
+//! description is generated from the handlers below and served at
+//! `/api/openapi.json`.
+
+use std::sync::Arc;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use shared::{ChatHistory, ConversationInfo, GgufMetadata, Message, ProcessUsage, Role, ServerMessage};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use crate::{switch_model, AppState};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "llamacpp-chat", description = "REST API of the local chat server."),
+    paths(
+        list_conversations,
+        get_conversation,
+        delete_conversation,
+        list_messages,
+        list_models,
+        get_status,
+        set_model,
+    ),
+    components(schemas(
+        ConversationInfo,
+        ChatHistory,
+        Message,
+        Role,
+        ModelEntry,
+        GgufMetadata,
+        ProcessUsage,
+        BackendStatus,
+        SetModelRequest,
+        ApiError,
+    ))
+)]
+struct ApiDoc;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/openapi.json", get(openapi))
+        .route("/api/conversations", get(list_conversations))
+        .route("/api/conversations/:id", get(get_conversation).delete(delete_conversation))
+        .route("/api/conversations/:id/messages", get(list_messages))
+        .route("/api/models", get(list_models))
+        .route("/api/status", get(get_status))
+        .route("/api/model", put(set_model))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiError {
+    pub error: String,
+}
+
+/// An error response with a status code.
+struct Failure(StatusCode, String);
+
+impl IntoResponse for Failure {
+    fn into_response(self) -> Response {
+        (self.0, Json(ApiError { error: self.1 })).into_response()
+    }
+}
+
+fn not_found(id: &str) -> Failure {
+    Failure(StatusCode::NOT_FOUND, format!("Conversation '{}' not found", id))
+}
+
+fn internal(e: anyhow::Error) -> Failure {
+    Failure(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))
+}
+
+/// A model entry from `models.json`.
+#[derive(Serialize, ToSchema)]
+pub struct ModelEntry {
+    pub name: String,
+    /// `managed`, `external`, `openai`, `mock` or `replay`.
+    pub backend: String,
+    pub path: Option<String>,
+    pub args: Vec<String>,
+    pub url: Option<String>,
+    /// Whether this is the model currently loaded.
+    pub loaded: bool,
+    /// Whether this is the configured default.
+    pub default: bool,
+    /// Whether the model was found in a models directory rather than
+    /// configured in `models.json`.
+    pub discovered: bool,
+    /// Header of the GGUF file at `path`, if readable.
+    pub metadata: Option<GgufMetadata>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BackendStatus {
+    /// Model the backend was started for, if any.
+    pub model: Option<String>,
+    /// Kind of the active backend: `managed`, `external`, `openai`, `mock` or `replay`.
+    pub backend: Option<String>,
+    /// Whether the backend is up (for a managed llama-server, whether the process is alive).
+    pub running: bool,
+    /// Whether a model switch is in progress.
+    pub loading: bool,
+    /// Whether the backend answers its health check.
+    pub healthy: bool,
+    pub url: Option<String>,
+    /// Model an external llama-server reports in its `/v1/models`.
+    pub served_model: Option<String>,
+    /// Latest resource sample of a managed llama-server.
+    pub usage: Option<ProcessUsage>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetModelRequest {
+    pub name: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct MessagesQuery {
+    /// Return every branch instead of only the active one.
+    #[serde(default)]
+    pub all: bool,
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Lists stored conversations, most recently active first.
+#[utoipa::path(get, path = "/api/conversations", tag = "conversations",
+    responses((status = 200, body = [ConversationInfo])))]
+async fn list_conversations(State(state): State<Arc<AppState>>) -> Result<Json<Vec<ConversationInfo>>, Failure> {
+    let conversations = state.store.list().map_err(internal)?;
+    Ok(Json(conversations.iter().map(ChatHistory::info).collect()))
+}
+
+/// Returns a conversation with its complete message tree.
+#[utoipa::path(get, path = "/api/conversations/{id}", tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id")),
+    responses((status = 200, body = ChatHistory), (status = 404, body = ApiError)))]
+async fn get_conversation(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ChatHistory>, Failure> {
+    load(&state, &id).map(Json)
+}
+
+/// Deletes a conversation. Deleting the active one starts a new, empty conversation.
+#[utoipa::path(delete, path = "/api/conversations/{id}", tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id")),
+    responses((status = 204), (status = 404, body = ApiError)))]
+async fn delete_conversation(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, Failure> {
+    let replacement = {
+        let mut history = state.history.lock().unwrap();
+        if history.id == id {
+            let model = history.current_model.clone();
+            *history = state.store.create(&model);
+            Some(history.clone())
+        } else {
+            None
+        }
+    };
+
+    if state.store.exists(&id) {
+        state.store.delete(&id).map_err(internal)?;
+    } else if replacement.is_none() {
+        return Err(not_found(&id));
+    }
+    state.search.lock().unwrap().remove(&id);
+
+    if let Some(history) = replacement {
+        let _ = state.events.send(ServerMessage::History(history));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lists the messages of a conversation: the active branch, or every branch with `all=true`.
+#[utoipa::path(get, path = "/api/conversations/{id}/messages", tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id"), MessagesQuery),
+    responses((status = 200, body = [Message]), (status = 404, body = ApiError)))]
+async fn list_messages(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<MessagesQuery>,
+) -> Result<Json<Vec<Message>>, Failure> {
+    let history = load(&state, &id)?;
+    let messages = if query.all {
+        history.messages
+    } else {
+        history.active_path().into_iter().cloned().collect()
+    };
+    Ok(Json(messages))
+}
+
+/// Lists the models of `models.json` and the models directories.
+#[utoipa::path(get, path = "/api/models", tag = "models",
+    responses((status = 200, body = [ModelEntry])))]
+async fn list_models(State(state): State<Arc<AppState>>) -> Json<Vec<ModelEntry>> {
+    // While a model is loading the manager is busy; report nothing as loaded.
+    let loaded = state
+        .process_manager
+        .try_lock()
+        .ok()
+        .and_then(|pm| pm.current_model().map(str::to_string));
+    let catalog = state.models.read().unwrap();
+    let models: Vec<ModelEntry> = catalog
+        .iter()
+        .into_iter()
+        .map(|(name, model)| ModelEntry {
+            name: name.clone(),
+            backend: model.backend_kind().as_str().to_string(),
+            path: model.path.clone(),
+            args: model.args.clone(),
+            url: model.url.clone(),
+            loaded: loaded.as_deref() == Some(name.as_str()),
+            default: *name == state.config.default,
+            discovered: catalog.is_discovered(name),
+            metadata: catalog.metadata(name).cloned(),
+        })
+        .collect();
+    Json(models)
+}
+
+/// Reports the state of the active backend.
+#[utoipa::path(get, path = "/api/status", tag = "models",
+    responses((status = 200, body = BackendStatus)))]
+async fn get_status(State(state): State<Arc<AppState>>) -> Json<BackendStatus> {
+    Json(status(&state).await)
+}
+
+/// Switches to another model and waits until its backend is ready.
+#[utoipa::path(put, path = "/api/model", tag = "models", request_body = SetModelRequest,
+    responses((status = 200, body = BackendStatus), (status = 404, body = ApiError), (status = 503, body = ApiError)))]
+async fn set_model(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SetModelRequest>,
+) -> Result<Json<BackendStatus>, Failure> {
+    if !state.models.read().unwrap().contains(&request.name) {
+        return Err(Failure(StatusCode::NOT_FOUND, format!("Model '{}' not found", request.name)));
+    }
+    switch_model(&state, &request.name, true)
+        .await
+        .map_err(|e| Failure(StatusCode::SERVICE_UNAVAILABLE, format!("{:#}", e)))?;
+    Ok(Json(status(&state).await))
+}
+
+/// Loads a conversation, preferring the in-memory copy of the active one.
+fn load(state: &AppState, id: &str) -> Result<ChatHistory, Failure> {
+    {
+        let history = state.history.lock().unwrap();
+        if history.id == id {
+            return Ok(history.clone());
+        }
+    }
+    if !state.store.exists(id) {
+        return Err(not_found(id));
+    }
+    state.store.load(id).map_err(internal)
+}
+
+async fn status(state: &AppState) -> BackendStatus {
+    // The manager stays locked for the whole duration of a model switch.
+    let Ok(mut pm) = state.process_manager.try_lock() else {
+        return BackendStatus {
+            model: None,
+            backend: None,
+            running: false,
+            loading: true,
+            healthy: false,
+            url: None,
+            served_model: None,
+            usage: state.usage.lock().unwrap().clone(),
+        };
+    };
+    BackendStatus {
+        model: pm.current_model().map(str::to_string),
+        backend: pm.backend().map(|b| b.kind().as_str().to_string()),
+        running: pm.is_running(),
+        loading: false,
+        healthy: pm.healthy().await,
+        url: pm.backend().map(|b| b.url().to_string()),
+        served_model: match pm.backend() {
+            Some(backend) => backend.served_model().await,
+            None => None,
+        },
+        usage: state.usage.lock().unwrap().clone(),
+    }
+}
@@ -0,0 +1,312 @@
+//! Inference backends.
+//!
+//! Every model entry in `models.json` picks a backend with its `backend`
+//! field (see [`BackendKind`]). The [`ProcessManager`](crate::process::ProcessManager)
+//! starts and stops backends through the [`Backend`] trait, and everything
+//! that talks to a model gets its [`OAIClient`] from the active one.
+
+use std::process::Stdio;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use crate::child::ChildProcess;
+use crate::config::{BackendKind, ModelConfig};
+use crate::openai::OAIClient;
+
+mod mock;
+mod replay;
+
+/// Where the managed llama-server listens (see the `--port` passed in `start`).
+pub const LLAMA_SERVER_URL: &str = "http://127.0.0.1:8080";
+const MANAGED_PORT: &str = "8080";
+
+/// Timeout of a single health check.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[async_trait]
+pub trait Backend: Send + Sync {
+    fn kind(&self) -> BackendKind;
+
+    /// Base URL of the OpenAI-compatible API.
+    fn url(&self) -> &str;
+
+    /// Brings the backend up. Returns before it is ready to serve.
+    async fn start(&mut self) -> Result<()>;
+
+    async fn stop(&mut self) -> Result<()>;
+
+    /// Whether the backend can still become healthy; `false` once a
+    /// spawned process has exited.
+    fn is_running(&mut self) -> bool;
+
+    /// Whether the backend answers requests right now.
+    async fn healthy(&self) -> bool;
+
+    /// Client for chat completions against this backend.
+    fn client(&self) -> OAIClient;
+
+    /// Model the backend reports as loaded, for backends this server does
+    /// not start itself.
+    async fn served_model(&self) -> Option<String> {
+        None
+    }
+
+    /// Checks that a ready backend serves the expected model.
+    async fn verify(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Id of the process serving the model, if this server spawned one.
+    fn pid(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Creates the backend for model entry `name`. Nothing is started yet.
+pub fn create(name: &str, config: &ModelConfig) -> Result<Box<dyn Backend>> {
+    let url = |kind: BackendKind| {
+        config
+            .url
+            .as_deref()
+            .map(|url| url.trim_end_matches('/').to_string())
+            .ok_or_else(|| anyhow!("Model '{}' needs a `url` for the {} backend", name, kind.as_str()))
+    };
+    let kind = config.backend_kind();
+    Ok(match kind {
+        BackendKind::Managed => {
+            let path = config
+                .path
+                .clone()
+                .ok_or_else(|| anyhow!("Model '{}' needs a `path` for the managed backend", name))?;
+            Box::new(ManagedLlamaServer {
+                path,
+                args: config.args.clone(),
+                child: None,
+            })
+        }
+        BackendKind::External => Box::new(ExternalLlamaServer {
+            url: url(kind)?,
+            expected: config.path.clone(),
+        }),
+        BackendKind::OpenAi => {
+            let api_key = match &config.api_key_env {
+                Some(var) => Some(std::env::var(var).with_context(|| format!("Environment variable {} is not set", var))?),
+                None => None,
+            };
+            Box::new(OpenAiEndpoint {
+                url: url(kind)?,
+                model: config.remote_model.clone().unwrap_or_else(|| name.to_string()),
+                api_key,
+            })
+        }
+        BackendKind::Mock => Box::new(mock::MockBackend::new(name, &config.mock)),
+        BackendKind::Replay => {
+            let cassette = config
+                .cassette
+                .as_deref()
+                .ok_or_else(|| anyhow!("Model '{}' needs a `cassette` for the replay backend", name))?;
+            Box::new(replay::ReplayBackend::new(cassette))
+        }
+    })
+}
+
+/// llama-server spawned and owned by this server.
+struct ManagedLlamaServer {
+    path: String,
+    args: Vec<String>,
+    child: Option<ChildProcess>,
+}
+
+#[async_trait]
+impl Backend for ManagedLlamaServer {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Managed
+    }
+
+    fn url(&self) -> &str {
+        LLAMA_SERVER_URL
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        tracing::info!("Starting llama-server with model {} and args: {:?}", self.path, self.args);
+
+        // `llama-server -m <path> <args>` is the standard way; the port is
+        // fixed because everything else expects LLAMA_SERVER_URL.
+        let mut cmd = Command::new("llama-server");
+        cmd.arg("-m").arg(&self.path);
+        cmd.args(&self.args);
+        cmd.arg("--port").arg(MANAGED_PORT);
+
+        cmd.stdout(Stdio::null()); // or piped for logging
+        cmd.stderr(Stdio::null());
+
+        self.child = Some(ChildProcess::spawn("llama-server", cmd)?);
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        match self.child.take() {
+            Some(mut child) => child.stop().await,
+            None => Ok(()),
+        }
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.child.as_mut().is_some_and(ChildProcess::is_running)
+    }
+
+    async fn healthy(&self) -> bool {
+        get_ok(&format!("{}/health", LLAMA_SERVER_URL), None).await
+    }
+
+    fn client(&self) -> OAIClient {
+        OAIClient::new(LLAMA_SERVER_URL)
+    }
+
+    fn pid(&self) -> Option<u32> {
+        self.child.as_ref().and_then(|child| child.id())
+    }
+}
+
+/// llama-server run by someone else (systemd, a container, ...). It is only
+/// health-checked, never spawned or stopped.
+struct ExternalLlamaServer {
+    url: String,
+    /// GGUF file the entry expects the server to have loaded.
+    expected: Option<String>,
+}
+
+#[async_trait]
+impl Backend for ExternalLlamaServer {
+    fn kind(&self) -> BackendKind {
+        BackendKind::External
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        tracing::info!("Using external llama-server at {}", self.url);
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn is_running(&mut self) -> bool {
+        true
+    }
+
+    async fn healthy(&self) -> bool {
+        get_ok(&format!("{}/health", self.url), None).await
+    }
+
+    fn client(&self) -> OAIClient {
+        OAIClient::new(&self.url)
+    }
+
+    /// The first model listed by the server's `/v1/models`: the `-m` path,
+    /// or the `--alias` if one was given.
+    async fn served_model(&self) -> Option<String> {
+        let res = reqwest::Client::new()
+            .get(format!("{}/v1/models", self.url))
+            .timeout(HEALTH_TIMEOUT)
+            .send()
+            .await
+            .ok()?;
+        let json: serde_json::Value = res.json().await.ok()?;
+        json.pointer("/data/0/id")?.as_str().map(str::to_string)
+    }
+
+    async fn verify(&self) -> Result<()> {
+        let Some(served) = self.served_model().await else {
+            bail!("llama-server at {} does not list a loaded model in /v1/models", self.url);
+        };
+        tracing::info!("llama-server at {} serves {}", self.url, served);
+        if let Some(expected) = &self.expected {
+            if file_name(&served) != file_name(expected) {
+                bail!("llama-server at {} serves {} instead of {}", self.url, served, expected);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+/// Any server implementing the OpenAI chat completions API.
+struct OpenAiEndpoint {
+    url: String,
+    /// Model name the endpoint knows the model by.
+    model: String,
+    api_key: Option<String>,
+}
+
+#[async_trait]
+impl Backend for OpenAiEndpoint {
+    fn kind(&self) -> BackendKind {
+        BackendKind::OpenAi
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        tracing::info!("Using OpenAI-compatible endpoint {} with model {}", self.url, self.model);
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn is_running(&mut self) -> bool {
+        true
+    }
+
+    async fn healthy(&self) -> bool {
+        get_ok(&format!("{}/v1/models", self.url), self.api_key.as_deref()).await
+    }
+
+    fn client(&self) -> OAIClient {
+        let client = OAIClient::new(&self.url).with_model(&self.model);
+        match &self.api_key {
+            Some(key) => client.with_api_key(key),
+            None => client,
+        }
+    }
+}
+
+/// Serves `app` on an ephemeral local port, for the built-in backends.
+/// Returns its base URL and the server task.
+async fn serve_locally(app: axum::Router) -> Result<(String, JoinHandle<()>)> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("Failed to bind a local port for the backend")?;
+    let url = format!("http://{}", listener.local_addr()?);
+    let server = tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("Built-in backend failed: {}", e);
+        }
+    });
+    Ok((url, server))
+}
+
+/// Whether a GET to `url` succeeds within [`HEALTH_TIMEOUT`].
+async fn get_ok(url: &str, api_key: Option<&str>) -> bool {
+    let mut request = reqwest::Client::new().get(url).timeout(HEALTH_TIMEOUT);
+    if let Some(key) = api_key {
+        request = request.bearer_auth(key);
+    }
+    match request.send().await {
+        Ok(res) => res.status().is_success(),
+        Err(_) => false,
+    }
+}
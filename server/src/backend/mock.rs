@@ -0,0 +1,287 @@
+//! Built-in mock model for offline development and tests.
+//!
+//! The mock serves the OpenAI chat completions API from an in-process HTTP
+//! server on an ephemeral port, so replies reach clients exactly like those
+//! of llama-server. Replies are deterministic for a given configuration.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use crate::config::{BackendKind, MockConfig, MockMode, MockReply};
+use crate::openai::OAIClient;
+use super::{get_ok, serve_locally, Backend};
+
+const DEFAULT_LOREM_WORDS: usize = 40;
+
+const LOREM: &[&str] = &[
+    "lorem", "ipsum", "dolor", "sit", "amet", "consectetur", "adipiscing", "elit", "sed", "do",
+    "eiusmod", "tempor", "incididunt", "ut", "labore", "et", "dolore", "magna", "aliqua", "enim",
+    "ad", "minim", "veniam", "quis", "nostrud", "exercitation", "ullamco", "laboris", "nisi",
+    "aliquip", "ex", "ea", "commodo", "consequat", "duis", "aute", "irure", "in", "reprehenderit",
+    "voluptate", "velit", "esse", "cillum", "fugiat", "nulla", "pariatur",
+];
+
+pub struct MockBackend {
+    name: String,
+    config: MockConfig,
+    url: String,
+    server: Option<JoinHandle<()>>,
+}
+
+struct MockState {
+    name: String,
+    config: MockConfig,
+    /// Requests answered so far, for scripts and error injection.
+    requests: AtomicU64,
+}
+
+impl MockBackend {
+    pub fn new(name: &str, config: &MockConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            config: config.clone(),
+            url: String::new(),
+            server: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for MockBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Mock
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let state = Arc::new(MockState {
+            name: self.name.clone(),
+            config: self.config.clone(),
+            requests: AtomicU64::new(0),
+        });
+        let app = Router::new()
+            .route("/health", get(|| async { Json(json!({ "status": "ok" })) }))
+            .route("/v1/models", get(list_models))
+            .route("/v1/chat/completions", post(chat_completions))
+            .with_state(state);
+
+        let (url, server) = serve_locally(app).await?;
+        tracing::info!("Mock backend for {} ({:?}) listening on {}", self.name, self.config.mode, url);
+        self.url = url;
+        self.server = Some(server);
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(server) = self.server.take() {
+            server.abort();
+        }
+        Ok(())
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.server.as_ref().is_some_and(|server| !server.is_finished())
+    }
+
+    async fn healthy(&self) -> bool {
+        get_ok(&format!("{}/health", self.url), None).await
+    }
+
+    fn client(&self) -> OAIClient {
+        OAIClient::new(&self.url)
+    }
+}
+
+async fn list_models(State(state): State<Arc<MockState>>) -> Json<Value> {
+    Json(json!({ "object": "list", "data": [{ "id": state.name, "object": "model", "owned_by": "mock" }] }))
+}
+
+async fn chat_completions(State(state): State<Arc<MockState>>, Json(body): Json<Value>) -> Response {
+    let n = state.requests.fetch_add(1, Ordering::SeqCst) + 1;
+    let config = &state.config;
+    let reply = reply(config, &body, n);
+    let fail = config.error_every.is_some_and(|every| every > 0 && n % every == 0);
+
+    if fail && config.error_after == 0 {
+        let error = json!({ "error": { "message": format!("Injected mock error on request {}", n), "type": "server_error" } });
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+    }
+
+    let reply = match reply {
+        MockReply::Text(text) => text,
+        MockReply::ToolCall { tool, arguments } => return tool_call(&state, &body, n, &tool, &arguments),
+    };
+    if !body.get("stream").and_then(Value::as_bool).unwrap_or(false) {
+        let response = json!({
+            "object": "chat.completion",
+            "model": state.name,
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": reply }, "finish_reason": "stop" }],
+        });
+        return Json(response).into_response();
+    }
+
+    let mut tokens: Vec<String> = reply.split_inclusive(' ').map(str::to_string).collect();
+    let cut = fail.then_some(config.error_after);
+    if let Some(cut) = cut {
+        tokens.truncate(cut);
+    }
+    // Words stand in for tokens.
+    let usage = json!({
+        "prompt_tokens": prompt_words(&body),
+        "completion_tokens": tokens.len(),
+        "total_tokens": prompt_words(&body) + tokens.len(),
+    });
+    let include_usage = include_usage(&body);
+
+    let mut events: Vec<Result<Bytes, std::io::Error>> = tokens
+        .into_iter()
+        .map(|token| Ok(sse(json!({ "choices": [{ "index": 0, "delta": { "content": token }, "finish_reason": null }] }))))
+        .collect();
+    match cut {
+        // Ends the body with an error so the client sees a broken stream.
+        Some(_) => events.push(Err(std::io::Error::other(format!("Injected mock error on request {}", n)))),
+        None => {
+            events.push(Ok(sse(json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }] }))));
+            if include_usage {
+                events.push(Ok(sse(json!({ "choices": [], "usage": usage }))));
+            }
+            events.push(Ok(Bytes::from_static(b"data: [DONE]\n\n")));
+        }
+    }
+
+    event_stream(events, Duration::from_millis(config.delay_ms))
+}
+
+/// Asks for a call of `tool`, streaming the arguments in two pieces like a
+/// model writing them.
+fn tool_call(state: &MockState, body: &Value, n: u64, tool: &str, arguments: &Value) -> Response {
+    let id = format!("call_{}", n);
+    let arguments = if arguments.is_null() { "{}".to_string() } else { arguments.to_string() };
+
+    if !body.get("stream").and_then(Value::as_bool).unwrap_or(false) {
+        let call = json!({ "id": id, "type": "function", "function": { "name": tool, "arguments": arguments } });
+        let response = json!({
+            "object": "chat.completion",
+            "model": state.name,
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": null, "tool_calls": [call] }, "finish_reason": "tool_calls" }],
+        });
+        return Json(response).into_response();
+    }
+
+    let middle = arguments.char_indices().nth(arguments.chars().count() / 2).map_or(0, |(i, _)| i);
+    let (head, tail) = arguments.split_at(middle);
+    let mut events = vec![
+        Ok(sse(json!({ "choices": [{ "index": 0, "delta": { "tool_calls": [
+            { "index": 0, "id": id, "type": "function", "function": { "name": tool, "arguments": head } }
+        ] }, "finish_reason": null }] }))),
+        Ok(sse(json!({ "choices": [{ "index": 0, "delta": { "tool_calls": [
+            { "index": 0, "function": { "arguments": tail } }
+        ] }, "finish_reason": null }] }))),
+        Ok(sse(json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "tool_calls" }] }))),
+    ];
+    if include_usage(body) {
+        let usage = json!({ "prompt_tokens": prompt_words(body), "completion_tokens": 1, "total_tokens": prompt_words(body) + 1 });
+        events.push(Ok(sse(json!({ "choices": [], "usage": usage }))));
+    }
+    events.push(Ok(Bytes::from_static(b"data: [DONE]\n\n")));
+    event_stream(events, Duration::from_millis(state.config.delay_ms))
+}
+
+fn include_usage(body: &Value) -> bool {
+    body.pointer("/stream_options/include_usage")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// Streams `events` as the body of a server-sent events response, pausing
+/// `delay` before each.
+fn event_stream(events: Vec<Result<Bytes, std::io::Error>>, delay: Duration) -> Response {
+    let stream = futures::stream::unfold(events.into_iter(), move |mut events| async move {
+        let event = events.next()?;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        Some((event, events))
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::from_stream(stream))
+        .unwrap_or_else(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())
+}
+
+/// The reply to request number `n`.
+fn reply(config: &MockConfig, body: &Value, n: u64) -> MockReply {
+    match config.mode {
+        MockMode::Echo => MockReply::Text(last_user_message(body).unwrap_or_default()),
+        MockMode::Script if config.script.is_empty() => MockReply::Text(String::new()),
+        MockMode::Script => config.script[((n - 1) % config.script.len() as u64) as usize].clone(),
+        MockMode::Lorem => MockReply::Text(lorem(config.words.unwrap_or(DEFAULT_LOREM_WORDS), config.seed.wrapping_add(n))),
+    }
+}
+
+fn last_user_message(body: &Value) -> Option<String> {
+    body.get("messages")?
+        .as_array()?
+        .iter()
+        .rev()
+        .find(|m| m.get("role").and_then(Value::as_str) == Some("user"))?
+        .get("content")?
+        .as_str()
+        .map(str::to_string)
+}
+
+fn prompt_words(body: &Value) -> usize {
+    body.get("messages")
+        .and_then(Value::as_array)
+        .map(|messages| {
+            messages
+                .iter()
+                .filter_map(|m| m.get("content").and_then(Value::as_str))
+                .map(|content| content.split_whitespace().count())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// `words` lorem ipsum words picked by a xorshift generator seeded with `seed`.
+fn lorem(words: usize, seed: u64) -> String {
+    let mut x = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    let mut text: Vec<String> = Vec::with_capacity(words);
+    for i in 0..words {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        let word = LOREM[(x % LOREM.len() as u64) as usize];
+        if i == 0 {
+            let mut chars = word.chars();
+            text.push(chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default());
+        } else {
+            text.push(word.to_string());
+        }
+    }
+    if words > 0 {
+        format!("{}.", text.join(" "))
+    } else {
+        String::new()
+    }
+}
+
+fn sse(data: Value) -> Bytes {
+    Bytes::from(format!("data: {}\n\n", data))
+}
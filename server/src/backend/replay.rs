@@ -0,0 +1,131 @@
+//! Replays recorded cassettes as a backend.
+//!
+//! Requests are answered with the recorded responses in turn, each chunk
+//! sent at its original offset from the request, so streaming bugs seen
+//! with a real model can be reproduced without it.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use crate::cassette::Cassette;
+use crate::config::BackendKind;
+use crate::openai::{OAIClient, SseParser};
+use super::{get_ok, serve_locally, Backend};
+
+pub struct ReplayBackend {
+    path: PathBuf,
+    url: String,
+    server: Option<JoinHandle<()>>,
+}
+
+struct ReplayState {
+    cassettes: Vec<Cassette>,
+    /// Requests answered so far.
+    played: AtomicUsize,
+}
+
+impl ReplayBackend {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            url: String::new(),
+            server: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for ReplayBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Replay
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let cassettes = Cassette::load_all(&self.path)?;
+        if cassettes.is_empty() {
+            bail!("No cassettes in {}", self.path.display());
+        }
+        let count = cassettes.len();
+        let state = Arc::new(ReplayState {
+            cassettes,
+            played: AtomicUsize::new(0),
+        });
+        let app = Router::new()
+            .route("/health", get(|| async { Json(json!({ "status": "ok" })) }))
+            .route("/v1/chat/completions", post(chat_completions))
+            .with_state(state);
+
+        let (url, server) = serve_locally(app).await?;
+        tracing::info!("Replaying {} cassette(s) from {} on {}", count, self.path.display(), url);
+        self.url = url;
+        self.server = Some(server);
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(server) = self.server.take() {
+            server.abort();
+        }
+        Ok(())
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.server.as_ref().is_some_and(|server| !server.is_finished())
+    }
+
+    async fn healthy(&self) -> bool {
+        get_ok(&format!("{}/health", self.url), None).await
+    }
+
+    fn client(&self) -> OAIClient {
+        OAIClient::new(&self.url)
+    }
+}
+
+async fn chat_completions(State(state): State<Arc<ReplayState>>, Json(body): Json<Value>) -> Response {
+    let started = Instant::now();
+    let n = state.played.fetch_add(1, Ordering::SeqCst);
+    let cassette = state.cassettes[n % state.cassettes.len()].clone();
+    let status = StatusCode::from_u16(cassette.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+    // A recorded stream answers a non-streaming request as one message.
+    if status.is_success() && !body.get("stream").and_then(Value::as_bool).unwrap_or(false) {
+        let mut parser = SseParser::default();
+        let content: String = cassette.chunks.iter().map(|chunk| parser.feed(chunk.data())).collect();
+        let response = json!({
+            "object": "chat.completion",
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": content }, "finish_reason": "stop" }],
+        });
+        return Json(response).into_response();
+    }
+
+    let content_type = if status.is_success() { "text/event-stream" } else { "application/json" };
+    let stream = futures::stream::unfold(cassette.chunks.into_iter(), move |mut chunks| async move {
+        let chunk = chunks.next()?;
+        tokio::time::sleep_until(started + Duration::from_millis(chunk.at_ms)).await;
+        Some((Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk.data())), chunks))
+    });
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from_stream(stream))
+        .unwrap_or_else(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())
+}
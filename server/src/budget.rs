@@ -0,0 +1,135 @@
+//! Fits a conversation into the context window of the model answering it,
+//! leaving out the oldest messages first.
+//!
+//! Token counts are estimated from the text; only llama-server knows the
+//! exact ones, and asking it would cost a request per message.
+
+use shared::GgufMetadata;
+use crate::config::{BackendKind, ModelConfig};
+use crate::openai::Message;
+
+/// Context size llama-server uses when `-c`/`--ctx-size` is not given.
+pub const DEFAULT_CONTEXT: u64 = 4096;
+
+/// Share of the window kept free for the reply: a quarter.
+const REPLY_SHARE: u64 = 4;
+
+/// Tokens of chat template markup around each message.
+const MESSAGE_OVERHEAD: u64 = 4;
+
+/// Context window, in tokens, the backend of `model` works with, if known.
+///
+/// A managed llama-server gets `-c` (0 meaning the trained length) or its
+/// default, never more than the model was trained for. Other backends
+/// choose their own; the trained length is the best guess, if the entry's
+/// `path` points at the GGUF file.
+pub fn context_window(model: &ModelConfig, metadata: Option<&GgufMetadata>) -> Option<u64> {
+    let trained = metadata.and_then(|m| m.context_length);
+    if model.backend_kind() != BackendKind::Managed {
+        return trained;
+    }
+    Some(match context_arg(&model.args) {
+        Some(0) => trained.unwrap_or(DEFAULT_CONTEXT),
+        Some(size) => size,
+        None => trained.map_or(DEFAULT_CONTEXT, |trained| trained.min(DEFAULT_CONTEXT)),
+    })
+}
+
+/// The `-c`/`--ctx-size` passed to llama-server.
+fn context_arg(args: &[String]) -> Option<u64> {
+    args.iter()
+        .position(|arg| arg == "-c" || arg == "--ctx-size")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|value| value.parse().ok())
+}
+
+/// Rough token count of a message: about four characters per token.
+fn estimate_tokens(message: &Message) -> u64 {
+    let calls: usize = message
+        .tool_calls
+        .iter()
+        .map(|call| call.function.name.chars().count() + call.function.arguments.chars().count())
+        .sum();
+    (message.content.chars().count() + calls).div_ceil(4) as u64 + MESSAGE_OVERHEAD
+}
+
+/// Drops the oldest messages until the rest fit `window` with room for the
+/// reply. A leading system prompt and the last message are always kept,
+/// and the conversation still starts with a user message.
+///
+/// Returns how many messages were dropped.
+pub fn fit(messages: &mut Vec<Message>, window: u64) -> usize {
+    let budget = window - window / REPLY_SHARE;
+    let first = usize::from(messages.first().is_some_and(|m| m.role == "system"));
+    let mut total: u64 = messages.iter().map(estimate_tokens).sum();
+    let mut dropped = 0;
+    while messages.len() > first + 1 && (total > budget || (dropped > 0 && messages[first].role != "user")) {
+        total -= estimate_tokens(&messages.remove(first));
+        dropped += 1;
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A message of `role` taking `tokens` tokens, overhead included.
+    fn message(role: &str, tokens: u64) -> Message {
+        Message::new(role, "x".repeat(((tokens - MESSAGE_OVERHEAD) * 4) as usize))
+    }
+
+    fn roles(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.role.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_everything_that_fits() {
+        let mut messages = vec![message("user", 30), message("assistant", 30), message("user", 15)];
+        assert_eq!(fit(&mut messages, 100), 0);
+        assert_eq!(messages.len(), 3);
+    }
+
+    #[test]
+    fn drops_the_oldest_turns_first() {
+        let mut messages = vec![
+            message("user", 30),
+            message("assistant", 30),
+            message("user", 10),
+            message("assistant", 10),
+            message("user", 10),
+        ];
+        // 75 tokens leave room for the reply; the first turn does not fit.
+        assert_eq!(fit(&mut messages, 100), 2);
+        assert_eq!(roles(&messages), ["user", "assistant", "user"]);
+
+        // Dropping only the first user message would start with the assistant.
+        let mut messages = vec![message("user", 30), message("assistant", 10), message("user", 30), message("assistant", 10)];
+        assert_eq!(fit(&mut messages, 100), 2);
+        assert_eq!(roles(&messages), ["user", "assistant"]);
+    }
+
+    #[test]
+    fn keeps_the_system_prompt() {
+        let mut messages = vec![
+            message("system", 20),
+            message("user", 30),
+            message("assistant", 30),
+            message("user", 10),
+        ];
+        assert_eq!(fit(&mut messages, 100), 2);
+        assert_eq!(roles(&messages), ["system", "user"]);
+    }
+
+    #[test]
+    fn keeps_the_last_message_even_when_it_is_too_big() {
+        let mut messages = vec![message("user", 10), message("assistant", 10), message("user", 500)];
+        assert_eq!(fit(&mut messages, 100), 2);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(estimate_tokens(&messages[0]), 500);
+
+        let mut messages = vec![message("system", 10), message("user", 500)];
+        assert_eq!(fit(&mut messages, 100), 0);
+        assert_eq!(roles(&messages), ["system", "user"]);
+    }
+}
@@ -0,0 +1,131 @@
+//! Recorded backend traffic.
+//!
+//! A cassette holds one chat completion exchange: the request body and the
+//! raw response chunks exactly as they arrived, with their offsets from the
+//! moment the request was sent. [`Recording`] writes them while a response
+//! is streamed; the replay backend serves them back with the same timing.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const CASSETTE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cassette {
+    pub version: u32,
+    /// Base URL the request was sent to.
+    pub url: String,
+    pub request: Value,
+    /// HTTP status of the response.
+    pub status: u16,
+    pub chunks: Vec<Chunk>,
+}
+
+/// One chunk of the response body. Chunks that are not valid UTF-8 on their
+/// own (e.g. a character split across chunks) are kept as raw bytes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Milliseconds since the request was sent.
+    pub at_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl Chunk {
+    pub fn new(at_ms: u64, data: &[u8]) -> Self {
+        match std::str::from_utf8(data) {
+            Ok(text) => Self { at_ms, text: Some(text.to_string()), bytes: None },
+            Err(_) => Self { at_ms, text: None, bytes: Some(data.to_vec()) },
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match (&self.text, &self.bytes) {
+            (Some(text), _) => text.as_bytes(),
+            (None, Some(bytes)) => bytes,
+            (None, None) => &[],
+        }
+    }
+}
+
+impl Cassette {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid cassette {}", path.display()))
+    }
+
+    /// Loads `path`, or every `.json` file in it, by name, if it is a directory.
+    pub fn load_all(path: &Path) -> Result<Vec<Self>> {
+        if !path.is_dir() {
+            return Ok(vec![Self::load(path)?]);
+        }
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read cassette directory {}", path.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        files.sort();
+        files.iter().map(|file| Self::load(file)).collect()
+    }
+}
+
+/// A cassette being recorded. It is written to disk when dropped, i.e. when
+/// the response has been consumed or abandoned.
+pub struct Recording {
+    path: PathBuf,
+    started: Instant,
+    cassette: Cassette,
+}
+
+impl Recording {
+    /// Starts a cassette in directory `dir` for `request` sent to `url`.
+    pub fn start(dir: &Path, url: &str, request: Value) -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let name = format!("{}-{}.json", millis, COUNTER.fetch_add(1, Ordering::SeqCst));
+        Self {
+            path: dir.join(name),
+            started: Instant::now(),
+            cassette: Cassette {
+                version: CASSETTE_VERSION,
+                url: url.to_string(),
+                request,
+                status: 0,
+                chunks: Vec::new(),
+            },
+        }
+    }
+
+    pub fn status(&mut self, status: u16) {
+        self.cassette.status = status;
+    }
+
+    pub fn chunk(&mut self, data: &[u8]) {
+        let at_ms = self.started.elapsed().as_millis() as u64;
+        self.cassette.chunks.push(Chunk::new(at_ms, data));
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(&self.cassette)?)?;
+        Ok(())
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        match self.save() {
+            Ok(()) => tracing::debug!("Recorded cassette {}", self.path.display()),
+            Err(e) => tracing::error!("Failed to write cassette {}: {}", self.path.display(), e),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Long-running child processes this server owns: the managed llama-server
+//! and MCP servers.
+//!
+//! Each is spawned to die with this server, checked for having exited
+//! before use, and killed and waited for when stopped; the owner starts a
+//! new one in its place when it has exited.
+
+use anyhow::{Context, Result};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+
+pub struct ChildProcess {
+    /// What the process is, for messages: "llama-server", "the MCP server x".
+    what: String,
+    child: Child,
+}
+
+impl ChildProcess {
+    /// Spawns `cmd`, killed when the process is dropped.
+    pub fn spawn(what: &str, mut cmd: Command) -> Result<Self> {
+        // Don't leave a model holding gigabytes of memory, or a server
+        // nobody talks to, behind us.
+        cmd.kill_on_drop(true);
+        let child = cmd.spawn().with_context(|| format!("Failed to start {}", what))?;
+        Ok(Self { what: what.to_string(), child })
+    }
+
+    /// Whether the process has not exited yet.
+    pub fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    /// The ends of the pipes set up with [`std::process::Stdio::piped`].
+    pub fn take_stdio(&mut self) -> (Option<ChildStdin>, Option<ChildStdout>, Option<ChildStderr>) {
+        (self.child.stdin.take(), self.child.stdout.take(), self.child.stderr.take())
+    }
+
+    /// Kills the process and waits for it to be gone.
+    pub async fn stop(&mut self) -> Result<()> {
+        tracing::info!("Stopping {}", self.what);
+        self.child.kill().await.with_context(|| format!("Failed to stop {}", self.what))
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::PathBuf;
+use anyhow::{anyhow, bail, Result};
+use shared::ExportFormat;
+
+pub const USAGE: &str = "\
+Usage:
+  server                      Run the chat server
+  server export [options]     Export conversations
+  server import <file> [options]
+                              Import conversations from other chat tools
+
+Export options:
+  --format <md|html|json>     Output format (default: md)
+  --conversation <id>         Conversation to export (default: most recent)
+  --all                       Export every conversation
+  --output <path>             Write to a file instead of stdout
+
+Import options:
+  --format <auto|openai|chatgpt|llamacpp>
+                              Input format (default: auto)
+  --strict                    Import nothing if any entry cannot be mapped
+  --dry-run                   Report what would be imported without saving";
+
+#[derive(Debug)]
+pub enum Command {
+    Serve,
+    Export(ExportArgs),
+    Import(ImportArgs),
+}
+
+#[derive(Debug)]
+pub struct ExportArgs {
+    pub format: ExportFormat,
+    pub conversation: Option<String>,
+    pub all: bool,
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct ImportArgs {
+    pub path: PathBuf,
+    pub source: ImportSource,
+    pub strict: bool,
+    pub dry_run: bool,
+}
+
+/// Tool that produced an import file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportSource {
+    /// Detect from the file content.
+    Auto,
+    /// OpenAI-style `messages` arrays.
+    OpenAi,
+    /// ChatGPT's `conversations.json` export.
+    ChatGpt,
+    /// llama.cpp web UI export.
+    LlamaCpp,
+}
+
+/// Parses the command line, without the program name.
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    match args.next().as_deref() {
+        None | Some("serve") => Ok(Command::Serve),
+        Some("export") => parse_export(args).map(Command::Export),
+        Some("import") => parse_import(args).map(Command::Import),
+        Some(other) => bail!("Unknown command '{}'", other),
+    }
+}
+
+fn parse_export(mut args: impl Iterator<Item = String>) -> Result<ExportArgs> {
+    let mut export = ExportArgs {
+        format: ExportFormat::Markdown,
+        conversation: None,
+        all: false,
+        output: None,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => export.format = parse_format(&value(&mut args, &arg)?)?,
+            "--conversation" => export.conversation = Some(value(&mut args, &arg)?),
+            "--all" => export.all = true,
+            "--output" => export.output = Some(value(&mut args, &arg)?.into()),
+            other => bail!("Unknown export option '{}'", other),
+        }
+    }
+    if export.all && export.conversation.is_some() {
+        bail!("--all and --conversation are mutually exclusive");
+    }
+    Ok(export)
+}
+
+fn parse_import(mut args: impl Iterator<Item = String>) -> Result<ImportArgs> {
+    let mut path = None;
+    let mut import = ImportArgs {
+        path: PathBuf::new(),
+        source: ImportSource::Auto,
+        strict: false,
+        dry_run: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                import.source = match value(&mut args, &arg)?.as_str() {
+                    "auto" => ImportSource::Auto,
+                    "openai" => ImportSource::OpenAi,
+                    "chatgpt" => ImportSource::ChatGpt,
+                    "llamacpp" => ImportSource::LlamaCpp,
+                    other => bail!("Unknown import format '{}'", other),
+                }
+            }
+            "--strict" => import.strict = true,
+            "--dry-run" => import.dry_run = true,
+            other if other.starts_with("--") => bail!("Unknown import option '{}'", other),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            other => bail!("Unexpected argument '{}'", other),
+        }
+    }
+    import.path = path.ok_or_else(|| anyhow!("Missing file to import"))?;
+    Ok(import)
+}
+
+pub fn parse_format(name: &str) -> Result<ExportFormat> {
+    match name {
+        "md" | "markdown" => Ok(ExportFormat::Markdown),
+        "html" => Ok(ExportFormat::Html),
+        "json" => Ok(ExportFormat::Json),
+        other => bail!("Unknown export format '{}'", other),
+    }
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    args.next().ok_or_else(|| anyhow!("Missing value for {}", flag))
+}
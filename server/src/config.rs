@@ -1,18 +1,325 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use serde::Deserialize;
+use shared::Capability;
 use tokio::fs;
 use anyhow::Result;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct AppConfig {
+    #[serde(default)]
     pub models: HashMap<String, ModelConfig>,
     pub default: String,
+    /// Directories searched for `.gguf` files, which become models named
+    /// after their path in the directory. Entries of `models` take precedence.
+    #[serde(default)]
+    pub models_dirs: Vec<String>,
+    #[serde(default)]
+    pub titles: TitleConfig,
+    #[serde(default)]
+    pub tools: ToolsConfig,
+    /// Directory every streamed backend response is recorded to as a
+    /// cassette. Off unless set.
+    #[serde(default)]
+    pub record: Option<String>,
+    /// Serve clients that skip the handshake with the unversioned protocol:
+    /// bare messages, and frames that are not JSON taken as prompts. Clients
+    /// that wait for the server to speak first are served once the
+    /// handshake times out, after 2 seconds.
+    #[serde(default)]
+    pub legacy_protocol: bool,
+    /// Refuse to start a managed llama-server when its model file plus an
+    /// estimate of its context does not fit in the available memory.
+    #[serde(default)]
+    pub memory_check: bool,
 }
 
+/// Automatic conversation titles and summaries.
 #[derive(Debug, Deserialize, Clone)]
+pub struct TitleConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Model asked for titles. Defaults to the loaded model. Only entries
+    /// that need no process of their own (`external`, `openai`) are used
+    /// without switching.
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+impl Default for TitleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            model: None,
+        }
+    }
+}
+
+/// Tools offered to models whose capabilities include tool calls.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ToolsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Rounds of tool calls within one reply. The request after the last
+    /// round offers no tools, so the model has to answer.
+    #[serde(default = "default_max_rounds")]
+    pub max_rounds: usize,
+    /// Read-only file tools confined to a directory. Off unless set.
+    #[serde(default)]
+    pub files: Option<FilesConfig>,
+    /// A tool running commands in a directory. Off unless set.
+    #[serde(default)]
+    pub shell: Option<ShellConfig>,
+    /// Model Context Protocol servers, by name, whose tools are offered
+    /// as `<name>__<tool>`.
+    #[serde(default)]
+    pub mcp: BTreeMap<String, McpServerConfig>,
+}
+
+/// Whether calls of a tool wait for the user to allow them.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Approval {
+    Never,
+    /// Unless the conversation lets calls run without asking.
+    #[default]
+    Ask,
+    /// Even if the conversation lets calls run without asking.
+    Always,
+}
+
+/// An MCP server run by this server, speaking JSON-RPC on its stdin and
+/// stdout.
+#[derive(Debug, Deserialize, Clone)]
+pub struct McpServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Variables added to the environment of the process.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Working directory of the process. Defaults to the server's.
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// Approval of calls of all the server's tools.
+    #[serde(default)]
+    pub approval: Approval,
+    /// Longest wait for the server to start or answer a request.
+    #[serde(default = "default_mcp_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// The file tools: listing, reading, searching and describing files below
+/// `root`, each call approved by the user unless the conversation opted out.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FilesConfig {
+    /// Directory the tools can see. Paths given by the model are taken
+    /// relative to it and may not leave it, not even through symlinks.
+    pub root: String,
+    /// Largest piece of a file returned by one `read_file` call.
+    #[serde(default = "default_max_file_bytes")]
+    pub max_file_bytes: usize,
+    /// Most directory entries or search matches returned by one call.
+    #[serde(default = "default_max_results")]
+    pub max_results: usize,
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_rounds: default_max_rounds(),
+            files: None,
+            shell: None,
+            mcp: BTreeMap::new(),
+        }
+    }
+}
+
+/// The `run_command` tool. Every call is approved by the user, whatever
+/// the conversation's setting.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ShellConfig {
+    /// Working directory of the commands.
+    pub cwd: String,
+    /// Commands allowed to run, as their first words: `"cargo"` allows any
+    /// cargo command, `"git log"` only that one. Nothing runs while empty.
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Commands refused even if allowed: the program, by file name, and the
+    /// words after it anywhere in the command.
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Commands still running after this long are killed.
+    #[serde(default = "default_command_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Output kept of each of stdout and stderr; the middle of longer
+    /// output is left out.
+    #[serde(default = "default_max_output_bytes")]
+    pub max_output_bytes: usize,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_rounds() -> usize {
+    8
+}
+
+fn default_max_file_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_max_results() -> usize {
+    200
+}
+
+fn default_command_timeout_secs() -> u64 {
+    60
+}
+
+fn default_max_output_bytes() -> usize {
+    16 * 1024
+}
+
+fn default_mcp_timeout_secs() -> u64 {
+    60
+}
+
+/// Which [`Backend`](crate::backend::Backend) serves a model entry.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// llama-server spawned by this server from `path` and `args`.
+    Managed,
+    /// llama-server started elsewhere, reached at `url`.
+    External,
+    /// Any OpenAI-compatible endpoint at `url` (Ollama, vLLM, ...).
+    OpenAi,
+    /// Built-in fake model answering as configured in `mock`.
+    Mock,
+    /// Serves the recorded responses in `cassette` with their original timing.
+    Replay,
+}
+
+impl BackendKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BackendKind::Managed => "managed",
+            BackendKind::External => "external",
+            BackendKind::OpenAi => "openai",
+            BackendKind::Mock => "mock",
+            BackendKind::Replay => "replay",
+        }
+    }
+
+    /// Whether the backend runs elsewhere and can be used without loading it.
+    pub fn is_remote(self) -> bool {
+        matches!(self, BackendKind::External | BackendKind::OpenAi)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct ModelConfig {
-    pub path: String,
+    /// Explicit backend; see [`ModelConfig::backend_kind`] for the default.
+    #[serde(default)]
+    pub backend: Option<BackendKind>,
+    /// GGUF file for a managed llama-server. For an external one, the file
+    /// it is expected to serve.
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
     pub args: Vec<String>,
+    /// Base URL, without `/v1`, of an external or OpenAI-compatible backend.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Model name sent to an OpenAI-compatible endpoint. Defaults to the entry's name.
+    #[serde(default)]
+    pub remote_model: Option<String>,
+    /// Environment variable holding the endpoint's API key.
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    /// Cassette file, or directory of cassettes played in turn, for the
+    /// replay backend.
+    #[serde(default)]
+    pub cassette: Option<String>,
+    /// Behaviour of the mock backend.
+    #[serde(default)]
+    pub mock: MockConfig,
+    /// Name shown in clients instead of the entry's name.
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Free-form labels shown next to the model, e.g. `"coding"`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// What the model can do, replacing what is guessed from its GGUF
+    /// header and arguments.
+    #[serde(default)]
+    pub capabilities: Option<Vec<Capability>>,
+}
+
+/// What the mock backend replies.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MockMode {
+    /// Repeats the last user message.
+    #[default]
+    Echo,
+    /// Replies with the entries of `script` in turn.
+    Script,
+    /// Lorem ipsum, random but reproducible from `seed`.
+    Lorem,
+}
+
+/// An entry of a mock script: a text reply, or `{"tool": ..., "arguments": {...}}`
+/// to ask for a tool call instead.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum MockReply {
+    Text(String),
+    ToolCall {
+        tool: String,
+        #[serde(default)]
+        arguments: serde_json::Value,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MockConfig {
+    #[serde(default)]
+    pub mode: MockMode,
+    /// Canned replies for [`MockMode::Script`], starting over after the last.
+    #[serde(default)]
+    pub script: Vec<MockReply>,
+    /// Length of lorem ipsum replies in words. Defaults to 40.
+    #[serde(default)]
+    pub words: Option<usize>,
+    #[serde(default)]
+    pub seed: u64,
+    /// Pause before each streamed token.
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// Makes every n-th request fail.
+    #[serde(default)]
+    pub error_every: Option<u64>,
+    /// Tokens streamed before an injected failure cuts the stream; with 0
+    /// the request is answered with an HTTP 500.
+    #[serde(default)]
+    pub error_after: usize,
+}
+
+impl ModelConfig {
+    /// The configured backend. Entries giving a `url` attach to an external
+    /// llama-server; everything else is managed.
+    pub fn backend_kind(&self) -> BackendKind {
+        match self.backend {
+            Some(kind) => kind,
+            None if self.url.is_some() => BackendKind::External,
+            None => BackendKind::Managed,
+        }
+    }
 }
 
 pub async fn load_config(path: &str) -> Result<AppConfig> {
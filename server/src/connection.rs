@@ -0,0 +1,148 @@
+//! A client's WebSocket and the protocol negotiated in the handshake.
+//!
+//! Clients open with `ClientMessage::Hello` carrying their protocol version
+//! and are answered with `ServerMessage::Welcome`; from then on messages
+//! travel in `ClientFrame`/`ServerFrame` envelopes so responses carry the id
+//! of their request. Clients of another version are sent a bare `Error` and
+//! disconnected. With `legacy_protocol` set, clients that open with anything
+//! but a hello, or stay silent until the handshake times out, are served the
+//! unversioned protocol instead.
+
+use std::time::Duration;
+use axum::extract::ws::{close_code, CloseFrame, Message as WsMessage, WebSocket};
+use shared::{ClientFrame, ClientMessage, RequestId, ServerFrame, ServerMessage, PROTOCOL_VERSION};
+
+/// Optional protocol features this server supports, announced in `Welcome`.
+pub const CAPABILITIES: &[&str] = &["stop", "branches", "conversations", "search", "export", "resources", "rescan", "tools"];
+
+/// How long a client may take to say hello.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a client may take to say hello with `legacy_protocol` set.
+/// Legacy clients that wait for the server to speak first are served once
+/// it has passed, so it is kept short.
+const LEGACY_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct Connection {
+    socket: WebSocket,
+    /// Bare messages, and text that is not a message taken as a prompt.
+    legacy: bool,
+    /// The request being handled, echoed on every message sent.
+    request: Option<RequestId>,
+}
+
+impl Connection {
+    /// Performs the handshake. Returns the connection, answering the hello
+    /// until [`set_request`](Self::set_request) is called, and for legacy
+    /// clients the first frame if it was read already; `None` if the client
+    /// was rejected or left.
+    pub async fn accept(mut socket: WebSocket, legacy_protocol: bool) -> Option<(Self, Option<String>)> {
+        let timeout = if legacy_protocol { LEGACY_HANDSHAKE_TIMEOUT } else { HANDSHAKE_TIMEOUT };
+        let first = match tokio::time::timeout(timeout, next_text(&mut socket)).await {
+            Ok(Some(text)) => Some(text),
+            Ok(None) => return None,
+            Err(_) => None,
+        };
+        let frame = first.as_deref().and_then(|text| serde_json::from_str::<ClientFrame>(text).ok());
+
+        match frame {
+            Some(ClientFrame { id, message: ClientMessage::Hello { version, client, capabilities } }) => {
+                if version != PROTOCOL_VERSION {
+                    let reason = format!(
+                        "Incompatible protocol version {} from {}: this server speaks version {}",
+                        version, client, PROTOCOL_VERSION
+                    );
+                    reject(socket, reason).await;
+                    return None;
+                }
+                tracing::info!("Client {} connected (capabilities: {:?})", client, capabilities);
+                let mut connection = Self { socket, legacy: false, request: id };
+                let welcome = ServerMessage::Welcome {
+                    version: PROTOCOL_VERSION,
+                    server: format!("llamacpp-chat server {}", env!("CARGO_PKG_VERSION")),
+                    capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+                };
+                connection.send(&welcome).await.then_some((connection, None))
+            }
+            _ if legacy_protocol => {
+                tracing::info!("Client connected without a handshake, using the legacy protocol");
+                Some((Self { socket, legacy: true, request: None }, first))
+            }
+            _ => {
+                let reason = match first {
+                    Some(_) => format!("Expected Hello with protocol version {} as the first message", PROTOCOL_VERSION),
+                    None => format!("Timed out waiting for Hello with protocol version {}", PROTOCOL_VERSION),
+                };
+                reject(socket, reason).await;
+                None
+            }
+        }
+    }
+
+    /// Sets the request that following messages answer; `None` for events.
+    pub fn set_request(&mut self, id: Option<RequestId>) {
+        self.request = id;
+    }
+
+    /// The next frame from the client; `None` once the socket is closed.
+    pub async fn recv(&mut self) -> Option<Result<WsMessage, axum::Error>> {
+        self.socket.recv().await
+    }
+
+    /// Parses a text frame into a request, or the error to report.
+    pub fn parse(&self, text: &str) -> Result<(Option<RequestId>, ClientMessage), String> {
+        if self.legacy {
+            // Raw text from clients predating the JSON protocol (or manual testing).
+            let message = serde_json::from_str(text).unwrap_or_else(|_| ClientMessage::Text(text.to_string()));
+            return Ok((None, message));
+        }
+        let frame: ClientFrame = serde_json::from_str(text).map_err(|e| format!("Invalid message: {}", e))?;
+        Ok((frame.id, frame.message))
+    }
+
+    /// Sends `message` in reply to the current request.
+    ///
+    /// Returns `false` once the socket is closed.
+    pub async fn send(&mut self, message: &ServerMessage) -> bool {
+        let json = if self.legacy {
+            match message {
+                // Legacy clients predate model details and expect names.
+                ServerMessage::AvailableModels(models) => {
+                    let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+                    serde_json::to_string(&serde_json::json!({ "AvailableModels": names }))
+                }
+                _ => serde_json::to_string(message),
+            }
+        } else {
+            serde_json::to_string(&ServerFrame { reply_to: self.request, message: message.clone() })
+        };
+        match json {
+            Ok(json) => self.socket.send(WsMessage::Text(json)).await.is_ok(),
+            Err(e) => {
+                tracing::error!("Failed to serialize server message: {}", e);
+                true
+            }
+        }
+    }
+}
+
+async fn next_text(socket: &mut WebSocket) -> Option<String> {
+    loop {
+        match socket.recv().await? {
+            Ok(WsMessage::Text(text)) => return Some(text),
+            Ok(WsMessage::Close(_)) | Err(_) => return None,
+            Ok(_) => {}
+        }
+    }
+}
+
+/// Tells the client why in a bare `Error`, readable whatever its version,
+/// and closes the connection.
+async fn reject(mut socket: WebSocket, reason: String) {
+    tracing::warn!("Rejected client: {}", reason);
+    if let Ok(json) = serde_json::to_string(&ServerMessage::Error(reason)) {
+        let _ = socket.send(WsMessage::Text(json)).await;
+    }
+    let close = CloseFrame { code: close_code::PROTOCOL, reason: "protocol handshake failed".into() };
+    let _ = socket.send(WsMessage::Close(Some(close))).await;
+}
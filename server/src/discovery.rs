@@ -0,0 +1,252 @@
+//! Finds GGUF models in the configured `models_dirs` and derives launch
+//! profiles for them from their metadata, so new downloads need no
+//! `models.json` entry.
+//!
+//! Models split into shards (`name-00001-of-00003.gguf`) are registered
+//! once, by their first shard, which is what llama-server is given.
+//! Projectors and adapters, GGUF files that are not models, are not
+//! registered; a projector (`mmproj*.gguf`) is given to the models next to
+//! it, making them see images.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::Result;
+use notify::{RecursiveMode, Watcher};
+use shared::GgufMetadata;
+use tokio::sync::mpsc;
+use crate::config::{BackendKind, ModelConfig};
+use crate::gguf;
+
+/// Context size given to discovered models, unless trained for less. Larger
+/// contexts cost memory that a default should not take for granted.
+const DISCOVERED_CONTEXT: u64 = 8192;
+
+/// Directories are not searched deeper than this.
+const MAX_DEPTH: usize = 4;
+
+/// Quiet time after a file system change before rescanning: a download
+/// writes for minutes, a copy in many chunks.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// A model found on disk.
+#[derive(Debug, Clone)]
+pub struct Discovered {
+    /// Path relative to its models directory, without `.gguf` and shard suffix.
+    pub name: String,
+    pub config: ModelConfig,
+    pub metadata: GgufMetadata,
+    /// Size of all its files.
+    pub file_size: u64,
+}
+
+/// Scans `dirs` for models. On name clashes the earlier directory wins.
+pub fn scan(dirs: &[String]) -> Vec<Discovered> {
+    let mut found: BTreeMap<String, Discovered> = BTreeMap::new();
+    for dir in dirs {
+        let dir = Path::new(dir);
+        let mut files = Vec::new();
+        if let Err(e) = collect(dir, 0, &mut files) {
+            tracing::warn!("Cannot scan models directory {}: {}", dir.display(), e);
+            continue;
+        }
+        files.sort();
+        let (projectors, files): (Vec<_>, Vec<_>) = files.into_iter().partition(|path| is_projector(path));
+        let mut projectors_by_dir: HashMap<&Path, Vec<&PathBuf>> = HashMap::new();
+        for projector in &projectors {
+            projectors_by_dir.entry(projector.parent().unwrap_or(dir)).or_default().push(projector);
+        }
+        for model in group_shards(dir, files) {
+            if found.contains_key(&model.name) {
+                continue;
+            }
+            // Only an unambiguous projector is paired.
+            let projector = match projectors_by_dir.get(model.paths[0].parent().unwrap_or(dir)).map(Vec::as_slice) {
+                Some([projector]) => Some(projector.as_path()),
+                _ => None,
+            };
+            if let Some(discovered) = inspect(model, projector) {
+                found.insert(discovered.name.clone(), discovered);
+            }
+        }
+    }
+    found.into_values().collect()
+}
+
+/// The `.gguf` files below `dir`.
+fn collect(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if depth < MAX_DEPTH {
+                collect(&path, depth + 1, files)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gguf")) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Multimodal projectors are named `mmproj-<model>.gguf` by llama.cpp's
+/// conversion scripts.
+fn is_projector(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().to_ascii_lowercase().starts_with("mmproj"))
+}
+
+/// The files of one model: one, or all shards of a split model in order.
+struct ModelFiles {
+    name: String,
+    paths: Vec<PathBuf>,
+}
+
+/// Groups sorted `files` into models named relative to `dir`.
+fn group_shards(dir: &Path, files: Vec<PathBuf>) -> Vec<ModelFiles> {
+    let mut models: Vec<ModelFiles> = Vec::new();
+    for path in files {
+        let relative = path.strip_prefix(dir).unwrap_or(&path).with_extension("");
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let (name, shard) = match split_shard(&relative) {
+            Some((base, index, count)) => (base.to_string(), Some((index, count))),
+            None => (relative, None),
+        };
+        match (models.last_mut(), shard) {
+            // Later shards join the model started by shard 1.
+            (Some(last), Some((index, count))) if last.name == name && index > 1 && index <= count => {
+                last.paths.push(path);
+            }
+            (_, Some((1, _))) | (_, None) => models.push(ModelFiles { name, paths: vec![path] }),
+            (_, Some(_)) => tracing::warn!("Ignoring {}: its first shard is missing", path.display()),
+        }
+    }
+    models
+}
+
+/// Splits `name-00001-of-00003` into `("name", 1, 3)`.
+fn split_shard(stem: &str) -> Option<(&str, u32, u32)> {
+    let (rest, count) = stem.rsplit_once("-of-")?;
+    let (base, index) = rest.rsplit_once('-')?;
+    let all_digits = |s: &str| s.len() == 5 && s.bytes().all(|b| b.is_ascii_digit());
+    if !all_digits(index) || !all_digits(count) {
+        return None;
+    }
+    Some((base, index.parse().ok()?, count.parse().ok()?))
+}
+
+/// Reads the headers of a model and derives its launch profile.
+fn inspect(model: ModelFiles, projector: Option<&Path>) -> Option<Discovered> {
+    let first = &model.paths[0];
+    let header = match gguf::read(first) {
+        Ok(header) => header,
+        Err(e) => {
+            // Often a download in progress; the next change rescans it.
+            tracing::warn!("Skipping {}: {:#}", first.display(), e);
+            return None;
+        }
+    };
+    if let Some(gguf::Value::String(kind)) = header.get("general.type") {
+        if kind != "model" {
+            tracing::debug!("Skipping {}: a {}, not a model", first.display(), kind);
+            return None;
+        }
+    }
+    let (metadata, file_size) = with_shards(header, &model.paths[1..]);
+
+    Some(Discovered {
+        config: launch_profile(first, &metadata, projector),
+        name: model.name,
+        metadata,
+        file_size,
+    })
+}
+
+/// Metadata and total size of the model whose first file is `path`,
+/// counting the shards that follow it.
+pub fn read_model(path: &Path) -> Result<(GgufMetadata, u64)> {
+    let header = gguf::read(path)?;
+    Ok(with_shards(header, &later_shards(path)))
+}
+
+/// Completes the `header` of a first shard with the parameters and sizes
+/// of `shards`; a single file has none.
+fn with_shards(header: gguf::Header, shards: &[PathBuf]) -> (GgufMetadata, u64) {
+    let mut metadata = header.summary();
+    let mut file_size = header.file_size;
+    // Each shard holds a part of the tensors.
+    let mut parameters = header.parameters;
+    for shard in shards {
+        match gguf::read(shard) {
+            Ok(header) => {
+                parameters += header.parameters;
+                file_size += header.file_size;
+            }
+            Err(e) => tracing::warn!("Cannot read shard {}: {:#}", shard.display(), e),
+        }
+    }
+    metadata.parameters = (parameters > 0).then_some(parameters);
+    (metadata, file_size)
+}
+
+/// Shards 2 to n, if `path` is the first shard of a split model.
+fn later_shards(path: &Path) -> Vec<PathBuf> {
+    let Some((base, 1, count)) = path.file_stem().and_then(|stem| split_shard(stem.to_str()?)) else {
+        return Vec::new();
+    };
+    (2..=count)
+        .map(|index| path.with_file_name(format!("{}-{:05}-of-{:05}.gguf", base, index, count)))
+        .collect()
+}
+
+/// A managed llama-server for the model at `path`: a context capped at
+/// [`DISCOVERED_CONTEXT`], the embedded chat template if there is one, the
+/// `projector` for images and embeddings for embedding models.
+fn launch_profile(path: &Path, metadata: &GgufMetadata, projector: Option<&Path>) -> ModelConfig {
+    let context = metadata.context_length.map_or(DISCOVERED_CONTEXT, |trained| trained.min(DISCOVERED_CONTEXT));
+    let mut args = vec!["-c".to_string(), context.to_string()];
+    if metadata.chat_template.is_some() {
+        args.push("--jinja".to_string());
+    }
+    if let Some(projector) = projector {
+        args.push("--mmproj".to_string());
+        args.push(projector.to_string_lossy().into_owned());
+    }
+    if metadata.embedding {
+        args.push("--embedding".to_string());
+    }
+    ModelConfig {
+        backend: Some(BackendKind::Managed),
+        path: Some(path.to_string_lossy().into_owned()),
+        args,
+        ..ModelConfig::default()
+    }
+}
+
+/// Watches `dirs` for changes, sending a notice on `changes` once they have
+/// settled for [`SETTLE_TIME`]. Changes stop being watched when the returned
+/// watcher is dropped.
+pub fn watch(dirs: &[String], changes: mpsc::Sender<()>) -> Result<notify::RecommendedWatcher> {
+    let (events, mut raw) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if !event.kind.is_access() {
+                let _ = events.send(());
+            }
+        }
+    })?;
+    for dir in dirs {
+        if let Err(e) = watcher.watch(Path::new(dir), RecursiveMode::Recursive) {
+            tracing::warn!("Cannot watch models directory {}: {}", dir, e);
+        }
+    }
+
+    tokio::spawn(async move {
+        while raw.recv().await.is_some() {
+            // Wait for a quiet moment, swallowing the burst.
+            while let Ok(Some(())) = tokio::time::timeout(SETTLE_TIME, raw.recv()).await {}
+            if changes.send(()).await.is_err() {
+                return;
+            }
+        }
+    });
+    Ok(watcher)
+}
@@ -0,0 +1,195 @@
+//! Conversation export to Markdown, HTML and JSON.
+//!
+//! Markdown and HTML contain the active branch of each conversation, which
+//! is what a reader expects to see. JSON contains the complete message tree;
+//! its layout is described in `docs/EXPORT_FORMAT.md`.
+
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use shared::{unix_now, ChatHistory, ExportFormat, Message, Role};
+use crate::cli::ExportArgs;
+use crate::store::ConversationStore;
+
+/// Identifies JSON exports written by this server.
+pub const JSON_FORMAT_NAME: &str = "llamacpp-chat-export";
+/// Bumped whenever the JSON export layout changes incompatibly.
+pub const JSON_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    format: &'static str,
+    version: u32,
+    exported_at: u64,
+    conversations: &'a [ChatHistory],
+}
+
+/// Renders `conversations` in the requested format.
+pub fn render(conversations: &[ChatHistory], format: ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Markdown => Ok(markdown(conversations)),
+        ExportFormat::Html => Ok(html(conversations)),
+        ExportFormat::Json => {
+            let export = JsonExport {
+                format: JSON_FORMAT_NAME,
+                version: JSON_FORMAT_VERSION,
+                exported_at: unix_now(),
+                conversations,
+            };
+            Ok(serde_json::to_string_pretty(&export)?)
+        }
+    }
+}
+
+/// Runs the `export` subcommand.
+pub fn run_cli(args: &ExportArgs, store: &ConversationStore) -> Result<()> {
+    let conversations = if args.all {
+        store.list()?
+    } else if let Some(id) = &args.conversation {
+        vec![store.load(id)?]
+    } else {
+        vec![store.latest()?.ok_or_else(|| anyhow!("No conversations to export"))?]
+    };
+
+    let content = render(&conversations, args.format)?;
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+            eprintln!("Exported {} conversation(s) to {}", conversations.len(), path.display());
+        }
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+/// Writes an export into `dir` as `<name>-<time>.<ext>` and returns its path.
+pub fn write_to_dir(dir: &Path, name: &str, format: ExportFormat, content: &str) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create export directory {}", dir.display()))?;
+    let path = dir.join(format!("{}-{}.{}", name, unix_now(), format.extension()));
+    std::fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+fn markdown(conversations: &[ChatHistory]) -> String {
+    let mut out = String::new();
+    for history in conversations {
+        out.push_str(&format!("# {}\n\n", title(history)));
+        out.push_str(&format!("- Conversation: `{}`\n", history.id));
+        out.push_str(&format!("- Model: {}\n", history.current_model));
+        out.push_str(&format!("- Created: {}\n\n", format_time(history.created_at)));
+
+        for message in history.active_path() {
+            out.push_str(&format!(
+                "## {} · {}\n\n{}\n\n",
+                speaker(history, message),
+                format_time(message.timestamp),
+                message.content
+            ));
+            for call in &message.tool_calls {
+                out.push_str(&format!("Calls `{}`\n\n", call.summary()));
+            }
+        }
+    }
+    out
+}
+
+fn html(conversations: &[ChatHistory]) -> String {
+    let mut body = String::new();
+    for history in conversations {
+        body.push_str("<section class=\"conversation\">\n");
+        body.push_str(&format!("<h1>{}</h1>\n", escape_html(&title(history))));
+        body.push_str(&format!(
+            "<p class=\"meta\">Model: {} · Created: {}</p>\n",
+            escape_html(&history.current_model),
+            format_time(history.created_at)
+        ));
+        for message in history.active_path() {
+            let class = match message.role {
+                Role::User => "user",
+                Role::Assistant => "assistant",
+                Role::Tool => "tool",
+            };
+            let calls: String = message
+                .tool_calls
+                .iter()
+                .map(|call| format!("<div class=\"call\">Calls <code>{}</code></div>", escape_html(&call.summary())))
+                .collect();
+            body.push_str(&format!(
+                "<div class=\"message {}\"><div class=\"meta\">{} · {}</div><div class=\"content\">{}</div>{}</div>\n",
+                class,
+                escape_html(&speaker(history, message)),
+                format_time(message.timestamp),
+                escape_html(&message.content),
+                calls
+            ));
+        }
+        body.push_str("</section>\n");
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Chat export</title>
+<style>
+body {{ font-family: sans-serif; max-width: 50rem; margin: 2rem auto; padding: 0 1rem; }}
+.meta {{ color: #666; font-size: 0.85rem; margin-bottom: 0.25rem; }}
+.message {{ border-radius: 6px; padding: 0.75rem; margin: 0.75rem 0; }}
+.user {{ background: #eef3ff; }}
+.assistant {{ background: #f4f4f4; }}
+.tool {{ background: #fbf8ee; font-family: monospace; }}
+.call {{ color: #666; font-size: 0.85rem; margin-top: 0.5rem; }}
+.content {{ white-space: pre-wrap; }}
+</style>
+</head>
+<body>
+{}</body>
+</html>
+"#,
+        body
+    )
+}
+
+fn title(history: &ChatHistory) -> String {
+    history
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("Conversation {}", history.id))
+}
+
+fn speaker(history: &ChatHistory, message: &Message) -> String {
+    match (&message.role, &message.model) {
+        (Role::User, _) => "User".to_string(),
+        (Role::Assistant, Some(model)) => format!("Assistant ({})", model),
+        (Role::Assistant, None) => "Assistant".to_string(),
+        (Role::Tool, _) => match message.tool_call_id.as_deref().and_then(|id| history.tool_call(id)) {
+            Some(call) => format!("Tool ({})", call.name),
+            None => "Tool".to_string(),
+        },
+    }
+}
+
+/// Formats a Unix timestamp as UTC, or "unknown time".
+pub fn format_time(timestamp: Option<u64>) -> String {
+    timestamp
+        .and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0))
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| "unknown time".to_string())
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
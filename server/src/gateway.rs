@@ -0,0 +1,183 @@
+//! OpenAI-compatible HTTP API.
+//!
+//! `/v1/models` lists the models from `models.json` and
+//! `/v1/chat/completions` resolves the request's `model` against them, loads
+//! it through the [`ProcessManager`](crate::process::ProcessManager) if
+//! needed, and proxies the request to the model's backend. Streaming
+//! responses are passed through chunk by chunk.
+//!
+//! Sending an `X-Conversation: <id>` header records the last user message
+//! and the reply into that conversation, creating it if necessary.
+
+use std::sync::Arc;
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use shared::{ChatHistory, Role};
+use tokio::sync::mpsc;
+use crate::openai::SseParser;
+use crate::store;
+use crate::{save_history, switch_model, update_conversation, AppState};
+
+const CONVERSATION_HEADER: &str = "x-conversation";
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/v1/models", get(list_models))
+        .route("/v1/chat/completions", post(chat_completions))
+}
+
+async fn list_models(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let data: Vec<Value> = state
+        .models
+        .read()
+        .unwrap()
+        .iter()
+        .into_iter()
+        .map(|(name, _)| json!({ "id": name, "object": "model", "created": 0, "owned_by": "local" }))
+        .collect();
+    Json(json!({ "object": "list", "data": data }))
+}
+
+async fn chat_completions(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    let Some(model) = body.get("model").and_then(Value::as_str).map(str::to_string) else {
+        return error(StatusCode::BAD_REQUEST, "invalid_request_error", "`model` is required");
+    };
+    if !state.models.read().unwrap().contains(&model) {
+        let message = format!("The model `{}` does not exist", model);
+        return error(StatusCode::NOT_FOUND, "model_not_found", message);
+    }
+
+    let conversation = headers
+        .get(CONVERSATION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    if let Some(id) = &conversation {
+        if !store::valid_id(id) {
+            let message = format!("Invalid conversation id `{}`", id);
+            return error(StatusCode::BAD_REQUEST, "invalid_request_error", message);
+        }
+    }
+
+    if let Err(e) = switch_model(&state, &model, true).await {
+        let message = format!("Failed to load model `{}`: {}", model, e);
+        return error(StatusCode::SERVICE_UNAVAILABLE, "model_unavailable", message);
+    }
+
+    let prompt = last_user_message(&body);
+    let streaming = body.get("stream").and_then(Value::as_bool).unwrap_or(false);
+
+    let client = match state.process_manager.lock().await.client() {
+        Ok(client) => client,
+        Err(e) => return error(StatusCode::SERVICE_UNAVAILABLE, "model_unavailable", format!("{:#}", e)),
+    };
+    let upstream = match client.forward(&body).await {
+        Ok(res) => res,
+        Err(e) => return error(StatusCode::BAD_GATEWAY, "backend_error", format!("{:#}", e)),
+    };
+
+    let status = StatusCode::from_u16(upstream.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    if !status.is_success() || !streaming {
+        let text = upstream.text().await.unwrap_or_default();
+        if status.is_success() {
+            if let (Some(id), Ok(json)) = (&conversation, serde_json::from_str::<Value>(&text)) {
+                let reply = json.pointer("/choices/0/message/content").and_then(Value::as_str);
+                record_exchange(&state, id, &model, prompt, reply.unwrap_or_default().to_string());
+            }
+        }
+        return (status, [(header::CONTENT_TYPE, "application/json")], text).into_response();
+    }
+
+    // Relay the SSE stream while collecting the reply for recording.
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(32);
+    tokio::spawn(async move {
+        let mut chunks = upstream.bytes_stream();
+        let mut parser = SseParser::default();
+        let mut reply = String::new();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    let _ = tx.send(Err(std::io::Error::other(e))).await;
+                    return;
+                }
+            };
+            reply.push_str(&parser.feed(&chunk));
+            if tx.send(Ok(chunk)).await.is_err() {
+                // The caller went away; do not record an incomplete reply.
+                return;
+            }
+        }
+        if let Some(id) = conversation {
+            record_exchange(&state, &id, &model, prompt, reply);
+        }
+    });
+
+    let body = futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::from_stream(body))
+        .unwrap_or_else(|e| error(StatusCode::INTERNAL_SERVER_ERROR, "server_error", e.to_string()))
+}
+
+/// Text of the last `user` message, with content given as a string or as typed parts.
+fn last_user_message(body: &Value) -> Option<String> {
+    let message = body
+        .get("messages")?
+        .as_array()?
+        .iter()
+        .rev()
+        .find(|m| m.get("role").and_then(Value::as_str) == Some("user"))?;
+    match message.get("content")? {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(parts) => Some(
+            parts
+                .iter()
+                .filter_map(|p| p.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join(""),
+        ),
+        _ => None,
+    }
+}
+
+/// Appends a prompt and its reply to conversation `id`, creating it if needed.
+fn record_exchange(state: &AppState, id: &str, model: &str, prompt: Option<String>, reply: String) {
+    let record = |history: &mut ChatHistory| {
+        history.current_model = model.to_string();
+        if let Some(prompt) = prompt {
+            history.push(Role::User, prompt);
+        }
+        history.push(Role::Assistant, reply);
+        true
+    };
+
+    let exists = state.history.lock().unwrap().id == id || state.store.exists(id);
+    if exists {
+        if let Err(e) = update_conversation(state, id, record) {
+            tracing::error!("Failed to record exchange into {}: {}", id, e);
+        }
+    } else {
+        let mut history = state.store.create(model);
+        history.id = id.to_string();
+        record(&mut history);
+        save_history(state, &mut history);
+    }
+}
+
+fn error(status: StatusCode, kind: &str, message: impl Into<String>) -> Response {
+    let body = json!({ "error": { "message": message.into(), "type": kind } });
+    (status, Json(body)).into_response()
+}
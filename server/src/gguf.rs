@@ -0,0 +1,416 @@
+//! Reads the metadata of GGUF model files without loading the model: the
+//! key-value header and the tensor descriptions, never the weights.
+//!
+//! See <https://github.com/ggml-org/ggml/blob/master/docs/gguf.md> for the
+//! format. Versions 2 and 3 are supported; version 1 files predate 64-bit
+//! lengths and are long gone.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use shared::GgufMetadata;
+
+const MAGIC: &[u8; 4] = b"GGUF";
+
+/// Sanity limit for string lengths and counts, so a corrupt file fails
+/// cleanly instead of allocating gigabytes.
+const MAX_LEN: u64 = 1 << 30;
+
+/// Deepest nesting of arrays in arrays read, so a crafted file cannot
+/// overflow the stack. Real files nest at most once.
+const MAX_DEPTH: u32 = 8;
+
+/// A metadata value. Arrays are not kept, only their length: the tokenizer's
+/// vocabulary alone has hundreds of thousands of entries.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Uint(u64),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    Array { len: u64 },
+}
+
+impl Value {
+    fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Uint(v) => Some(v),
+            Value::Int(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// The header of a GGUF file.
+#[derive(Debug, Default)]
+pub struct Header {
+    pub metadata: HashMap<String, Value>,
+    /// Number of weights in the tensors of this file.
+    pub parameters: u64,
+    /// Size of the file, weights included.
+    pub file_size: u64,
+}
+
+impl Header {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.metadata.get(key)
+    }
+
+    /// The metadata clients are shown.
+    pub fn summary(&self) -> GgufMetadata {
+        let string = |key: &str| self.get(key).and_then(Value::as_str).map(str::to_string);
+        let architecture = string("general.architecture");
+        let arch_u64 = |key: &str| {
+            let arch = architecture.as_deref()?;
+            self.get(&format!("{}.{}", arch, key)).and_then(Value::as_u64)
+        };
+        GgufMetadata {
+            name: string("general.name"),
+            parameters: (self.parameters > 0).then_some(self.parameters),
+            quantization: self.get("general.file_type").and_then(Value::as_u64).and_then(file_type_name).map(str::to_string),
+            context_length: arch_u64("context_length"),
+            chat_template: string("tokenizer.chat_template"),
+            tokenizer: string("tokenizer.ggml.model"),
+            vocab_size: match self.get("tokenizer.ggml.tokens") {
+                Some(Value::Array { len }) => Some(*len),
+                _ => None,
+            },
+            embedding: arch_u64("pooling_type").is_some(),
+            architecture,
+        }
+    }
+
+    /// Size of the f16 KV cache llama-server allocates per context token:
+    /// a key and a value for each KV head of each layer. `None` if the
+    /// header lacks the dimensions.
+    pub fn kv_bytes_per_token(&self) -> Option<u64> {
+        let arch = self.get("general.architecture").and_then(Value::as_str)?;
+        let arch_u64 = |key: &str| self.get(&format!("{}.{}", arch, key)).and_then(Value::as_u64);
+        let layers = arch_u64("block_count")?;
+        let heads = arch_u64("attention.head_count").filter(|&heads| heads > 0)?;
+        // Grouped-query attention caches fewer heads than it queries with.
+        let kv_heads = arch_u64("attention.head_count_kv").unwrap_or(heads);
+        let head_size = arch_u64("embedding_length")? / heads;
+        let key_length = arch_u64("attention.key_length").unwrap_or(head_size);
+        let value_length = arch_u64("attention.value_length").unwrap_or(head_size);
+        // Checked, as the header may hold anything.
+        layers.checked_mul(kv_heads)?.checked_mul(key_length.checked_add(value_length)?)?.checked_mul(2)
+    }
+}
+
+/// Reads the header of the GGUF file at `path`.
+pub fn read(path: impl AsRef<Path>) -> Result<Header> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let file_size = file.metadata()?.len();
+    let header = parse(&mut BufReader::new(file)).with_context(|| format!("Failed to read GGUF header of {}", path.display()))?;
+    Ok(Header { file_size, ..header })
+}
+
+/// Parses a GGUF header from the start of `reader`.
+pub fn parse(reader: &mut impl Read) -> Result<Header> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("Not a GGUF file");
+    }
+    let version = read_u32(reader)?;
+    if !(2..=3).contains(&version) {
+        bail!("Unsupported GGUF version {}", version);
+    }
+    let tensor_count = read_len(reader)?;
+    let metadata_count = read_len(reader)?;
+
+    let mut metadata = HashMap::new();
+    for _ in 0..metadata_count {
+        let key = read_string(reader)?;
+        let kind = read_u32(reader)?;
+        let value = read_value(reader, kind, 0).with_context(|| format!("Invalid value of {}", key))?;
+        metadata.insert(key, value);
+    }
+
+    let mut parameters: u64 = 0;
+    for _ in 0..tensor_count {
+        skip_string(reader)?;
+        let dims = read_u32(reader)?;
+        let mut elements: u64 = 1;
+        for _ in 0..dims {
+            elements = elements.saturating_mul(read_u64(reader)?);
+        }
+        read_u32(reader)?; // type
+        read_u64(reader)?; // offset
+        parameters = parameters.saturating_add(elements);
+    }
+
+    Ok(Header { metadata, parameters, file_size: 0 })
+}
+
+/// Reads a value of type `kind`, inside `depth` arrays.
+fn read_value(reader: &mut impl Read, kind: u32, depth: u32) -> Result<Value> {
+    Ok(match kind {
+        0 => Value::Uint(read_array::<1>(reader)?[0] as u64),
+        1 => Value::Int(read_array::<1>(reader)?[0] as i8 as i64),
+        2 => Value::Uint(u16::from_le_bytes(read_array(reader)?) as u64),
+        3 => Value::Int(i16::from_le_bytes(read_array(reader)?) as i64),
+        4 => Value::Uint(read_u32(reader)? as u64),
+        5 => Value::Int(i32::from_le_bytes(read_array(reader)?) as i64),
+        6 => Value::Float(f32::from_le_bytes(read_array(reader)?) as f64),
+        7 => Value::Bool(read_array::<1>(reader)?[0] != 0),
+        8 => Value::String(read_string(reader)?),
+        9 => {
+            if depth >= MAX_DEPTH {
+                bail!("Arrays nested deeper than {}", MAX_DEPTH);
+            }
+            let item_kind = read_u32(reader)?;
+            let len = read_len(reader)?;
+            for _ in 0..len {
+                skip_value(reader, item_kind, depth + 1)?;
+            }
+            Value::Array { len }
+        }
+        10 => Value::Uint(read_u64(reader)?),
+        11 => Value::Int(i64::from_le_bytes(read_array(reader)?)),
+        12 => Value::Float(f64::from_le_bytes(read_array(reader)?)),
+        other => bail!("Unknown value type {}", other),
+    })
+}
+
+fn skip_value(reader: &mut impl Read, kind: u32, depth: u32) -> Result<()> {
+    let size = match kind {
+        0 | 1 | 7 => 1,
+        2 | 3 => 2,
+        4..=6 => 4,
+        10..=12 => 8,
+        8 => return skip_string(reader),
+        _ => {
+            read_value(reader, kind, depth)?;
+            return Ok(());
+        }
+    };
+    skip(reader, size)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    read_array(reader).map(u32::from_le_bytes)
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    read_array(reader).map(u64::from_le_bytes)
+}
+
+fn read_len(reader: &mut impl Read) -> Result<u64> {
+    let len = read_u64(reader)?;
+    if len > MAX_LEN {
+        bail!("Implausible length {}", len);
+    }
+    Ok(len)
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let len = read_len(reader)?;
+    // Grown as bytes arrive: a corrupt length must not allocate up front.
+    let mut buf = Vec::new();
+    reader.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn skip_string(reader: &mut impl Read) -> Result<()> {
+    let len = read_len(reader)?;
+    skip(reader, len)
+}
+
+fn skip(reader: &mut impl Read, len: u64) -> Result<()> {
+    if io::copy(&mut reader.take(len), &mut io::sink())? != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(())
+}
+
+/// Name of a `general.file_type`, llama.cpp's `llama_ftype`.
+fn file_type_name(file_type: u64) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        19 => "IQ2_XXS",
+        20 => "IQ2_XS",
+        21 => "Q2_K_S",
+        22 => "IQ3_XS",
+        23 => "IQ3_XXS",
+        24 => "IQ1_S",
+        25 => "IQ4_NL",
+        26 => "IQ3_S",
+        27 => "IQ3_M",
+        28 => "IQ2_S",
+        29 => "IQ2_M",
+        30 => "IQ4_XS",
+        31 => "IQ1_M",
+        32 => "BF16",
+        36 => "TQ1_0",
+        37 => "TQ2_0",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GGUF bytes, written field by field.
+    #[derive(Default)]
+    struct Fixture(Vec<u8>);
+
+    impl Fixture {
+        /// Magic, `version` and the two counts.
+        fn header(version: u32, tensors: u64, metadata: u64) -> Self {
+            Self::default().bytes(MAGIC).u32(version).u64(tensors).u64(metadata)
+        }
+
+        fn bytes(mut self, bytes: &[u8]) -> Self {
+            self.0.extend_from_slice(bytes);
+            self
+        }
+
+        fn u32(self, v: u32) -> Self {
+            self.bytes(&v.to_le_bytes())
+        }
+
+        fn u64(self, v: u64) -> Self {
+            self.bytes(&v.to_le_bytes())
+        }
+
+        fn string(self, s: &str) -> Self {
+            self.u64(s.len() as u64).bytes(s.as_bytes())
+        }
+
+        fn parse(&self) -> Result<Header> {
+            parse(&mut self.0.as_slice())
+        }
+    }
+
+    fn error(fixture: Fixture) -> String {
+        format!("{:#}", fixture.parse().unwrap_err())
+    }
+
+    #[test]
+    fn reads_metadata_and_counts_weights() {
+        let fixture = Fixture::header(3, 2, 5)
+            .string("general.architecture").u32(8).string("llama")
+            .string("llama.context_length").u32(4).u32(4096)
+            .string("general.file_type").u32(4).u32(15)
+            .string("tokenizer.ggml.tokens").u32(9).u32(8).u64(2).string("<s>").string("</s>")
+            .string("tokenizer.ggml.merges").u32(9).u32(9).u64(1).u32(0).u64(3).bytes(&[1, 2, 3])
+            .string("token_embd.weight").u32(2).u64(4).u64(8).u32(0).u64(0)
+            .string("output.weight").u32(1).u64(4).u32(0).u64(128);
+
+        let header = fixture.parse().unwrap();
+        assert_eq!(header.parameters, 36);
+        assert_eq!(header.get("tokenizer.ggml.merges"), Some(&Value::Array { len: 1 }));
+        let summary = header.summary();
+        assert_eq!(summary.architecture.as_deref(), Some("llama"));
+        assert_eq!(summary.context_length, Some(4096));
+        assert_eq!(summary.quantization.as_deref(), Some("Q4_K_M"));
+        assert_eq!(summary.vocab_size, Some(2));
+        assert!(!summary.embedding);
+    }
+
+    #[test]
+    fn sizes_the_kv_cache_from_the_dimensions() {
+        let dimensions = |fixture: Fixture| {
+            fixture
+                .string("general.architecture").u32(8).string("llama")
+                .string("llama.block_count").u32(4).u32(32)
+                .string("llama.embedding_length").u32(4).u32(4096)
+                .string("llama.attention.head_count").u32(4).u32(32)
+        };
+        // 32 layers of 4096-wide f16 keys and values.
+        let full = dimensions(Fixture::header(3, 0, 4)).parse().unwrap();
+        assert_eq!(full.kv_bytes_per_token(), Some(512 * 1024));
+        let grouped = dimensions(Fixture::header(3, 0, 5)).string("llama.attention.head_count_kv").u32(4).u32(8);
+        assert_eq!(grouped.parse().unwrap().kv_bytes_per_token(), Some(128 * 1024));
+
+        let incomplete = Fixture::header(3, 0, 2)
+            .string("general.architecture").u32(8).string("llama")
+            .string("llama.block_count").u32(4).u32(32);
+        assert_eq!(incomplete.parse().unwrap().kv_bytes_per_token(), None);
+    }
+
+    #[test]
+    fn refuses_other_files_and_versions() {
+        assert_eq!(error(Fixture::default().bytes(b"GGML").u32(3)), "Not a GGUF file");
+        assert_eq!(error(Fixture::header(1, 0, 0)), "Unsupported GGUF version 1");
+        assert_eq!(error(Fixture::header(4, 0, 0)), "Unsupported GGUF version 4");
+        assert!(Fixture::header(2, 0, 0).parse().is_ok());
+    }
+
+    #[test]
+    fn refuses_truncated_files() {
+        let short_value = Fixture::header(3, 0, 1).string("general.name").u32(8).u64(10).bytes(b"abc");
+        assert_eq!(error(short_value), "Invalid value of general.name: unexpected end of file");
+        let short_key = Fixture::header(3, 0, 1).u64(1 << 29).bytes(b"general");
+        assert_eq!(error(short_key), "unexpected end of file");
+        let short_tensor = Fixture::header(3, 1, 0).string("output.weight").u32(2).u64(4);
+        assert!(short_tensor.parse().is_err());
+        assert!(Fixture::default().bytes(b"GG").parse().is_err());
+    }
+
+    #[test]
+    fn refuses_implausible_lengths() {
+        assert_eq!(error(Fixture::header(3, 0, MAX_LEN + 1)), format!("Implausible length {}", MAX_LEN + 1));
+        let long_array = Fixture::header(3, 0, 1).string("tokenizer.ggml.tokens").u32(9).u32(8).u64(u64::MAX);
+        assert_eq!(error(long_array), format!("Invalid value of tokenizer.ggml.tokens: Implausible length {}", u64::MAX));
+    }
+
+    #[test]
+    fn refuses_unknown_value_types() {
+        let fixture = Fixture::header(3, 0, 1).string("general.name").u32(13);
+        assert_eq!(error(fixture), "Invalid value of general.name: Unknown value type 13");
+        let in_array = Fixture::header(3, 0, 1).string("general.tags").u32(9).u32(42).u64(1);
+        assert_eq!(error(in_array), "Invalid value of general.tags: Unknown value type 42");
+    }
+
+    #[test]
+    fn refuses_arrays_nested_too_deeply() {
+        let nested = |depth: u32| {
+            let mut fixture = Fixture::header(3, 0, 1).string("general.tags").u32(9);
+            for _ in 1..depth {
+                fixture = fixture.u32(9).u64(1);
+            }
+            fixture.u32(4).u64(1).u32(7)
+        };
+        assert!(nested(MAX_DEPTH).parse().is_ok());
+        assert_eq!(error(nested(MAX_DEPTH + 1)), format!("Invalid value of general.tags: Arrays nested deeper than {}", MAX_DEPTH));
+    }
+}
@@ -0,0 +1,558 @@
+//! Importers for transcripts produced by other chat tools.
+//!
+//! Supported inputs:
+//! - OpenAI-style `messages` arrays: a bare array, an object with a
+//!   `messages` field, or JSON Lines with one such object per line.
+//! - ChatGPT's `conversations.json` data export, including its branches.
+//! - llama.cpp web UI exports: `{ "conv": ..., "messages": [...] }` or an
+//!   array of those.
+//!
+//! Entries that cannot be represented (system prompts, tool calls, images,
+//! ...) are never dropped silently; they are collected as [`ImportIssue`]s.
+
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use shared::{ChatHistory, MessageId, Role};
+use crate::cli::{ImportArgs, ImportSource};
+use crate::store::ConversationStore;
+
+/// An entry that could not be converted.
+#[derive(Debug)]
+pub struct ImportIssue {
+    /// Where the entry was found, e.g. `conversation 2, message 5`.
+    pub location: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub conversations: Vec<ChatHistory>,
+    pub issues: Vec<ImportIssue>,
+}
+
+impl ImportReport {
+    fn issue(&mut self, location: String, reason: impl Into<String>) {
+        self.issues.push(ImportIssue {
+            location,
+            reason: reason.into(),
+        });
+    }
+}
+
+/// Runs the `import` subcommand.
+pub fn run_cli(args: &ImportArgs, store: &ConversationStore, default_model: &str) -> Result<()> {
+    let content = std::fs::read_to_string(&args.path)
+        .with_context(|| format!("Failed to read {}", args.path.display()))?;
+    let mut report = parse(&content, args.source, default_model)?;
+
+    for issue in &report.issues {
+        eprintln!("warning: {}: {}", issue.location, issue.reason);
+    }
+    if args.strict && !report.issues.is_empty() {
+        bail!("{} unmappable entries, nothing imported (--strict)", report.issues.len());
+    }
+
+    if args.dry_run {
+        eprintln!("Would import {} conversation(s)", report.conversations.len());
+        return Ok(());
+    }
+    for history in &mut report.conversations {
+        store.save(history)?;
+        println!("{}\t{}", history.id, history.title.as_deref().unwrap_or(""));
+    }
+    eprintln!(
+        "Imported {} conversation(s) from {}, {} entries skipped",
+        report.conversations.len(),
+        display_name(&args.path),
+        report.issues.len()
+    );
+    Ok(())
+}
+
+/// Converts `content` from `source` into conversations.
+pub fn parse(content: &str, source: ImportSource, default_model: &str) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+
+    let value = match serde_json::from_str::<Value>(content) {
+        Ok(value) => value,
+        Err(e) => {
+            // Not a single JSON document; JSON Lines is only valid for OpenAI-style data.
+            if matches!(source, ImportSource::Auto | ImportSource::OpenAi) {
+                for (i, line) in content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+                    let location = format!("line {}", i + 1);
+                    match serde_json::from_str::<Value>(line) {
+                        Ok(value) => openai(&value, &location, default_model, &mut report),
+                        Err(e) => report.issue(location, format!("invalid JSON: {}", e)),
+                    }
+                }
+                return Ok(report);
+            }
+            return Err(e).context("Input is not valid JSON");
+        }
+    };
+
+    match source.resolve(&value)? {
+        ImportSource::OpenAi => match &value {
+            // Several conversations, each with its own `messages`
+            Value::Array(items) if items.first().is_some_and(|i| i.get("messages").is_some()) => {
+                for (i, item) in items.iter().enumerate() {
+                    openai(item, &format!("conversation {}", i + 1), default_model, &mut report);
+                }
+            }
+            _ => openai(&value, "conversation 1", default_model, &mut report),
+        },
+        ImportSource::ChatGpt => {
+            let items = value.as_array().ok_or_else(|| anyhow!("Expected an array of conversations"))?;
+            for (i, item) in items.iter().enumerate() {
+                chatgpt(item, &format!("conversation {}", i + 1), default_model, &mut report);
+            }
+        }
+        ImportSource::LlamaCpp => {
+            let items = match &value {
+                Value::Array(items) => items.clone(),
+                other => vec![other.clone()],
+            };
+            for (i, item) in items.iter().enumerate() {
+                llamacpp(item, &format!("conversation {}", i + 1), default_model, &mut report);
+            }
+        }
+        ImportSource::Auto => unreachable!("resolve never returns Auto"),
+    }
+    Ok(report)
+}
+
+impl ImportSource {
+    /// Detects the format of `value` when `self` is `Auto`.
+    fn resolve(self, value: &Value) -> Result<ImportSource> {
+        if self != ImportSource::Auto {
+            return Ok(self);
+        }
+        let first = match value {
+            Value::Array(items) => items.first().unwrap_or(&Value::Null),
+            other => other,
+        };
+        if first.get("mapping").is_some() {
+            Ok(ImportSource::ChatGpt)
+        } else if first.get("conv").is_some() {
+            Ok(ImportSource::LlamaCpp)
+        } else if first.get("messages").is_some() || first.get("role").is_some() {
+            Ok(ImportSource::OpenAi)
+        } else {
+            bail!("Could not detect the input format, pass --format")
+        }
+    }
+}
+
+fn openai(value: &Value, location: &str, default_model: &str, report: &mut ImportReport) {
+    let messages = match value {
+        Value::Array(messages) => messages,
+        other => match other.get("messages").and_then(Value::as_array) {
+            Some(messages) => messages,
+            None => {
+                report.issue(location.to_string(), "no `messages` array");
+                return;
+            }
+        },
+    };
+    let model = value.get("model").and_then(Value::as_str);
+    let mut history = ChatHistory::new(model.unwrap_or(default_model));
+
+    for (i, message) in messages.iter().enumerate() {
+        let at = format!("{}, message {}", location, i + 1);
+        let Some(role) = map_role(message.get("role").and_then(Value::as_str), &at, report) else {
+            continue;
+        };
+        let Some(content) = openai_content(message.get("content"), &at, report) else {
+            continue;
+        };
+        let parent = history.active_leaf;
+        push_imported(&mut history, parent, role, content, None, model);
+    }
+    finish(history, location, report);
+}
+
+/// Joins OpenAI content given as a string or as an array of typed parts.
+fn openai_content(content: Option<&Value>, location: &str, report: &mut ImportReport) -> Option<String> {
+    match content {
+        Some(Value::String(text)) => Some(text.clone()),
+        Some(Value::Array(parts)) => {
+            let mut text = String::new();
+            for part in parts {
+                match (part.get("type").and_then(Value::as_str), part.get("text").and_then(Value::as_str)) {
+                    (Some("text"), Some(t)) => text.push_str(t),
+                    (kind, _) => report.issue(
+                        location.to_string(),
+                        format!("unsupported content part `{}`", kind.unwrap_or("unknown")),
+                    ),
+                }
+            }
+            Some(text)
+        }
+        _ => {
+            report.issue(location.to_string(), "message has no text content");
+            None
+        }
+    }
+}
+
+fn chatgpt(value: &Value, location: &str, default_model: &str, report: &mut ImportReport) {
+    let Some(mapping) = value.get("mapping").and_then(Value::as_object) else {
+        report.issue(location.to_string(), "no `mapping` object");
+        return;
+    };
+    let mut history = ChatHistory::new(default_model);
+    history.title = value.get("title").and_then(Value::as_str).map(str::to_string);
+    if let Some(created) = value.get("create_time").and_then(Value::as_f64) {
+        history.created_at = Some(created as u64);
+    }
+
+    // Walk the tree from its roots so parents are always converted first.
+    // Nodes that are skipped hand their children to the nearest converted ancestor.
+    let mut converted: HashMap<&str, Option<MessageId>> = HashMap::new();
+    let mut queue: Vec<(&str, Option<MessageId>)> = mapping
+        .iter()
+        .filter(|(_, node)| node.get("parent").and_then(Value::as_str).is_none())
+        .map(|(id, _)| (id.as_str(), None))
+        .collect();
+
+    while let Some((node_id, parent)) = queue.pop() {
+        // A node listed twice, or in a cycle, is converted once.
+        let Some(node) = mapping.get(node_id).filter(|_| !converted.contains_key(node_id)) else {
+            continue;
+        };
+        let at = format!("{}, node {}", location, node_id);
+        let mapped = node
+            .get("message")
+            .filter(|m| !m.is_null())
+            .and_then(|message| chatgpt_message(message, &at, report))
+            .map(|(role, content, timestamp, model)| {
+                if let Some(model) = &model {
+                    history.current_model = model.clone();
+                }
+                push_imported(&mut history, parent, role, content, timestamp, model.as_deref())
+            });
+        let own = mapped.or(parent);
+        converted.insert(node_id, own);
+
+        let children = node.get("children").and_then(Value::as_array).into_iter().flatten();
+        // Reverse so the oldest child is converted first and keeps the lowest id.
+        for child in children.rev().filter_map(Value::as_str) {
+            queue.push((child, own));
+        }
+    }
+
+    // Nodes whose parent is missing, or that it does not list, are not
+    // reached from a root.
+    for node_id in mapping.keys().filter(|id| !converted.contains_key(id.as_str())) {
+        report.issue(format!("{}, node {}", location, node_id), "not linked into the conversation tree");
+    }
+
+    history.active_leaf = value
+        .get("current_node")
+        .and_then(Value::as_str)
+        .and_then(|node| converted.get(node).copied().flatten())
+        .or(history.active_leaf);
+    finish(history, location, report);
+}
+
+type Converted = (Role, String, Option<u64>, Option<String>);
+
+fn chatgpt_message(message: &Value, location: &str, report: &mut ImportReport) -> Option<Converted> {
+    let role = message.pointer("/author/role").and_then(Value::as_str);
+    // ChatGPT stores an empty system message at the root of every conversation.
+    let content = message.get("content");
+    let parts = content.and_then(|c| c.get("parts")).and_then(Value::as_array);
+    if role == Some("system") && parts.is_none_or(|p| p.iter().all(|v| v.as_str() == Some(""))) {
+        return None;
+    }
+
+    let role = map_role(role, location, report)?;
+    let kind = content.and_then(|c| c.get("content_type")).and_then(Value::as_str);
+    if kind != Some("text") {
+        report.issue(
+            location.to_string(),
+            format!("unsupported content type `{}`", kind.unwrap_or("unknown")),
+        );
+        return None;
+    }
+
+    let mut text = String::new();
+    for part in parts.into_iter().flatten() {
+        match part.as_str() {
+            Some(t) => text.push_str(t),
+            None => report.issue(location.to_string(), "non-text content part"),
+        }
+    }
+    let timestamp = message.get("create_time").and_then(Value::as_f64).map(|t| t as u64);
+    let model = message.pointer("/metadata/model_slug").and_then(Value::as_str).map(str::to_string);
+    Some((role, text, timestamp, model))
+}
+
+fn llamacpp(value: &Value, location: &str, default_model: &str, report: &mut ImportReport) {
+    let (Some(conv), Some(messages)) = (value.get("conv"), value.get("messages").and_then(Value::as_array)) else {
+        report.issue(location.to_string(), "expected `conv` and `messages`");
+        return;
+    };
+    let mut history = ChatHistory::new(default_model);
+    history.title = conv.get("name").and_then(Value::as_str).map(str::to_string);
+
+    // The web UI uses arbitrary string or numeric ids; messages are stored
+    // parent-first, so a single pass is enough.
+    let mut converted: HashMap<String, Option<MessageId>> = HashMap::new();
+    for (i, message) in messages.iter().enumerate() {
+        let key = message.get("id").map(id_key).unwrap_or_else(|| i.to_string());
+        let at = format!("{}, message {}", location, key);
+        let parent = message
+            .get("parent")
+            .map(id_key)
+            .and_then(|p| converted.get(&p).copied())
+            .flatten();
+        // Timestamps are in milliseconds.
+        let timestamp = message.get("timestamp").and_then(Value::as_u64).map(|t| t / 1000);
+
+        let mapped = if message.get("type").and_then(Value::as_str) == Some("root") {
+            None
+        } else {
+            map_role(message.get("role").and_then(Value::as_str), &at, report).and_then(|role| {
+                match message.get("content").and_then(Value::as_str) {
+                    Some(content) => {
+                        let model = message.get("model").and_then(Value::as_str);
+                        Some(push_imported(&mut history, parent, role, content.to_string(), timestamp, model))
+                    }
+                    None => {
+                        report.issue(at.clone(), "message has no text content");
+                        None
+                    }
+                }
+            })
+        };
+        converted.insert(key, mapped.or(parent));
+    }
+
+    history.active_leaf = conv
+        .get("currNode")
+        .map(id_key)
+        .and_then(|node| converted.get(&node).copied().flatten())
+        .or(history.active_leaf);
+    finish(history, location, report);
+}
+
+fn id_key(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn map_role(role: Option<&str>, location: &str, report: &mut ImportReport) -> Option<Role> {
+    match role {
+        Some("user") => Some(Role::User),
+        Some("assistant") => Some(Role::Assistant),
+        Some(other) => {
+            report.issue(location.to_string(), format!("role `{}` has no equivalent", other));
+            None
+        }
+        None => {
+            report.issue(location.to_string(), "message has no role");
+            None
+        }
+    }
+}
+
+/// Adds a message while keeping the source's timestamp and model.
+fn push_imported(
+    history: &mut ChatHistory,
+    parent: Option<MessageId>,
+    role: Role,
+    content: String,
+    timestamp: Option<u64>,
+    model: Option<&str>,
+) -> MessageId {
+    let id = history.add_child(parent, role, content);
+    if let Some(message) = history.messages.last_mut() {
+        message.timestamp = timestamp;
+        if matches!(message.role, Role::Assistant) {
+            message.model = model.map(str::to_string);
+        }
+    }
+    id
+}
+
+fn finish(mut history: ChatHistory, location: &str, report: &mut ImportReport) {
+    if history.messages.is_empty() {
+        report.issue(location.to_string(), "conversation has no importable messages");
+        return;
+    }
+    // A conversation is at least as old as its oldest message.
+    if let Some(first) = history.messages.iter().filter_map(|m| m.timestamp).min() {
+        history.created_at = Some(history.created_at.map_or(first, |c| c.min(first)));
+    }
+    report.conversations.push(history);
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn import(value: Value, source: ImportSource) -> ImportReport {
+        parse(&value.to_string(), source, "default").unwrap()
+    }
+
+    fn issues(report: &ImportReport) -> Vec<String> {
+        report.issues.iter().map(|issue| format!("{}: {}", issue.location, issue.reason)).collect()
+    }
+
+    /// Role and content of the active branch.
+    fn path(history: &ChatHistory) -> Vec<(Role, &str)> {
+        history.active_path().iter().map(|m| (m.role.clone(), m.content.as_str())).collect()
+    }
+
+    #[test]
+    fn imports_openai_messages_and_reports_the_rest() {
+        let report = import(json!({
+            "model": "gpt-4o",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": [{ "type": "text", "text": "What is this?" }, { "type": "image_url", "image_url": {} }] },
+                { "role": "assistant", "content": "A cat." },
+                { "role": "assistant", "content": null },
+            ],
+        }), ImportSource::Auto);
+
+        let history = &report.conversations[0];
+        assert_eq!(path(history), [(Role::User, "What is this?"), (Role::Assistant, "A cat.")]);
+        assert_eq!(history.current_model, "gpt-4o");
+        assert_eq!(history.active_path()[1].model.as_deref(), Some("gpt-4o"));
+        assert_eq!(issues(&report), [
+            "conversation 1, message 1: role `system` has no equivalent",
+            "conversation 1, message 2: unsupported content part `image_url`",
+            "conversation 1, message 4: message has no text content",
+        ]);
+    }
+
+    #[test]
+    fn imports_json_lines_line_by_line() {
+        let lines = [
+            json!({ "messages": [{ "role": "user", "content": "one" }] }).to_string(),
+            "{ not json".to_string(),
+            String::new(),
+            json!({ "messages": [{ "content": "no role" }] }).to_string(),
+            json!({ "messages": [{ "role": "user", "content": "two" }] }).to_string(),
+        ];
+        let report = parse(&lines.join("\n"), ImportSource::Auto, "default").unwrap();
+
+        let contents: Vec<&str> = report.conversations.iter().map(|c| c.messages[0].content.as_str()).collect();
+        assert_eq!(contents, ["one", "two"]);
+        assert_eq!(report.conversations[0].current_model, "default");
+        let issues = issues(&report);
+        assert_eq!(issues.len(), 3, "{:?}", issues);
+        assert!(issues[0].starts_with("line 2: invalid JSON"), "{}", issues[0]);
+        assert_eq!(issues[1..], ["line 4, message 1: message has no role", "line 4: conversation has no importable messages"]);
+    }
+
+    #[test]
+    fn imports_chatgpt_branches_and_reports_unmappable_nodes() {
+        let text = |role: &str, text: &str| json!({
+            "author": { "role": role },
+            "content": { "content_type": "text", "parts": [text] },
+            "create_time": 1700000100.5,
+        });
+        let report = import(json!([{
+            "title": "Cats",
+            "create_time": 1700000000.0,
+            "current_node": "b",
+            "mapping": {
+                "root": { "parent": null, "message": { "author": { "role": "system" }, "content": { "content_type": "text", "parts": [""] } }, "children": ["q"] },
+                "q": { "parent": "root", "message": text("user", "Draw a cat"), "children": ["a", "img"] },
+                "a": { "parent": "q", "message": text("assistant", "I cannot draw."), "children": [] },
+                "img": { "parent": "q", "message": { "author": { "role": "tool" }, "content": { "content_type": "multimodal_text", "parts": [{}] } }, "children": ["b"] },
+                "b": { "parent": "img", "message": {
+                    "author": { "role": "assistant" },
+                    "content": { "content_type": "text", "parts": ["Here is ", "a cat."] },
+                    "metadata": { "model_slug": "gpt-4" },
+                }, "children": [] },
+            },
+        }]), ImportSource::Auto);
+
+        let history = &report.conversations[0];
+        assert_eq!(history.title.as_deref(), Some("Cats"));
+        assert_eq!(history.created_at, Some(1700000000));
+        assert_eq!(history.current_model, "gpt-4");
+        // The unmappable node is reported, and its reply kept in its place.
+        assert_eq!(issues(&report), ["conversation 1, node img: role `tool` has no equivalent"]);
+        assert_eq!(path(history), [(Role::User, "Draw a cat"), (Role::Assistant, "Here is a cat.")]);
+        let siblings: Vec<&str> = history.siblings(history.active_leaf.unwrap()).iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(siblings, ["I cannot draw.", "Here is a cat."]);
+        assert_eq!(history.active_path()[0].timestamp, Some(1700000100));
+    }
+
+    #[test]
+    fn reports_chatgpt_nodes_it_cannot_convert() {
+        let report = import(json!([
+            { "mapping": {
+                "q": { "parent": null, "message": { "author": { "role": "user" }, "content": { "content_type": "text", "parts": ["hi", { "asset": 1 }] } }, "children": ["c"] },
+                "c": { "parent": "q", "message": { "author": { "role": "assistant" }, "content": { "content_type": "code", "text": "x" } } },
+                "lost": { "parent": "gone", "message": { "author": { "role": "user" }, "content": { "content_type": "text", "parts": ["?"] } } },
+            } },
+            { "title": "broken" },
+        ]), ImportSource::ChatGpt);
+
+        assert_eq!(path(&report.conversations[0]), [(Role::User, "hi")]);
+        assert_eq!(issues(&report), [
+            "conversation 1, node q: non-text content part",
+            "conversation 1, node c: unsupported content type `code`",
+            "conversation 1, node lost: not linked into the conversation tree",
+            "conversation 2: no `mapping` object",
+        ]);
+    }
+
+    #[test]
+    fn imports_llamacpp_exports() {
+        let report = import(json!({
+            "conv": { "name": "Greeting", "currNode": 3 },
+            "messages": [
+                { "id": 1, "type": "root", "role": "system", "content": "" },
+                { "id": 2, "parent": 1, "role": "user", "content": "hi", "timestamp": 1700000000123u64 },
+                { "id": 3, "parent": 2, "role": "assistant", "content": "hello", "model": "qwen" },
+                { "id": 4, "parent": 2, "role": "assistant", "content": "hey" },
+                { "id": 5, "parent": 2, "role": "system", "content": "note" },
+                { "id": 6, "parent": 2, "role": "user" },
+            ],
+        }), ImportSource::Auto);
+
+        let history = &report.conversations[0];
+        assert_eq!(history.title.as_deref(), Some("Greeting"));
+        assert_eq!(path(history), [(Role::User, "hi"), (Role::Assistant, "hello")]);
+        assert_eq!(history.active_path()[0].timestamp, Some(1700000000));
+        assert_eq!(history.created_at, Some(1700000000));
+        assert_eq!(history.active_path()[1].model.as_deref(), Some("qwen"));
+        assert_eq!(history.messages.len(), 3);
+        assert_eq!(issues(&report), [
+            "conversation 1, message 5: role `system` has no equivalent",
+            "conversation 1, message 6: message has no text content",
+        ]);
+    }
+
+    #[test]
+    fn refuses_input_it_cannot_read() {
+        let error = |content: &str, source| format!("{:#}", parse(content, source, "default").unwrap_err());
+        assert_eq!(error(r#"{"title": "what"}"#, ImportSource::Auto), "Could not detect the input format, pass --format");
+        assert_eq!(error(r#"{"mapping": {}}"#, ImportSource::ChatGpt), "Expected an array of conversations");
+        assert!(error("{ not json", ImportSource::ChatGpt).starts_with("Input is not valid JSON"));
+
+        let report = import(json!({ "conv": {} }), ImportSource::LlamaCpp);
+        assert_eq!(issues(&report), ["conversation 1: expected `conv` and `messages`"]);
+        let report = import(json!({ "messages": "none" }), ImportSource::OpenAi);
+        assert_eq!(issues(&report), ["conversation 1: no `messages` array"]);
+        assert!(report.conversations.is_empty());
+    }
+}
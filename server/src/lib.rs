@@ -0,0 +1,1166 @@
+//! Chat server: WebSocket protocol, HTTP APIs and model backends.
+//!
+//! The binary wires these up from `models.json` and the `conversations`
+//! directory; tests build the same app with [`start`] and [`router`].
+
+mod api;
+mod child;
+pub mod cli;
+pub mod config;
+mod connection;
+pub mod export;
+mod gateway;
+mod gguf;
+mod mcp;
+pub mod import;
+mod models;
+mod process;
+mod openai;
+mod resources;
+mod backend;
+mod budget;
+mod cassette;
+mod discovery;
+mod search;
+mod stats;
+mod telemetry;
+pub mod store;
+mod titles;
+mod tools;
+mod web;
+
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use shared::{Capability, ChatHistory, ClientMessage, GenerationStats, MessageId, ModelDescriptor, ProcessUsage, Role, ServerMessage, ToolCall};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use config::AppConfig;
+use connection::Connection;
+use models::ModelCatalog;
+use process::ProcessManager;
+use openai::{OAIClient, Message as OAIMessage, StreamEvent, ToolSpec};
+use futures::StreamExt;
+use search::SearchIndex;
+use stats::StatsRecorder;
+use telemetry::GaugeGuard;
+use store::ConversationStore;
+use tokio::sync::broadcast;
+use tools::ToolRegistry;
+
+const EXPORTS_DIR: &str = "exports";
+/// Large models can take minutes to load.
+const MODEL_LOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+/// How often the llama-server process is sampled.
+const RESOURCE_SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+pub struct AppState {
+    /// The conversation this server is currently chatting in, shared by
+    /// all clients: those that open another one switch everyone over.
+    history: Mutex<ChatHistory>,
+    store: ConversationStore,
+    search: Mutex<SearchIndex>,
+    config: AppConfig,
+    /// Models of `models.json` and the models directories.
+    models: RwLock<ModelCatalog>,
+    /// Tools offered to models that can call them.
+    tools: ToolRegistry,
+    process_manager: tokio::sync::Mutex<ProcessManager>,
+    /// Notifications pushed to every connected client.
+    events: broadcast::Sender<ServerMessage>,
+    /// Conversations whose title is currently being generated.
+    titling: Mutex<HashSet<String>>,
+    metrics: metrics_exporter_prometheus::PrometheusHandle,
+    /// Model and process id of the running llama-server, if any. Kept
+    /// outside the process manager, which is locked while models load.
+    monitored: Mutex<Option<(String, u32)>>,
+    /// Latest resource sample of that process.
+    usage: Mutex<Option<ProcessUsage>>,
+}
+
+/// Builds the shared state: continues the most recent conversation of
+/// `store`, indexes all conversations and starts the default model.
+pub async fn start(config: AppConfig, store: ConversationStore) -> Arc<AppState> {
+    // Load history: continue the most recent conversation
+    let history = match store.latest() {
+        Ok(Some(history)) => history,
+        Ok(None) => store.create(&config.default),
+        Err(e) => {
+            tracing::error!("Failed to list conversations: {}", e);
+            store.create(&config.default)
+        }
+    };
+
+    let search = match store.list() {
+        Ok(conversations) => SearchIndex::build(&conversations),
+        Err(e) => {
+            tracing::error!("Failed to index conversations: {}", e);
+            SearchIndex::default()
+        }
+    };
+
+    // Initialize ProcessManager
+    let mut process_manager = ProcessManager::new(config.record.as_ref().map(PathBuf::from))
+        .with_memory_check(config.memory_check);
+    
+    // Start default model
+    let mut catalog = load_catalog(&config).await;
+    let mut started = false;
+    if let Some(model_config) = catalog.get(&config.default).cloned() {
+        let loaded = process_manager.load(&config.default, &model_config).await;
+        if let Err(e) = &loaded {
+            tracing::warn!("Failed to start default model: {}. Switch to another model, e.g. a mock one.", e);
+        }
+        started = matches!(loaded, Ok(true));
+        let outcome = loaded.map(|_| ()).map_err(|e| format!("{:#}", e));
+        catalog.set_loaded(&config.default, outcome, process_manager.current_model());
+    } else {
+        tracing::warn!("Default model '{}' not found in config.", config.default);
+    }
+
+    let state = Arc::new(AppState {
+        history: Mutex::new(history),
+        store,
+        search: Mutex::new(search),
+        tools: ToolRegistry::new(&config.tools).await,
+        config,
+        models: RwLock::new(catalog),
+        monitored: Mutex::new(monitored(&process_manager)),
+        process_manager: tokio::sync::Mutex::new(process_manager),
+        events: broadcast::channel(64).0,
+        titling: Mutex::new(HashSet::new()),
+        metrics: telemetry::install(),
+        usage: Mutex::new(None),
+    });
+    if started {
+        spawn_default_model_check(Arc::downgrade(&state));
+    }
+    spawn_resource_sampler(Arc::downgrade(&state));
+    if !state.config.models_dirs.is_empty() {
+        spawn_model_watcher(Arc::downgrade(&state));
+    }
+    state
+}
+
+/// Waits for the default model started by [`start`] to become ready and
+/// verifies it, as [`switch_model`] does, without holding up startup. The
+/// process manager is locked only for each probe, so chats and switches
+/// are not held up either. A model that fails is marked broken in every
+/// client's model list.
+fn spawn_default_model_check(state: std::sync::Weak<AppState>) {
+    tokio::spawn(async move {
+        let deadline = tokio::time::Instant::now() + MODEL_LOAD_TIMEOUT;
+        loop {
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            let name = state.config.default.clone();
+            let mut pm = state.process_manager.lock().await;
+            // A client may have switched models meanwhile.
+            if pm.current_model() != Some(name.as_str()) {
+                return;
+            }
+            let result = match pm.probe().await {
+                Some(result) => result,
+                None if tokio::time::Instant::now() >= deadline => {
+                    Err(anyhow::anyhow!("The backend did not become ready within {:?}", MODEL_LOAD_TIMEOUT))
+                }
+                None => {
+                    drop(pm);
+                    drop(state);
+                    tokio::time::sleep(process::READY_POLL_INTERVAL).await;
+                    continue;
+                }
+            };
+            pm.finish_loading(&result);
+            let Err(e) = result else {
+                return;
+            };
+            tracing::warn!("Default model {} is not usable: {:#}", name, e);
+            *state.monitored.lock().unwrap() = monitored(&pm);
+            state.models.write().unwrap().set_loaded(&name, Err(format!("{:#}", e)), pm.current_model());
+            drop(pm);
+            let _ = state.events.send(ServerMessage::AvailableModels(available_models(&state)));
+            return;
+        }
+    });
+}
+
+/// Reads the GGUF headers of the `models.json` entries and scans the
+/// models directories.
+async fn load_catalog(config: &AppConfig) -> ModelCatalog {
+    let configured = config.models.clone();
+    let dirs = config.models_dirs.clone();
+    // Tokenizer vocabularies make headers megabytes long.
+    let load = tokio::task::spawn_blocking(move || {
+        let mut catalog = ModelCatalog::new(configured);
+        if !dirs.is_empty() {
+            catalog.set_discovered(discovery::scan(&dirs));
+        }
+        catalog
+    });
+    load.await.unwrap_or_default()
+}
+
+/// All models with their metadata, sorted by name.
+fn available_models(state: &AppState) -> Vec<ModelDescriptor> {
+    state.models.read().unwrap().descriptors()
+}
+
+/// Scans the models directories again and updates the catalog.
+///
+/// Returns whether the model list changed.
+async fn rescan_models(state: &AppState) -> bool {
+    let dirs = state.config.models_dirs.clone();
+    let found = match tokio::task::spawn_blocking(move || discovery::scan(&dirs)).await {
+        Ok(found) => found,
+        Err(e) => {
+            tracing::error!("Model scan failed: {}", e);
+            return false;
+        }
+    };
+    let count = found.len();
+    let changed = state.models.write().unwrap().set_discovered(found);
+    tracing::info!("Found {} models in the models directories{}", count, if changed { ", list changed" } else { "" });
+    changed
+}
+
+/// Rescans the models directories whenever their content changes and
+/// announces new model lists to every client.
+fn spawn_model_watcher(state: std::sync::Weak<AppState>) {
+    let Some(dirs) = state.upgrade().map(|state| state.config.models_dirs.clone()) else {
+        return;
+    };
+    let (changes, mut changed) = tokio::sync::mpsc::channel(1);
+    let watcher = match discovery::watch(&dirs, changes) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!("Models directories are not watched: {}. Use a rescan instead.", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        let _watcher = watcher;
+        while changed.recv().await.is_some() {
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            if rescan_models(&state).await {
+                let _ = state.events.send(ServerMessage::AvailableModels(available_models(&state)));
+            }
+        }
+    });
+}
+
+/// The model and process to sample after a load.
+fn monitored(pm: &ProcessManager) -> Option<(String, u32)> {
+    Some((pm.current_model()?.to_string(), pm.pid()?))
+}
+
+/// Samples the llama-server process every few seconds, exporting the
+/// result as metrics and pushing it to every client. Stops with the server.
+fn spawn_resource_sampler(state: std::sync::Weak<AppState>) {
+    tokio::spawn(async move {
+        let mut sampler = resources::Sampler::default();
+        let mut interval = tokio::time::interval(RESOURCE_SAMPLE_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            if let Some(available) = resources::available_memory() {
+                metrics::gauge!(telemetry::MEMORY_AVAILABLE).set(available as f64);
+            }
+            let target = state.monitored.lock().unwrap().clone();
+            let usage = target.and_then(|(model, pid)| sampler.sample(&model, pid));
+            let previous = std::mem::replace(&mut *state.usage.lock().unwrap(), usage.clone());
+            if let Some(previous) = previous.filter(|p| usage.as_ref().map(|u| &u.model) != Some(&p.model)) {
+                telemetry::forget_process(&previous.model);
+            }
+            if let Some(usage) = usage {
+                telemetry::record_process(&usage);
+                let _ = state.events.send(ServerMessage::ResourceUsage(usage));
+            }
+        }
+    });
+}
+
+/// All routes of the server: `/ws`, the OpenAI-compatible and REST APIs,
+/// `/metrics` and the web UI.
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/ws", get(ws_handler))
+        .merge(gateway::routes())
+        .merge(api::routes())
+        .merge(telemetry::routes())
+        .merge(web::routes())
+        .with_state(state)
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    ws.on_upgrade(|socket| handle_socket(socket, state))
+}
+
+async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
+    let Some((mut conn, first)) = Connection::accept(socket, state.config.legacy_protocol).await else {
+        return;
+    };
+    let _connected = GaugeGuard::new(telemetry::WS_CONNECTIONS);
+
+    // Send existing history
+    {
+        let history = state.history.lock().unwrap().clone();
+        if !conn.send(&ServerMessage::History(history)).await {
+            return;
+        }
+    }
+
+    // Send available models
+    if !conn.send(&ServerMessage::AvailableModels(available_models(&state))).await {
+        return;
+    }
+    conn.set_request(None);
+
+    let mut events = state.events.subscribe();
+    let mut pending = Pending::default();
+    if let Some(first) = first {
+        pending.push(first);
+    }
+    loop {
+        // Events first, so that those a request caused, like the `History`
+        // of a conversation it opened, arrive before the next request runs.
+        while let Ok(event) = events.try_recv() {
+            if !conn.send(&event).await {
+                return;
+            }
+        }
+        if let Some(text) = pending.pop() {
+            if !handle_message(&mut conn, &state, &mut pending, text).await {
+                return;
+            }
+            continue;
+        }
+
+        let msg = tokio::select! {
+            msg = conn.recv() => match msg {
+                Some(Ok(msg)) => msg,
+                _ => break,
+            },
+            event = events.recv() => {
+                if let Ok(event) = event {
+                    if !conn.send(&event).await {
+                        return;
+                    }
+                }
+                continue;
+            }
+        };
+
+        if let WsMessage::Text(text) = msg {
+            if !handle_message(&mut conn, &state, &mut pending, text).await {
+                return;
+            }
+        }
+    }
+}
+
+/// Handles one text frame from a client.
+///
+/// Returns `false` once the socket is closed.
+async fn handle_message(
+    conn: &mut Connection,
+    state: &Arc<AppState>,
+    pending: &mut Pending,
+    text: String,
+) -> bool {
+    tracing::debug!("received: {}", text);
+
+    let (id, client_msg) = match conn.parse(&text) {
+        Ok(request) => request,
+        Err(e) => return conn.send(&ServerMessage::Error(e)).await,
+    };
+    metrics::counter!(telemetry::WS_REQUESTS, "type" => telemetry::request_type(&client_msg)).increment(1);
+    conn.set_request(id);
+    let open = dispatch(conn, state, pending, client_msg).await;
+    conn.set_request(None);
+    open
+}
+
+/// Handles one request, replying on `conn`.
+///
+/// Returns `false` once the socket is closed.
+async fn dispatch(
+    conn: &mut Connection,
+    state: &Arc<AppState>,
+    pending: &mut Pending,
+    client_msg: ClientMessage,
+) -> bool {
+    match client_msg {
+        ClientMessage::Hello { .. } => {
+            let err = "Unexpected Hello: the handshake is already done".to_string();
+            if !conn.send(&ServerMessage::Error(err)).await {
+                return false;
+            }
+        }
+        ClientMessage::SetModel(model_name) => {
+            tracing::info!("Switching model to: {}", model_name);
+
+            match switch_model(state, &model_name, false).await {
+                Ok(restarted) => {
+                    tracing::info!("Model switched successfully to {}", model_name);
+                    {
+                        let mut history = state.history.lock().unwrap();
+                        history.current_model = model_name.clone();
+                        save_history(state, &mut history);
+                    }
+
+                    // A restart is announced to every client already.
+                    if !restarted && !conn.send(&ServerMessage::ModelChanged(model_name)).await {
+                        return false;
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Failed to switch model: {}", e);
+                    let err = format!("Failed to switch to {}: {}", model_name, e);
+                    if !conn.send(&ServerMessage::Error(err)).await {
+                        return false;
+                    }
+                }
+            }
+        }
+        ClientMessage::Text(content) => {
+            // User Message
+            let target = {
+                let mut history = state.history.lock().unwrap();
+                history.push(Role::User, content);
+                save_history(state, &mut history);
+                ReplyTarget::of(&history)
+            };
+
+            if !generate_reply(conn, state, pending, target).await {
+                return false;
+            }
+        }
+        ClientMessage::Regenerate => {
+            // Step back to the prompt so the new reply becomes a sibling
+            // of the current one instead of replacing it.
+            let prompt = {
+                let mut history = state.history.lock().unwrap();
+                if let Some(leaf) = history.active_leaf.and_then(|id| history.get(id)).cloned() {
+                    if matches!(leaf.role, Role::Assistant) {
+                        history.active_leaf = leaf.parent;
+                    }
+                }
+                history.active_leaf.map(|_| history.clone())
+            };
+
+            // Send the rewound tree first so clients can mirror the new branch.
+            let ok = if let Some(history) = prompt {
+                let target = ReplyTarget::of(&history);
+                conn.send(&ServerMessage::History(history)).await
+                    && generate_reply(conn, state, pending, target).await
+            } else {
+                conn.send(&ServerMessage::Error("Nothing to regenerate".to_string())).await
+            };
+            if !ok {
+                return false;
+            }
+        }
+        ClientMessage::Edit { id, content } => {
+            let edited = {
+                let mut history = state.history.lock().unwrap();
+                match history.get(id).cloned() {
+                    Some(original) if matches!(original.role, Role::User) => {
+                        history.add_child(original.parent, Role::User, content);
+                        save_history(state, &mut history);
+                        Some(history.clone())
+                    }
+                    _ => None,
+                }
+            };
+
+            let ok = if let Some(history) = edited {
+                let target = ReplyTarget::of(&history);
+                conn.send(&ServerMessage::History(history)).await
+                    && generate_reply(conn, state, pending, target).await
+            } else {
+                let err = format!("Message {} is not a user message", id);
+                conn.send(&ServerMessage::Error(err)).await
+            };
+            if !ok {
+                return false;
+            }
+        }
+        ClientMessage::ListBranches(id) => {
+            let siblings: Vec<_> = {
+                let history = state.history.lock().unwrap();
+                history.siblings(id).into_iter().cloned().collect()
+            };
+            if !conn.send(&ServerMessage::Branches { id, siblings }).await {
+                return false;
+            }
+        }
+        ClientMessage::Export { format, all, save } => {
+            let reply = match export_for_client(state, format, all, save) {
+                Ok(reply) => reply,
+                Err(e) => ServerMessage::Error(format!("Export failed: {}", e)),
+            };
+            if !conn.send(&reply).await {
+                return false;
+            }
+        }
+        ClientMessage::Search(query) => {
+            let hits = state.search.lock().unwrap().search(&query);
+            if !conn.send(&ServerMessage::SearchResults(hits)).await {
+                return false;
+            }
+        }
+        // Clients share the open conversation, so every one of them is
+        // sent the one opened.
+        ClientMessage::OpenConversation(id) => match state.store.load(&id) {
+            Ok(conversation) => {
+                *state.history.lock().unwrap() = conversation.clone();
+                let _ = state.events.send(ServerMessage::History(conversation));
+            }
+            Err(e) => {
+                let err = format!("Failed to open conversation: {}", e);
+                if !conn.send(&ServerMessage::Error(err)).await {
+                    return false;
+                }
+            }
+        },
+        ClientMessage::NewConversation => {
+            let history = {
+                let mut history = state.history.lock().unwrap();
+                let model = history.current_model.clone();
+                *history = state.store.create(&model);
+                history.clone()
+            };
+            let _ = state.events.send(ServerMessage::History(history));
+        }
+        ClientMessage::ListConversations => {
+            let reply = match state.store.list() {
+                Ok(conversations) => {
+                    ServerMessage::Conversations(conversations.iter().map(ChatHistory::info).collect())
+                }
+                Err(e) => ServerMessage::Error(format!("Failed to list conversations: {}", e)),
+            };
+            if !conn.send(&reply).await {
+                return false;
+            }
+        }
+        ClientMessage::SetConversationInfo { id, title, summary } => {
+            let updated = update_conversation(state, &id, |history| {
+                if title.is_none() && summary.is_none() {
+                    return false;
+                }
+                history.title = title.or(history.title.take());
+                history.summary = summary.or(history.summary.take());
+                history.user_titled = true;
+                true
+            });
+            match updated {
+                Ok(Some(info)) => {
+                    let _ = state.events.send(ServerMessage::ConversationUpdated(info));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    let err = format!("Failed to update conversation: {}", e);
+                    if !conn.send(&ServerMessage::Error(err)).await {
+                        return false;
+                    }
+                }
+            }
+        }
+        // Only meaningful while a reply is streaming; see `generate_reply`.
+        ClientMessage::Stop => {}
+        ClientMessage::ApproveTool { call_id, .. } => {
+            let err = format!("Tool call {} is not waiting for approval", call_id);
+            if !conn.send(&ServerMessage::Error(err)).await {
+                return false;
+            }
+        }
+        ClientMessage::SetAutoApprove(enabled) => {
+            let history = {
+                let mut history = state.history.lock().unwrap();
+                history.auto_approve = enabled;
+                save_history(state, &mut history);
+                history.clone()
+            };
+            if !conn.send(&ServerMessage::History(history)).await {
+                return false;
+            }
+        }
+        ClientMessage::RescanModels => {
+            rescan_models(state).await;
+            let _ = state.events.send(ServerMessage::AvailableModels(available_models(state)));
+        }
+        ClientMessage::SwitchBranch(id) => {
+            let reply = {
+                let mut history = state.history.lock().unwrap();
+                if history.switch_to(id) {
+                    save_history(state, &mut history);
+                    ServerMessage::History(history.clone())
+                } else {
+                    ServerMessage::Error(format!("Unknown message id {}", id))
+                }
+            };
+            if !conn.send(&reply).await {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Messages received while a reply was streaming, handled afterwards.
+#[derive(Default)]
+struct Pending(VecDeque<String>);
+
+impl Pending {
+    fn push(&mut self, text: String) {
+        metrics::gauge!(telemetry::WS_QUEUED_MESSAGES).increment(1.0);
+        self.0.push_back(text);
+    }
+
+    fn pop(&mut self) -> Option<String> {
+        let text = self.0.pop_front()?;
+        metrics::gauge!(telemetry::WS_QUEUED_MESSAGES).decrement(1.0);
+        Some(text)
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        metrics::gauge!(telemetry::WS_QUEUED_MESSAGES).decrement(self.0.len() as f64);
+    }
+}
+
+/// Loads model `name` from the config unless it is already running and
+/// announces the change to every client. With `wait`, waits until it is
+/// ready and verified first, as the HTTP APIs answer only then.
+///
+/// Returns whether llama-server was restarted.
+async fn switch_model(state: &AppState, name: &str, wait: bool) -> anyhow::Result<bool> {
+    let model = state
+        .models
+        .read()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("Model '{}' not found in config", name))?;
+    state.models.write().unwrap().set_loading(name);
+    let mut pm = state.process_manager.lock().await;
+    let result = match pm.load(name, &model).await {
+        Ok(true) if wait => pm.wait_ready(MODEL_LOAD_TIMEOUT).await.map(|_| true),
+        other => other,
+    };
+    // Even a failed load may have stopped the previous model.
+    *state.monitored.lock().unwrap() = monitored(&pm);
+    let outcome = result.as_ref().map(|_| ()).map_err(|e| format!("{:#}", e));
+    state.models.write().unwrap().set_loaded(name, outcome, pm.current_model());
+    match &result {
+        Ok(true) => {
+            let _ = state.events.send(ServerMessage::ModelChanged(name.to_string()));
+        }
+        Ok(false) => {}
+        // Every client's model list should show it as broken.
+        Err(_) => {
+            let _ = state.events.send(ServerMessage::AvailableModels(available_models(state)));
+        }
+    }
+    result
+}
+
+/// Streams a reply to `target` and appends it to its conversation, even
+/// if another client opens a different one meanwhile.
+///
+/// If the model asks to call tools, the reply is saved as an assistant
+/// message with those calls, the tools are run and their results appended
+/// as tool messages, and the model is asked again, until it answers in text.
+/// Calls that need approval wait for the client's `ApproveTool`.
+///
+/// The socket is read while streaming: `Stop` ends the reply early, other
+/// messages are queued in `pending` until it is complete.
+///
+/// Returns `false` once the socket is closed.
+async fn generate_reply(conn: &mut Connection, state: &Arc<AppState>, pending: &mut Pending, mut target: ReplyTarget) -> bool {
+    let (client, model) = {
+        let pm = state.process_manager.lock().await;
+        (pm.client(), pm.current_model().unwrap_or_default().to_string())
+    };
+    let (window, tools) = {
+        let models = state.models.read().unwrap();
+        let window = models.get(&model).and_then(|config| budget::context_window(config, models.metadata(&model)));
+        let tools = if models.capabilities(&model).contains(&Capability::Tools) {
+            state.tools.specs()
+        } else {
+            Vec::new()
+        };
+        (window, tools)
+    };
+
+    let mut round = 0;
+    loop {
+        // Without tools on the last round, the model has to answer.
+        let offered = if round < state.config.tools.max_rounds { tools.clone() } else { Vec::new() };
+        let tools_offered = !offered.is_empty();
+        let messages = match target.path(state) {
+            Ok(path) => context(&path, &model, window),
+            Err(e) => {
+                let err = format!("Failed to read the conversation: {:#}", e);
+                return conn.send(&ServerMessage::Error(err)).await && conn.send(&ServerMessage::EndOfMessage).await;
+            }
+        };
+        let step = stream_step(conn, &client, messages, offered, pending).await;
+        telemetry::record_generation(&model, step.outcome, step.stats.as_ref());
+        // Calls of a reply cut short are incomplete, and unasked-for ones cannot run.
+        let mut calls = if step.outcome == "complete" && tools_offered { step.tool_calls } else { Vec::new() };
+
+        // Save the assistant message. Clients append it to the active
+        // branch on `EndOfMessage` or `ToolCalls`, which yields the same id
+        // as here.
+        let saved = target.append(state, Role::Assistant, step.content, |message| {
+            message.stats = step.stats.clone();
+            // Some backends leave out ids; results must still be matched to
+            // calls, across rounds too.
+            for (i, call) in calls.iter_mut().enumerate() {
+                if call.id.is_empty() {
+                    call.id = format!("call_{}_{}", message.id, i);
+                }
+            }
+            message.tool_calls = calls.clone();
+        });
+        let snapshot = match saved {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                let err = format!("Failed to save the reply: {:#}", e);
+                return conn.send(&ServerMessage::Error(err)).await && conn.send(&ServerMessage::EndOfMessage).await;
+            }
+        };
+
+        if let Some(stats) = step.stats {
+            tracing::info!("Reply generated: {}", stats.summary());
+            if !conn.send(&ServerMessage::Stats(stats)).await {
+                return false;
+            }
+        }
+        if calls.is_empty() {
+            if state.config.titles.enabled && titles::needs_title(&snapshot) {
+                spawn_title_generation(state.clone(), snapshot);
+            }
+            return conn.send(&ServerMessage::EndOfMessage).await;
+        }
+
+        if !conn.send(&ServerMessage::ToolCalls(calls.clone())).await {
+            answer_left(state, &mut target, calls);
+            return false;
+        }
+        // Every call gets a result, even once the user stopped the reply or
+        // left: the model would not accept the conversation otherwise.
+        let mut stopped = false;
+        let mut calls = calls.into_iter();
+        while let Some(call) = calls.next() {
+            let auto_approve = snapshot.auto_approve;
+            let approved = !state.tools.needs_approval(&call, auto_approve);
+            let decision = if stopped {
+                Decision::Stopped
+            } else if approved {
+                Decision::Allowed
+            } else {
+                ask_approval(conn, &call, pending).await
+            };
+            let content = match decision {
+                Decision::Allowed => {
+                    tracing::info!("Calling tool {}", call.summary());
+                    state.tools.call(&call).await
+                }
+                Decision::Refused => state.tools.refused(&call, "The user refused this call"),
+                Decision::Stopped => {
+                    stopped = true;
+                    state.tools.refused(&call, "The user stopped the reply before this call ran")
+                }
+                Decision::Closed => {
+                    answer_left(state, &mut target, std::iter::once(call).chain(calls));
+                    return false;
+                }
+            };
+            if let Err(e) = target.append_tool_result(state, &call.id, content.clone()) {
+                tracing::error!("Failed to save the result of {}: {:#}", call.summary(), e);
+            }
+            if !conn.send(&ServerMessage::ToolResult { call_id: call.id, content }).await {
+                answer_left(state, &mut target, calls);
+                return false;
+            }
+        }
+        if stopped {
+            // Ends the reply like a stream stopped before its first token.
+            if let Err(e) = target.append(state, Role::Assistant, String::new(), |_| {}) {
+                tracing::error!("Failed to save the stopped reply: {:#}", e);
+            }
+            return conn.send(&ServerMessage::EndOfMessage).await;
+        }
+        round += 1;
+    }
+}
+
+/// Refuses the `calls` left when the client's socket closed, so the
+/// conversation stays one the model accepts. Nobody is left to ask.
+fn answer_left(state: &AppState, target: &mut ReplyTarget, calls: impl IntoIterator<Item = ToolCall>) {
+    let results: Vec<_> = calls
+        .into_iter()
+        .map(|call| (call.id.clone(), state.tools.refused(&call, "The user left before this call ran")))
+        .collect();
+    let saved = target.change(state, |history, mut leaf| {
+        for (call_id, content) in results {
+            let id = history.add_child(leaf, Role::Tool, content);
+            if let Some(message) = history.get_mut(id) {
+                message.tool_call_id = Some(call_id);
+            }
+            leaf = Some(id);
+        }
+        leaf
+    });
+    if let Err(e) = saved {
+        tracing::error!("Failed to save the refused calls: {:#}", e);
+    }
+}
+
+/// Where a reply goes: the conversation it was asked in, after the message
+/// it answers. The conversation open for all clients may change while the
+/// reply streams; the reply stays where it started.
+struct ReplyTarget {
+    conversation: String,
+    /// The message the next one of the reply follows.
+    leaf: Option<MessageId>,
+}
+
+impl ReplyTarget {
+    /// A reply to the active branch of `history`.
+    fn of(history: &ChatHistory) -> Self {
+        Self { conversation: history.id.clone(), leaf: history.active_leaf }
+    }
+
+    /// The messages the reply continues, root first.
+    fn path(&self, state: &AppState) -> anyhow::Result<Vec<shared::Message>> {
+        let read = |history: &ChatHistory| match self.leaf {
+            Some(leaf) => history.path_to(leaf).into_iter().cloned().collect(),
+            None => Vec::new(),
+        };
+        let active = state.history.lock().unwrap();
+        if active.id == self.conversation {
+            return Ok(read(&active));
+        }
+        drop(active);
+        Ok(read(&state.store.load(&self.conversation)?))
+    }
+
+    /// Appends a message to the reply, set up by `fill`, and returns the
+    /// saved conversation.
+    fn append(
+        &mut self,
+        state: &AppState,
+        role: Role,
+        content: String,
+        fill: impl FnOnce(&mut shared::Message),
+    ) -> anyhow::Result<ChatHistory> {
+        let mut snapshot = None;
+        self.change(state, |history, leaf| {
+            let id = history.add_child(leaf, role, content);
+            if let Some(message) = history.get_mut(id) {
+                fill(message);
+            }
+            snapshot = Some(history.clone());
+            Some(id)
+        })?;
+        snapshot.ok_or_else(|| anyhow::anyhow!("Nothing was appended"))
+    }
+
+    fn append_tool_result(&mut self, state: &AppState, call_id: &str, content: String) -> anyhow::Result<ChatHistory> {
+        self.append(state, Role::Tool, content, |message| message.tool_call_id = Some(call_id.to_string()))
+    }
+
+    /// Applies `change` to the reply's conversation, whether it is open or
+    /// only stored, and saves it. `change` gets the current leaf and returns
+    /// the new one.
+    fn change(
+        &mut self,
+        state: &AppState,
+        change: impl FnOnce(&mut ChatHistory, Option<MessageId>) -> Option<MessageId>,
+    ) -> anyhow::Result<()> {
+        // Held throughout, so the conversation cannot be opened between
+        // loading and saving it.
+        let mut active = state.history.lock().unwrap();
+        if active.id == self.conversation {
+            self.leaf = change(&mut active, self.leaf);
+            save_history(state, &mut active);
+            return Ok(());
+        }
+        let mut history = state.store.load(&self.conversation)?;
+        self.leaf = change(&mut history, self.leaf);
+        save_history(state, &mut history);
+        Ok(())
+    }
+}
+
+/// The user's answer to a tool call that needs approval.
+enum Decision {
+    Allowed,
+    Refused,
+    /// `Stop`: refused, and the reply ends.
+    Stopped,
+    /// The socket closed while waiting.
+    Closed,
+}
+
+/// Asks the client to approve `call` and waits for the answer. Other
+/// requests are queued in `pending` meanwhile.
+async fn ask_approval(conn: &mut Connection, call: &ToolCall, pending: &mut Pending) -> Decision {
+    if !conn.send(&ServerMessage::ToolApproval(call.clone())).await {
+        return Decision::Closed;
+    }
+    loop {
+        match conn.recv().await {
+            Some(Ok(WsMessage::Text(text))) => match conn.parse(&text) {
+                Ok((_, ClientMessage::ApproveTool { call_id, approved })) if call_id == call.id => {
+                    tracing::info!("Tool call {} {}", call.summary(), if approved { "approved" } else { "refused" });
+                    return if approved { Decision::Allowed } else { Decision::Refused };
+                }
+                Ok((_, ClientMessage::Stop)) => return Decision::Stopped,
+                _ => pending.push(text),
+            },
+            Some(Ok(_)) => {}
+            _ => return Decision::Closed,
+        }
+    }
+}
+
+/// One request to the model and what it streamed back.
+struct Step {
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    stats: Option<GenerationStats>,
+    /// How the stream ended, for metrics: `complete`, `stopped`,
+    /// `disconnected` or `error`.
+    outcome: &'static str,
+}
+
+/// The branch a reply continues, as sent to the model: the path from the
+/// root only, as other branches are never shown to it, fitted into the
+/// context `window`.
+fn context(path: &[shared::Message], model: &str, window: Option<u64>) -> Vec<OAIMessage> {
+    let mut messages: Vec<OAIMessage> = path.iter().map(OAIMessage::from).collect();
+    if let Some(window) = window {
+        let dropped = budget::fit(&mut messages, window);
+        if dropped > 0 {
+            tracing::info!("Left out the {} oldest messages to fit the {} token context of {}", dropped, window, model);
+        }
+    }
+    messages
+}
+
+/// Sends `messages` to the model, offering `tools`, and streams its reply
+/// to `conn`.
+async fn stream_step(
+    conn: &mut Connection,
+    client: &anyhow::Result<OAIClient>,
+    messages: Vec<OAIMessage>,
+    tools: Vec<ToolSpec>,
+    pending: &mut Pending,
+) -> Step {
+    let mut step = Step {
+        content: String::new(),
+        tool_calls: Vec::new(),
+        stats: None,
+        outcome: "complete",
+    };
+
+    let mut recorder = StatsRecorder::start();
+    let stream = match client {
+        Ok(client) => client.chat_stream(messages, tools).await,
+        Err(e) => Err(anyhow::anyhow!("{:#}", e)),
+    };
+    match stream {
+        Ok(mut stream) => {
+            loop {
+                tokio::select! {
+                    result = stream.next() => match result {
+                        Some(Ok(event)) => {
+                            recorder.record(&event);
+                            match event {
+                                StreamEvent::Token(token) => {
+                                    step.content.push_str(&token);
+                                    if !conn.send(&ServerMessage::Token(token)).await {
+                                        break;
+                                    }
+                                }
+                                StreamEvent::ToolCalls(calls) => step.tool_calls.extend(calls),
+                                StreamEvent::Usage(_) | StreamEvent::Timings(_) => {}
+                            }
+                        }
+                        Some(Err(e)) => {
+                            step.outcome = "error";
+                            conn.send(&ServerMessage::Error(e.to_string())).await;
+                        }
+                        None => break,
+                    },
+                    msg = conn.recv() => match msg {
+                        Some(Ok(WsMessage::Text(text))) => {
+                            if matches!(conn.parse(&text), Ok((_, ClientMessage::Stop))) {
+                                // Dropping the stream cancels the request to the backend.
+                                tracing::info!("Reply stopped by client");
+                                step.outcome = "stopped";
+                                break;
+                            }
+                            pending.push(text);
+                        }
+                        Some(Ok(_)) => {}
+                        // Closed: keep what was generated so far.
+                        _ => {
+                            step.outcome = "disconnected";
+                            break;
+                        }
+                    },
+                }
+            }
+            step.stats = Some(recorder.finish());
+        }
+        Err(e) => {
+            step.outcome = "error";
+            let err = format!("Failed to reach the model backend: {}. Is it running?", e);
+            conn.send(&ServerMessage::Error(err)).await;
+        }
+    }
+    step
+}
+
+/// Client for title generation: the configured title model if it can be
+/// reached without switching, the loaded model otherwise.
+async fn title_client(state: &AppState) -> anyhow::Result<OAIClient> {
+    let title_model = state
+        .config
+        .titles
+        .model
+        .as_ref()
+        .and_then(|name| state.models.read().unwrap().get(name).map(|config| (name, config.clone())));
+    match title_model {
+        Some((name, config)) if config.backend_kind().is_remote() => {
+            let record_dir = state.config.record.as_ref().map(PathBuf::from);
+            Ok(backend::create(name, &config)?.client().with_recording(record_dir))
+        }
+        _ => state.process_manager.lock().await.client(),
+    }
+}
+
+/// Generates a title and summary in the background and stores them.
+fn spawn_title_generation(state: Arc<AppState>, snapshot: ChatHistory) {
+    if !state.titling.lock().unwrap().insert(snapshot.id.clone()) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let result = match title_client(&state).await {
+            Ok(client) => titles::generate(&client, &snapshot).await,
+            Err(e) => Err(e),
+        };
+        state.titling.lock().unwrap().remove(&snapshot.id);
+
+        let (title, summary) = match result {
+            Ok(generated) => generated,
+            Err(e) => {
+                tracing::warn!("Title generation for {} failed: {}", snapshot.id, e);
+                return;
+            }
+        };
+        let updated = update_conversation(&state, &snapshot.id, |history| {
+            // The user may have set their own title in the meantime.
+            if history.user_titled || history.title.is_some() {
+                return false;
+            }
+            history.title = Some(title);
+            history.summary = Some(summary);
+            true
+        });
+        match updated {
+            Ok(Some(info)) => {
+                let _ = state.events.send(ServerMessage::ConversationUpdated(info));
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!("Failed to store title for {}: {}", snapshot.id, e),
+        }
+    });
+}
+
+/// Applies `change` to a conversation, whether it is the active one or only
+/// stored, and saves it if `change` returns `true`.
+fn update_conversation(
+    state: &AppState,
+    id: &str,
+    change: impl FnOnce(&mut ChatHistory) -> bool,
+) -> anyhow::Result<Option<shared::ConversationInfo>> {
+    let mut active = state.history.lock().unwrap();
+    if active.id == id {
+        if !change(&mut active) {
+            return Ok(None);
+        }
+        save_history(state, &mut active);
+        return Ok(Some(active.info()));
+    }
+    drop(active);
+
+    let mut history = state.store.load(id)?;
+    if !change(&mut history) {
+        return Ok(None);
+    }
+    save_history(state, &mut history);
+    Ok(Some(history.info()))
+}
+
+fn export_for_client(
+    state: &AppState,
+    format: shared::ExportFormat,
+    all: bool,
+    save: bool,
+) -> anyhow::Result<ServerMessage> {
+    let (name, conversations) = if all {
+        ("all".to_string(), state.store.list()?)
+    } else {
+        let history = state.history.lock().unwrap().clone();
+        (history.id.clone(), vec![history])
+    };
+    let content = export::render(&conversations, format)?;
+
+    if save {
+        let path = export::write_to_dir(Path::new(EXPORTS_DIR), &name, format, &content)?;
+        let path = std::fs::canonicalize(&path).unwrap_or(path);
+        Ok(ServerMessage::Exported {
+            format,
+            path: Some(path.display().to_string()),
+            content: None,
+        })
+    } else {
+        Ok(ServerMessage::Exported {
+            format,
+            path: None,
+            content: Some(content),
+        })
+    }
+}
+
+fn save_history(state: &AppState, history: &mut ChatHistory) {
+    if let Err(e) = state.store.save(history) {
+        tracing::error!("Failed to save history: {}", e);
+    }
+    state.search.lock().unwrap().update(history);
+}
@@ -0,0 +1,331 @@
+//! Client for Model Context Protocol servers over stdio.
+//!
+//! Each configured server is a child process reading JSON-RPC requests, one
+//! per line, on its stdin and answering on its stdout. Like the managed
+//! llama-server, it runs as a [`ChildProcess`]: started, made ready (here by
+//! the `initialize` handshake), checked for having exited before use and
+//! started again, and killed with this server.
+
+use std::collections::{HashMap, HashSet};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{ChildStdin, Command};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use crate::child::ChildProcess;
+use crate::config::{Approval, McpServerConfig};
+use crate::telemetry;
+
+/// Protocol revision asked for in the handshake.
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// JSON-RPC "method not found", for requests from the server this client
+/// does not support.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Pages of `tools/list` read at most, against servers that never stop
+/// handing out cursors.
+const MAX_TOOL_PAGES: usize = 100;
+
+/// Requests waiting for their response, by id.
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>>;
+
+/// A tool as listed by a server.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToolInfo {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(rename = "inputSchema", default = "empty_schema")]
+    pub input_schema: Value,
+}
+
+fn empty_schema() -> Value {
+    json!({ "type": "object", "properties": {} })
+}
+
+/// A configured server, started on first use and again after it exits.
+pub struct McpServer {
+    name: String,
+    config: McpServerConfig,
+    session: tokio::sync::Mutex<Option<Session>>,
+}
+
+impl McpServer {
+    pub fn new(name: &str, config: &McpServerConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            config: config.clone(),
+            session: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Approval of calls of the server's tools.
+    pub fn approval(&self) -> Approval {
+        self.config.approval
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.config.timeout_secs.max(1))
+    }
+
+    /// Every tool the server offers, or those of the first
+    /// [`MAX_TOOL_PAGES`] pages. Listing stops early if a cursor comes back.
+    pub async fn list_tools(&self) -> Result<Vec<ToolInfo>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        let mut seen = HashSet::new();
+        for _ in 0..MAX_TOOL_PAGES {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let page = self.request("tools/list", params).await?;
+            let listed: Vec<ToolInfo> = serde_json::from_value(page["tools"].clone())
+                .with_context(|| format!("The MCP server {} listed its tools in an unknown format", self.name))?;
+            tools.extend(listed);
+            match page["nextCursor"].as_str() {
+                Some(next) if !seen.insert(next.to_string()) => {
+                    tracing::warn!("MCP server {} handed out the cursor {} again; using the tools listed so far", self.name, next);
+                    return Ok(tools);
+                }
+                Some(next) => cursor = Some(next.to_string()),
+                None => return Ok(tools),
+            }
+        }
+        tracing::warn!("MCP server {} listed more than {} pages of tools; using the first ones", self.name, MAX_TOOL_PAGES);
+        Ok(tools)
+    }
+
+    /// Calls tool `name` and returns the text of its result. Results the
+    /// server marks as errors are returned as errors.
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<String> {
+        let result = self.request("tools/call", json!({ "name": name, "arguments": arguments })).await?;
+        let text = content_text(&result["content"]);
+        if result["isError"].as_bool() == Some(true) {
+            bail!("{}", text);
+        }
+        Ok(text)
+    }
+
+    /// Sends a request, starting the server first unless it is running,
+    /// and waits for the response.
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let (id, response, pending) = {
+            let mut guard = self.session.lock().await;
+            let mut session = match guard.take() {
+                Some(mut session) => {
+                    if session.is_running() {
+                        session
+                    } else {
+                        tracing::warn!("MCP server {} exited; starting it again", self.name);
+                        session.stop().await;
+                        self.start().await?
+                    }
+                }
+                None => self.start().await?,
+            };
+            let sent = session.send(method, params).await;
+            let pending = session.pending.clone();
+            *guard = Some(session);
+            let (id, response) = sent?;
+            (id, response, pending)
+        };
+        self.wait(id, response, &pending).await
+    }
+
+    /// Starts the process and shakes hands with it.
+    async fn start(&self) -> Result<Session> {
+        metrics::counter!(telemetry::MCP_SERVER_STARTS, "server" => self.name.clone()).increment(1);
+        let result = async {
+            let mut session = Session::spawn(&self.name, &self.config)?;
+            let params = json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {},
+                "clientInfo": { "name": "llamacpp-chat", "version": env!("CARGO_PKG_VERSION") },
+            });
+            let (id, response) = session.send("initialize", params).await?;
+            let info = self.wait(id, response, &session.pending.clone()).await?;
+            session.notify("notifications/initialized").await?;
+            tracing::info!(
+                "MCP server {} ready: {} {}",
+                self.name,
+                info["serverInfo"]["name"].as_str().unwrap_or("unnamed"),
+                info["serverInfo"]["version"].as_str().unwrap_or_default()
+            );
+            Ok(session)
+        }
+        .await;
+        if result.is_err() {
+            metrics::counter!(telemetry::MCP_SERVER_FAILURES, "server" => self.name.clone()).increment(1);
+        }
+        result
+    }
+
+    async fn wait(&self, id: u64, response: oneshot::Receiver<Result<Value, String>>, pending: &Pending) -> Result<Value> {
+        match tokio::time::timeout(self.timeout(), response).await {
+            Ok(Ok(result)) => result.map_err(|e| anyhow!("{}", e)),
+            Ok(Err(_)) => Err(anyhow!("The MCP server {} exited before answering", self.name)),
+            Err(_) => {
+                pending.lock().unwrap().remove(&id);
+                Err(anyhow!("The MCP server {} did not answer within {} s", self.name, self.timeout().as_secs()))
+            }
+        }
+    }
+}
+
+/// One run of a server process.
+struct Session {
+    child: ChildProcess,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Pending,
+    next_id: u64,
+    /// Readers of stdout, then stderr.
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Session {
+    fn spawn(name: &str, config: &McpServerConfig) -> Result<Self> {
+        tracing::info!("Starting MCP server {}: {} {:?}", name, config.command, config.args);
+        let mut cmd = Command::new(&config.command);
+        cmd.args(&config.args).envs(&config.env);
+        if let Some(cwd) = &config.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = ChildProcess::spawn(&format!("the MCP server {}", name), cmd)?;
+
+        let (Some(stdin), Some(stdout), Some(stderr)) = child.take_stdio() else {
+            bail!("The MCP server {} has no stdio", name);
+        };
+        let stdin = Arc::new(tokio::sync::Mutex::new(stdin));
+        let pending = Pending::default();
+        let tasks = vec![
+            tokio::spawn(read_messages(name.to_string(), stdout, stdin.clone(), pending.clone())),
+            tokio::spawn(log_stderr(name.to_string(), stderr)),
+        ];
+        Ok(Self { child, stdin, pending, next_id: 1, tasks })
+    }
+
+    /// Whether the process still runs and talks: its stdout closes as it
+    /// exits, possibly before it can be waited for.
+    fn is_running(&mut self) -> bool {
+        self.child.is_running() && !self.tasks[0].is_finished()
+    }
+
+    /// Sends a request; the response arrives on the returned receiver.
+    async fn send(&mut self, method: &str, params: Value) -> Result<(u64, oneshot::Receiver<Result<Value, String>>)> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if let Err(e) = write_message(&self.stdin, &request).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        Ok((id, receiver))
+    }
+
+    async fn notify(&mut self, method: &str) -> Result<()> {
+        write_message(&self.stdin, &json!({ "jsonrpc": "2.0", "method": method })).await
+    }
+
+    async fn stop(&mut self) {
+        let _ = self.child.stop().await;
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+async fn write_message(stdin: &tokio::sync::Mutex<ChildStdin>, message: &Value) -> Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    let mut stdin = stdin.lock().await;
+    stdin.write_all(line.as_bytes()).await.context("The MCP server stopped reading")?;
+    stdin.flush().await.context("The MCP server stopped reading")?;
+    Ok(())
+}
+
+/// Hands responses to their requests and answers requests of the server,
+/// until it closes stdout. Dropping the pending senders then fails the
+/// requests still waiting.
+async fn read_messages(name: String, stdout: impl AsyncRead + Unpin, stdin: Arc<tokio::sync::Mutex<ChildStdin>>, pending: Pending) {
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            tracing::debug!("MCP server {} wrote a line that is not JSON: {}", name, line);
+            continue;
+        };
+        match (message.get("id"), message["method"].as_str()) {
+            (Some(id), Some(method)) => {
+                // Only pings are supported; sampling and the like are refused.
+                let reply = if method == "ping" {
+                    json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+                } else {
+                    let error = json!({ "code": METHOD_NOT_FOUND, "message": format!("Method not found: {}", method) });
+                    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+                };
+                if write_message(&stdin, &reply).await.is_err() {
+                    break;
+                }
+            }
+            (Some(id), None) => {
+                let Some(sender) = id.as_u64().and_then(|id| pending.lock().unwrap().remove(&id)) else {
+                    continue;
+                };
+                let result = match message.get("error") {
+                    Some(error) => Err(error["message"].as_str().unwrap_or("Unknown error").to_string()),
+                    None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                };
+                let _ = sender.send(result);
+            }
+            (None, Some(method)) => tracing::debug!("MCP server {} sent {}", name, method),
+            (None, None) => {}
+        }
+    }
+    pending.lock().unwrap().clear();
+}
+
+async fn log_stderr(name: String, stderr: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        tracing::debug!("MCP server {}: {}", name, line);
+    }
+}
+
+/// The text of a tool result's content items. Other content is only
+/// mentioned, as models here read text.
+fn content_text(content: &Value) -> String {
+    let Some(items) = content.as_array() else {
+        return String::new();
+    };
+    items
+        .iter()
+        .map(|item| match item["type"].as_str() {
+            Some("text") => item["text"].as_str().unwrap_or_default().to_string(),
+            Some("resource") => match item["resource"]["text"].as_str() {
+                Some(text) => text.to_string(),
+                None => format!("[Resource {} left out]", item["resource"]["uri"].as_str().unwrap_or("without uri")),
+            },
+            Some(kind) => format!("[{} content left out]", kind),
+            None => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,208 @@
+//! The models this server offers: the entries of `models.json`, plus those
+//! discovered in its `models_dirs`. Entries of `models.json` take
+//! precedence, by name and by file.
+//!
+//! Besides the configuration, the catalog tracks which model is loading,
+//! loaded or failed to load, for the descriptors sent to clients.
+
+use std::collections::HashMap;
+use std::path::Path;
+use shared::{Capability, GgufMetadata, ModelDescriptor, ModelState};
+use crate::budget;
+use crate::config::{BackendKind, ModelConfig};
+use crate::discovery::{self, Discovered};
+
+struct Entry {
+    config: ModelConfig,
+    /// Header of the GGUF file, if readable.
+    metadata: Option<GgufMetadata>,
+    /// Size of the GGUF file, all shards together.
+    file_size: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct ModelCatalog {
+    configured: HashMap<String, Entry>,
+    discovered: HashMap<String, Entry>,
+    loading: Option<String>,
+    loaded: Option<String>,
+    /// Errors of the last failed load, by model name.
+    failures: HashMap<String, String>,
+}
+
+impl ModelCatalog {
+    /// A catalog of the `models.json` entries, reading the GGUF header of
+    /// every entry with a `path`. Blocks on file reads.
+    pub fn new(configured: HashMap<String, ModelConfig>) -> Self {
+        let configured = configured
+            .into_iter()
+            .map(|(name, config)| {
+                let read = config.path.as_ref().map(|path| discovery::read_model(Path::new(path)));
+                let (metadata, file_size) = match read {
+                    Some(Ok((metadata, size))) => (Some(metadata), Some(size)),
+                    Some(Err(e)) => {
+                        tracing::warn!("No metadata for model '{}': {:#}", name, e);
+                        (None, None)
+                    }
+                    None => (None, None),
+                };
+                (name, Entry { config, metadata, file_size })
+            })
+            .collect();
+        Self { configured, ..Self::default() }
+    }
+
+    fn entry(&self, name: &str) -> Option<&Entry> {
+        self.configured.get(name).or_else(|| self.discovered.get(name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ModelConfig> {
+        self.entry(name).map(|entry| &entry.config)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entry(name).is_some()
+    }
+
+    pub fn metadata(&self, name: &str) -> Option<&GgufMetadata> {
+        self.entry(name)?.metadata.as_ref()
+    }
+
+    /// What model `name` can do: as configured, else guessed.
+    pub fn capabilities(&self, name: &str) -> Vec<Capability> {
+        self.entry(name).map(entry_capabilities).unwrap_or_default()
+    }
+
+    /// Whether `name` was found in a models directory.
+    pub fn is_discovered(&self, name: &str) -> bool {
+        !self.configured.contains_key(name) && self.discovered.contains_key(name)
+    }
+
+    /// All models, sorted by name.
+    pub fn iter(&self) -> Vec<(&String, &ModelConfig)> {
+        self.entries().into_iter().map(|(name, entry)| (name, &entry.config)).collect()
+    }
+
+    fn entries(&self) -> Vec<(&String, &Entry)> {
+        let mut entries: Vec<_> = self
+            .configured
+            .iter()
+            .chain(self.discovered.iter().filter(|(name, _)| !self.configured.contains_key(*name)))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    /// The model list sent to clients, sorted by name.
+    pub fn descriptors(&self) -> Vec<ModelDescriptor> {
+        self.entries().into_iter().map(|(name, entry)| self.describe(name, entry)).collect()
+    }
+
+    fn describe(&self, name: &str, entry: &Entry) -> ModelDescriptor {
+        let config = &entry.config;
+        let metadata = entry.metadata.as_ref();
+        let error = self.failures.get(name).cloned();
+        let state = if self.loading.as_deref() == Some(name) {
+            ModelState::Loading
+        } else if self.loaded.as_deref() == Some(name) {
+            ModelState::Loaded
+        } else if error.is_some() {
+            ModelState::Failed
+        } else {
+            ModelState::Available
+        };
+        ModelDescriptor {
+            name: name.to_string(),
+            display_name: config
+                .display_name
+                .clone()
+                .or_else(|| metadata.and_then(|m| m.name.clone()))
+                .unwrap_or_else(|| name.to_string()),
+            description: config.description.clone(),
+            tags: config.tags.clone(),
+            backend: config.backend_kind().as_str().to_string(),
+            capabilities: entry_capabilities(entry),
+            context_size: budget::context_window(config, metadata),
+            file_size: entry.file_size,
+            state,
+            error: error.filter(|_| state == ModelState::Failed),
+            metadata: metadata.cloned(),
+        }
+    }
+
+    /// Marks `name` as being loaded.
+    pub fn set_loading(&mut self, name: &str) {
+        self.loading = Some(name.to_string());
+    }
+
+    /// Records the outcome of loading `name`; `current` is the model now
+    /// running, which after a failure may be none.
+    pub fn set_loaded(&mut self, name: &str, result: Result<(), String>, current: Option<&str>) {
+        self.loading = None;
+        match result {
+            Ok(()) => {
+                self.failures.remove(name);
+            }
+            Err(e) => {
+                self.failures.insert(name.to_string(), e);
+            }
+        }
+        // A model that never became ready may still be the current one.
+        self.loaded = current.filter(|current| !self.failures.contains_key(*current)).map(str::to_string);
+    }
+
+    /// Replaces the discovered models with the result of a new scan, leaving
+    /// out files that `models.json` entries already point at.
+    ///
+    /// Returns whether the model list changed.
+    pub fn set_discovered(&mut self, found: Vec<Discovered>) -> bool {
+        let before = self.descriptors();
+        let configured_files: Vec<_> = self
+            .configured
+            .values()
+            .filter_map(|entry| canonical(entry.config.path.as_deref()?))
+            .collect();
+
+        self.discovered.clear();
+        for model in found {
+            if self.configured.contains_key(&model.name) {
+                continue;
+            }
+            let file = model.config.path.as_deref().and_then(canonical);
+            if file.is_some_and(|file| configured_files.contains(&file)) {
+                continue;
+            }
+            let entry = Entry { config: model.config, metadata: Some(model.metadata), file_size: Some(model.file_size) };
+            self.discovered.insert(model.name, entry);
+        }
+        self.descriptors() != before
+    }
+}
+
+fn entry_capabilities(entry: &Entry) -> Vec<Capability> {
+    let config = &entry.config;
+    config.capabilities.clone().unwrap_or_else(|| capabilities(config, entry.metadata.as_ref()))
+}
+
+/// What a model can do, guessed from its chat template and arguments.
+fn capabilities(config: &ModelConfig, metadata: Option<&GgufMetadata>) -> Vec<Capability> {
+    let has_arg = |names: &[&str]| config.args.iter().any(|arg| names.contains(&arg.as_str()));
+    let mut capabilities = Vec::new();
+    if has_arg(&["--mmproj", "-mm", "--mmproj-url", "-mmu"]) {
+        capabilities.push(Capability::Vision);
+    }
+    // Templates that render tool definitions mention them. A managed
+    // llama-server only renders them, and accepts tools, with `--jinja`.
+    let renders_tools = metadata.and_then(|m| m.chat_template.as_deref()).is_some_and(|t| t.contains("tools"));
+    if renders_tools && (config.backend_kind() != BackendKind::Managed || has_arg(&["--jinja"])) {
+        capabilities.push(Capability::Tools);
+    }
+    if metadata.is_some_and(|m| m.embedding) || has_arg(&["--embedding", "--embeddings"]) {
+        capabilities.push(Capability::Embeddings);
+    }
+    capabilities
+}
+
+fn canonical(path: &str) -> Option<std::path::PathBuf> {
+    Path::new(path).canonicalize().ok()
+}
@@ -2,40 +2,203 @@ use anyhow::{Result, Context};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::pin::Pin;
 use futures::Stream;
+use serde_json::Value;
+use shared::ToolCall;
+use crate::cassette::Recording;
+use crate::telemetry::{self, GaugeGuard};
 
 #[derive(Serialize, Debug)]
 pub struct ChatRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
     pub messages: Vec<Message>,
     pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    /// Tools the model may call; llama-server needs `--jinja` for them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolSpec>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StreamOptions {
+    /// Ask for a final event with token counts.
+    pub include_usage: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message {
     pub role: String,
     pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<WireToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Message {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+}
+
+/// A message of the conversation as the model is sent it.
+impl From<&shared::Message> for Message {
+    fn from(message: &shared::Message) -> Self {
+        let role = match message.role {
+            shared::Role::User => "user",
+            shared::Role::Assistant => "assistant",
+            shared::Role::Tool => "tool",
+        };
+        Self {
+            role: role.to_string(),
+            content: message.content.clone(),
+            tool_calls: message.tool_calls.iter().map(WireToolCall::from).collect(),
+            tool_call_id: message.tool_call_id.clone(),
+        }
+    }
+}
+
+/// A tool offered to the model: a function with a JSON schema for its
+/// arguments.
+#[derive(Serialize, Debug, Clone)]
+pub struct ToolSpec {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub function: FunctionSpec,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FunctionSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+impl ToolSpec {
+    pub fn function(name: &str, description: &str, parameters: Value) -> Self {
+        Self {
+            kind: "function",
+            function: FunctionSpec {
+                name: name.to_string(),
+                description: description.to_string(),
+                parameters,
+            },
+        }
+    }
+}
+
+/// A tool call as it appears in assistant messages of the API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WireToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+impl From<&ToolCall> for WireToolCall {
+    fn from(call: &ToolCall) -> Self {
+        Self {
+            id: call.id.clone(),
+            kind: "function".to_string(),
+            function: FunctionCall {
+                name: call.name.clone(),
+                arguments: call.arguments.clone(),
+            },
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 struct ChatCompletionChunk {
+    #[serde(default)]
     choices: Vec<Choice>,
+    usage: Option<Usage>,
+    /// llama-server's own statistics, sent with the last event.
+    timings: Option<Timings>,
+}
+
+/// Token counts of a completion, as reported by the backend.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+}
+
+/// llama-server's timings of a completion.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct Timings {
+    pub prompt_n: Option<u64>,
+    pub prompt_per_second: Option<f64>,
+    pub predicted_n: Option<u64>,
+    pub predicted_per_second: Option<f64>,
+}
+
+/// What a streamed chat completion yields.
+#[derive(Debug)]
+pub enum StreamEvent {
+    Token(String),
+    /// The model asks to call these tools, complete once the choice has
+    /// finished.
+    ToolCalls(Vec<ToolCall>),
+    /// Token counts, usually with the last event.
+    Usage(Usage),
+    Timings(Timings),
 }
 
 #[derive(Deserialize, Debug)]
 struct Choice {
+    #[serde(default)]
     delta: Delta,
     finish_reason: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 struct Delta {
     content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+/// A piece of a streamed tool call: the first carries its id and name, the
+/// following ones parts of the arguments.
+#[derive(Deserialize, Debug)]
+struct ToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
 }
 
 pub struct OAIClient {
     client: Client,
     base_url: String,
+    model: Option<String>,
+    api_key: Option<String>,
+    /// Directory streamed exchanges are recorded to as cassettes.
+    record_dir: Option<PathBuf>,
 }
 
 impl OAIClient {
@@ -43,72 +206,256 @@ impl OAIClient {
         Self {
             client: Client::new(),
             base_url: base_url.to_string(),
+            model: None,
+            api_key: None,
+            record_dir: None,
         }
     }
 
+    /// Sets the `model` field sent with each request.
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    /// Sends `key` as a bearer token with each request.
+    pub fn with_api_key(mut self, key: &str) -> Self {
+        self.api_key = Some(key.to_string());
+        self
+    }
+
+    /// Records every streamed exchange to a cassette in `dir`.
+    pub fn with_recording(mut self, dir: Option<PathBuf>) -> Self {
+        self.record_dir = dir;
+        self
+    }
+
+    fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        let request = self.client.post(url);
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+
+    /// Runs a chat completion and returns the whole reply at once.
+    pub async fn complete(&self, messages: Vec<Message>) -> Result<String> {
+        let mut stream = self.chat_stream(messages, Vec::new()).await?;
+        let mut reply = String::new();
+        while let Some(event) = stream.next().await {
+            if let StreamEvent::Token(token) = event? {
+                reply.push_str(&token);
+            }
+        }
+        Ok(reply)
+    }
+
+    /// Streams a chat completion, offering the model `tools`.
     pub async fn chat_stream(
         &self,
         messages: Vec<Message>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        tools: Vec<ToolSpec>,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>> {
         let url = format!("{}/v1/chat/completions", self.base_url);
         let request = ChatRequest {
+            model: self.model.clone(),
             messages,
             stream: true,
+            stream_options: Some(StreamOptions { include_usage: true }),
+            tools,
         };
 
-        let res = self.client
-            .post(&url)
-            .json(&request)
-            .send()
-            .await
-            .context("Failed to send request to llama-server")?;
+        let mut recording = self.record_dir.as_ref().map(|dir| {
+            Recording::start(dir, &self.base_url, serde_json::to_value(&request).unwrap_or_default())
+        });
+
+        let res = self.post(&url).json(&request).send().await;
+        count_request(&res);
+        let res = res.context("Failed to send request to the model backend")?;
+        if let Some(recording) = recording.as_mut() {
+            recording.status(res.status().as_u16());
+        }
 
         if !res.status().is_success() {
              let text = res.text().await.unwrap_or_default();
+             if let Some(recording) = recording.as_mut() {
+                 recording.chunk(text.as_bytes());
+             }
              return Err(anyhow::anyhow!("API Error: {}", text));
         }
 
-        let stream = res.bytes_stream().map(|item| {
-            match item {
-                Ok(bytes) => {
-                    let s = String::from_utf8_lossy(&bytes);
-                    Ok(s.to_string())
+        // The recording lives as long as the stream and is written when it
+        // is dropped; so does the in-flight count.
+        let mut parser = SseParser::default();
+        let in_flight = GaugeGuard::new(telemetry::BACKEND_IN_FLIGHT);
+        let stream = res
+            .bytes_stream()
+            .map(move |item| {
+                let _in_flight = &in_flight;
+                let events = match item {
+                    Ok(bytes) => {
+                        if let Some(recording) = recording.as_mut() {
+                            recording.chunk(&bytes);
+                        }
+                        let tokens = parser.feed(&bytes);
+                        // Chunks holding no complete event yield nothing.
+                        let token = (!tokens.is_empty()).then_some(StreamEvent::Token(tokens));
+                        let tool_calls = parser.tool_calls.take().map(StreamEvent::ToolCalls);
+                        let usage = parser.usage.take().map(StreamEvent::Usage);
+                        let timings = parser.timings.take().map(StreamEvent::Timings);
+                        token.into_iter().chain(tool_calls).chain(usage).chain(timings).map(Ok).collect()
+                    }
+                    Err(e) => vec![Err(anyhow::anyhow!(e))],
+                };
+                futures::stream::iter(events)
+            })
+            .flatten();
+
+        Ok(Box::pin(stream))
+    }
+
+    /// Sends a raw chat completion request body and returns the response
+    /// untouched, for proxying. The body's `model` is replaced by the one set
+    /// with [`with_model`](Self::with_model), if any.
+    pub async fn forward(&self, body: &serde_json::Value) -> Result<reqwest::Response> {
+        let url = format!("{}/v1/chat/completions", self.base_url);
+        let mut body = body.clone();
+        if let (Some(model), Some(object)) = (&self.model, body.as_object_mut()) {
+            object.insert("model".to_string(), serde_json::Value::String(model.clone()));
+        }
+        let res = self.post(&url).json(&body).send().await;
+        count_request(&res);
+        res.context("Failed to send request to the model backend")
+    }
+}
+
+fn count_request(res: &reqwest::Result<reqwest::Response>) {
+    let status = match res {
+        Ok(res) => res.status().as_u16().to_string(),
+        Err(_) => "error".to_string(),
+    };
+    metrics::counter!(telemetry::BACKEND_REQUESTS, "status" => status).increment(1);
+}
+
+/// Incremental parser for the SSE stream of a chat completion. Chunks may
+/// split events, lines and even UTF-8 sequences at any byte.
+#[derive(Default)]
+pub struct SseParser {
+    /// Bytes of the line not terminated yet.
+    pending: Vec<u8>,
+    /// Tool calls being streamed, by index. A map, as the indices come
+    /// from the backend and may be anything.
+    calls: BTreeMap<usize, ToolCall>,
+    /// Tool calls of the finished choice, until taken.
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Token counts from the last event carrying them, until taken.
+    pub usage: Option<Usage>,
+    pub timings: Option<Timings>,
+}
+
+impl SseParser {
+    /// Feeds the next chunk and returns the content deltas of the events it
+    /// completes.
+    pub fn feed(&mut self, chunk: &[u8]) -> String {
+        self.pending.extend_from_slice(chunk);
+        let mut tokens = String::new();
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim_end().strip_prefix("data:") else {
+                continue;
+            };
+            let data = data.trim_start();
+            if data == "[DONE]" {
+                self.finish_tool_calls();
+                continue;
+            }
+            if let Ok(json) = serde_json::from_str::<ChatCompletionChunk>(data) {
+                if let Some(choice) = json.choices.into_iter().next() {
+                    if let Some(content) = &choice.delta.content {
+                        tokens.push_str(content);
+                    }
+                    for delta in choice.delta.tool_calls {
+                        self.add_tool_call_delta(delta);
+                    }
+                    if choice.finish_reason.is_some() {
+                        self.finish_tool_calls();
+                    }
                 }
-                Err(e) => Err(anyhow::anyhow!(e)),
+                self.usage = json.usage.or(self.usage);
+                self.timings = json.timings.or(self.timings);
             }
-        });
+        }
+        tokens
+    }
 
-        // This is a naive line/chunk parser. 
-        // Real SSE parsing is slightly more complex (data: ...).
-        // For robustness, we should use a proper SSE parser or just strip prefixes.
-        // Let's implement a simple transformer here.
-        
-        let sse_stream = stream.map(|chunk_res| {
-             match chunk_res {
-                 Ok(chunk) => {
-                     // The chunk might contain multiple "data: {...}\n\n" lines
-                     let mut tokens = String::new();
-                     for line in chunk.lines() {
-                         if line.starts_with("data: ") {
-                             let data = &line[6..];
-                             if data == "[DONE]" {
-                                 continue;
-                             }
-                             if let Ok(json) = serde_json::from_str::<ChatCompletionChunk>(data) {
-                                  if let Some(choice) = json.choices.first() {
-                                      if let Some(content) = &choice.delta.content {
-                                          tokens.push_str(content);
-                                      }
-                                  }
-                             }
-                         }
-                     }
-                     Ok(tokens)
-                 }
-                 Err(e) => Err(e),
-             }
+    fn add_tool_call_delta(&mut self, delta: ToolCallDelta) {
+        let call = self.calls.entry(delta.index).or_insert_with(|| ToolCall {
+            id: String::new(),
+            name: String::new(),
+            arguments: String::new(),
         });
+        if let Some(id) = delta.id {
+            call.id = id;
+        }
+        if let Some(function) = delta.function {
+            if let Some(name) = function.name {
+                call.name.push_str(&name);
+            }
+            if let Some(arguments) = function.arguments {
+                call.arguments.push_str(&arguments);
+            }
+        }
+    }
+
+    /// Ends the calls being streamed. Calls some backends leave without an
+    /// id keep an empty one, for the caller to name.
+    fn finish_tool_calls(&mut self) {
+        if self.calls.is_empty() {
+            return;
+        }
+        self.tool_calls = Some(std::mem::take(&mut self.calls).into_values().collect());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(delta: serde_json::Value, finish_reason: Option<&str>) -> String {
+        format!("data: {}\n\n", serde_json::json!({ "choices": [{ "delta": delta, "finish_reason": finish_reason }] }))
+    }
+
+    #[test]
+    fn joins_tokens_split_anywhere() {
+        let mut parser = SseParser::default();
+        let first = event(serde_json::json!({ "content": "Grüß" }), None);
+        let stream = first + &event(serde_json::json!({ "content": " dich" }), Some("stop"));
+        let bytes = stream.as_bytes();
+        // Splits "ü" between its two bytes.
+        let split = stream.find('ü').unwrap() + 1;
+        let mut text = parser.feed(&bytes[..split]);
+        text.push_str(&parser.feed(&bytes[split..]));
+        assert_eq!(text, "Grüß dich");
+        assert!(parser.tool_calls.is_none());
+    }
+
+    #[test]
+    fn assembles_tool_calls_by_index() {
+        let mut parser = SseParser::default();
+        let call = |index: u64, id: Option<&str>, name: Option<&str>, arguments: &str| {
+            event(serde_json::json!({ "tool_calls": [{ "index": index, "id": id, "function": { "name": name, "arguments": arguments } }] }), None)
+        };
+        // A huge index allocates nothing more than a small one.
+        parser.feed(call(4_000_000_000, None, Some("read_file"), r#"{"path":"#).as_bytes());
+        parser.feed(call(0, Some("abc"), Some("current_time"), "{}").as_bytes());
+        parser.feed(call(4_000_000_000, None, None, r#""a.txt"}"#).as_bytes());
+        assert!(parser.tool_calls.is_none(), "finished only with the choice");
+        parser.feed(b"data: [DONE]\n\n");
 
-        Ok(Box::pin(sse_stream))
+        let calls = parser.tool_calls.take().unwrap();
+        let calls: Vec<_> = calls.iter().map(|c| (c.id.as_str(), c.name.as_str(), c.arguments.as_str())).collect();
+        // The caller names calls without an id.
+        assert_eq!(calls, [("abc", "current_time", "{}"), ("", "read_file", r#"{"path":"a.txt"}"#)]);
     }
 }
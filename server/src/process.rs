@@ -1,71 +1,151 @@
-use std::process::Stdio;
-use tokio::process::{Child, Command};
-use anyhow::{Result, Context};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use crate::backend::{self, Backend};
+use crate::config::{BackendKind, ModelConfig};
+use crate::openai::OAIClient;
+use crate::resources;
+use crate::telemetry;
 
+/// Pause between health checks of a loading backend.
+pub const READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Owns the backend of the active model and switches between them.
 pub struct ProcessManager {
-    child: Option<Child>,
+    backend: Option<Box<dyn Backend>>,
+    /// Name of the model the backend was started for.
+    model: Option<String>,
+    /// Where clients record cassettes, if recording is on.
+    record_dir: Option<PathBuf>,
+    /// When the backend was started, until it is ready.
+    loading_since: Option<Instant>,
+    /// Check that managed models fit in memory before starting them.
+    memory_check: bool,
 }
 
 impl ProcessManager {
-    pub fn new() -> Self {
-        Self { child: None }
+    pub fn new(record_dir: Option<PathBuf>) -> Self {
+        Self { backend: None, model: None, record_dir, loading_since: None, memory_check: false }
     }
 
-    pub fn start(&mut self, _model_path: &str, args: &[String]) -> Result<()> {
-        // In a real scenario, we might use model_path as the -m argument if not provided in args,
-        // or ensure it's passed correctly. For now, we assume args contains everything needed or we append it.
-        // However, looking at the config, 'path' is separate. Let's construct the command properly.
-        
-        tracing::info!("Starting llama-server with args: {:?}", args);
+    /// Refuses to load managed models that are unlikely to fit in memory.
+    pub fn with_memory_check(mut self, enabled: bool) -> Self {
+        self.memory_check = enabled;
+        self
+    }
 
-        let mut cmd = Command::new("llama-server");
-        
-        // If we wanted to enforce the model path here:
-        // cmd.arg("-m").arg(model_path);
-        // But the user might have put "-m" in args. 
-        // For simplicity in this iteration, let's assume 'args' from config is comprehensive 
-        // OR we prepend the model path if it's not in args.
-        // Let's stick to the plan: pass args directly. 
-        // NOTE: The implementation plan implies using model_path designated in config.
-        
-        // Let's actually append -m model_path if it's not present, or assume the caller handles it.
-        // For safer implementation, let's look at how we'll call it.
-        // We will pass `config.path` and `config.args`. 
-        // `llama-server -m <path> <args>` is the standard way.
-        
-        cmd.arg("-m").arg(_model_path);
-        cmd.args(args);
+    /// Name of the model currently running, if any.
+    pub fn current_model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
 
-        // Standard ports configuration could also be enforced here if needed, 
-        // but let's assume args or default logic.
-        // Adding --port 8080 explicitly if not in args could be good, but let's trust the config/defaults.
-        // We'll add a default port if not specified? 
-        // Actually, let's stick to hardcoded 8080 for the server code's expectation for now, 
-        // or just let it be.
-        
-        cmd.arg("--port").arg("8080");
+    /// The active backend, if any.
+    pub fn backend(&self) -> Option<&dyn Backend> {
+        self.backend.as_deref()
+    }
 
-        cmd.stdout(Stdio::null()); // or piped for logging
-        cmd.stderr(Stdio::null());
+    /// Id of the backend process, if this server spawned one.
+    pub fn pid(&self) -> Option<u32> {
+        self.backend.as_ref().and_then(|backend| backend.pid())
+    }
 
-        let child = cmd.spawn().context("Failed to spawn llama-server")?;
-        self.child = Some(child);
-        
-        Ok(())
+    /// Client for the active backend.
+    pub fn client(&self) -> Result<OAIClient> {
+        self.backend
+            .as_ref()
+            .map(|backend| backend.client().with_recording(self.record_dir.clone()))
+            .ok_or_else(|| anyhow!("No model is loaded"))
     }
 
-    pub async fn stop(&mut self) -> Result<()> {
-        if let Some(mut child) = self.child.take() {
-            tracing::info!("Stopping llama-server process...");
-            child.kill().await.context("Failed to kill llama-server process")?;
-            child.wait().await.context("Failed to wait for llama-server process termination")?;
+    /// Makes sure `name` is the running model, replacing the backend only
+    /// if another model (or none) is running. Returns whether it restarted.
+    pub async fn load(&mut self, name: &str, model: &ModelConfig) -> Result<bool> {
+        if self.model.as_deref() == Some(name) && self.is_running() {
+            return Ok(false);
         }
-        Ok(())
+        // Checked before stopping the current model, which keeps running if
+        // the new one is refused.
+        if self.memory_check && model.backend_kind() == BackendKind::Managed {
+            if let Err(e) = resources::check_fits(name, model) {
+                metrics::counter!(telemetry::MODEL_LOAD_FAILURES, "model" => name.to_string()).increment(1);
+                return Err(e);
+            }
+        }
+        self.stop().await?;
+        metrics::counter!(telemetry::BACKEND_RESTARTS, "model" => name.to_string()).increment(1);
+        let started = Instant::now();
+        let mut backend = backend::create(name, model)?;
+        if let Err(e) = backend.start().await {
+            metrics::counter!(telemetry::MODEL_LOAD_FAILURES, "model" => name.to_string()).increment(1);
+            return Err(e);
+        }
+        self.backend = Some(backend);
+        self.model = Some(name.to_string());
+        self.loading_since = Some(started);
+        Ok(true)
     }
 
-    pub async fn restart(&mut self, model_path: &str, args: &[String]) -> Result<()> {
-        self.stop().await?;
-        self.start(model_path, args)?;
+    /// Whether the backend exists and has not exited.
+    pub fn is_running(&mut self) -> bool {
+        self.backend.as_mut().is_some_and(|backend| backend.is_running())
+    }
+
+    /// Whether the backend answers its health check.
+    pub async fn healthy(&self) -> bool {
+        match &self.backend {
+            Some(backend) => backend.healthy().await,
+            None => false,
+        }
+    }
+
+    /// Polls the backend's health check until it reports ready, then
+    /// verifies it serves the expected model.
+    pub async fn wait_ready(&mut self, timeout: Duration) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let result = loop {
+            if let Some(result) = self.probe().await {
+                break result;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                break Err(anyhow!("The backend did not become ready within {:?}", timeout));
+            }
+            tokio::time::sleep(READY_POLL_INTERVAL).await;
+        };
+        self.finish_loading(&result);
+        result
+    }
+
+    /// One look at a loading backend: the outcome once it is ready and
+    /// verified, or has exited; `None` while it is still loading.
+    pub async fn probe(&mut self) -> Option<Result<()>> {
+        if self.healthy().await {
+            return Some(match &self.backend {
+                Some(backend) => backend.verify().await,
+                None => Ok(()),
+            });
+        }
+        if !self.is_running() {
+            return Some(Err(anyhow!("The backend exited while loading the model")));
+        }
+        None
+    }
+
+    /// Records how loading the current model ended.
+    pub fn finish_loading(&mut self, result: &Result<()>) {
+        if let (Some(started), Some(model)) = (self.loading_since.take(), self.model.clone()) {
+            match result {
+                Ok(()) => metrics::histogram!(telemetry::MODEL_LOAD_DURATION, "model" => model)
+                    .record(started.elapsed().as_secs_f64()),
+                Err(_) => metrics::counter!(telemetry::MODEL_LOAD_FAILURES, "model" => model).increment(1),
+            }
+        }
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        self.model = None;
+        if let Some(mut backend) = self.backend.take() {
+            backend.stop().await?;
+        }
         Ok(())
     }
 }
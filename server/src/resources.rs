@@ -0,0 +1,121 @@
+//! Resource usage of backend processes and of the machine, read from
+//! `/proc`. Elsewhere (or for backends without a process) nothing is
+//! reported.
+
+use std::path::Path;
+use std::time::Instant;
+use anyhow::{bail, Result};
+use shared::{format_bytes, ProcessUsage};
+use crate::budget::{self, DEFAULT_CONTEXT};
+use crate::config::ModelConfig;
+use crate::{discovery, gguf};
+
+/// Kernel clock ticks per second, the unit of CPU times in `/proc/<pid>/stat`.
+/// 100 on every common Linux configuration.
+const CLOCK_TICKS: f64 = 100.0;
+
+/// KV cache size per context token of models whose header does not give
+/// their dimensions: an f16 cache of a 7B model (32 layers of 4096-wide keys
+/// and values).
+const DEFAULT_KV_BYTES_PER_TOKEN: u64 = 512 * 1024;
+
+/// Samples one process, remembering the previous sample to derive CPU usage.
+#[derive(Default)]
+pub struct Sampler {
+    previous: Option<(u32, f64, Instant)>,
+}
+
+impl Sampler {
+    /// Samples process `pid`, running model `model`; `None` if it is gone.
+    pub fn sample(&mut self, model: &str, pid: u32) -> Option<ProcessUsage> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        let cpu_seconds = cpu_seconds(&stat)?;
+        let now = Instant::now();
+
+        let cpu_percent = match self.previous {
+            Some((previous_pid, previous_cpu, at)) if previous_pid == pid => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                (elapsed > 0.0).then(|| (cpu_seconds - previous_cpu) / elapsed * 100.0)
+            }
+            _ => None,
+        };
+        self.previous = Some((pid, cpu_seconds, now));
+
+        Some(ProcessUsage {
+            model: model.to_string(),
+            pid,
+            rss_bytes: status_field(&status, "VmRSS:").map(|kib| kib * 1024).unwrap_or(0),
+            cpu_seconds,
+            cpu_percent,
+            threads: status_field(&status, "Threads:").unwrap_or(0),
+            memory_available_bytes: available_memory(),
+        })
+    }
+}
+
+/// User plus system CPU time from the content of `/proc/<pid>/stat`.
+fn cpu_seconds(stat: &str) -> Option<f64> {
+    // The command name in parentheses may contain spaces; fields are counted
+    // after it. utime and stime are fields 14 and 15 of the whole line.
+    let rest = &stat[stat.rfind(')')? + 1..];
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let utime: f64 = fields.get(11)?.parse().ok()?;
+    let stime: f64 = fields.get(12)?.parse().ok()?;
+    Some((utime + stime) / CLOCK_TICKS)
+}
+
+/// The number of a `Name:  value [kB]` line of a `/proc` status file.
+fn status_field(content: &str, name: &str) -> Option<u64> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix(name))
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Memory available for new processes without swapping.
+pub fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    status_field(&meminfo, "MemAvailable:").map(|kib| kib * 1024)
+}
+
+/// Estimated memory a managed llama-server needs: the model's `files`, all
+/// shards of it, plus a KV cache for `context` tokens.
+fn estimate_memory(files: u64, kv_bytes_per_token: u64, context: u64) -> u64 {
+    files.saturating_add(context.saturating_mul(kv_bytes_per_token))
+}
+
+/// Fails if `model` is unlikely to fit in the memory available right now.
+pub fn check_fits(name: &str, model: &ModelConfig) -> Result<()> {
+    let Some(path) = model.path.as_deref().map(Path::new) else {
+        return Ok(());
+    };
+    let kv_bytes_per_token = gguf::read(path)
+        .ok()
+        .and_then(|header| header.kv_bytes_per_token())
+        .unwrap_or(DEFAULT_KV_BYTES_PER_TOKEN);
+    let (metadata, files) = match discovery::read_model(path) {
+        Ok((metadata, files)) => (Some(metadata), files),
+        Err(_) => match std::fs::metadata(path) {
+            Ok(file) => (None, file.len()),
+            Err(_) => return Ok(()),
+        },
+    };
+    let context = budget::context_window(model, metadata.as_ref()).unwrap_or(DEFAULT_CONTEXT);
+    let needed = estimate_memory(files, kv_bytes_per_token, context);
+    let Some(available) = available_memory() else {
+        return Ok(());
+    };
+    if needed > available {
+        bail!(
+            "Model '{}' needs about {} ({} tokens of context) but only {} is available. \
+             Lower its context size or set \"memory_check\": false to load it anyway",
+            name,
+            format_bytes(needed),
+            context,
+            format_bytes(available)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,323 @@
+//! In-memory full-text index over every stored message.
+//!
+//! Messages are tokenized into lowercase alphanumeric words. Plain words are
+//! looked up in an inverted index and must all match; quoted phrases are
+//! additionally checked for consecutive occurrence in the message.
+
+use std::collections::{HashMap, HashSet};
+use shared::{ChatHistory, MessageId, Role, SearchHit, SearchQuery};
+
+const DEFAULT_LIMIT: usize = 50;
+/// Approximate number of bytes shown around the first match.
+const SNIPPET_LEN: usize = 160;
+
+type DocKey = (String, MessageId);
+
+struct Doc {
+    conversation_title: Option<String>,
+    role: Role,
+    model: Option<String>,
+    timestamp: Option<u64>,
+    content: String,
+    terms: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    docs: HashMap<DocKey, Doc>,
+    postings: HashMap<String, HashSet<DocKey>>,
+}
+
+impl SearchIndex {
+    pub fn build<'a>(conversations: impl IntoIterator<Item = &'a ChatHistory>) -> Self {
+        let mut index = Self::default();
+        for history in conversations {
+            index.update(history);
+        }
+        index
+    }
+
+    /// Replaces everything indexed for `history`'s conversation.
+    pub fn update(&mut self, history: &ChatHistory) {
+        self.remove(&history.id);
+        for message in &history.messages {
+            let key = (history.id.clone(), message.id);
+            let terms: Vec<String> = tokenize(&message.content).into_iter().map(|(t, _)| t).collect();
+            for term in &terms {
+                self.postings.entry(term.clone()).or_default().insert(key.clone());
+            }
+            self.docs.insert(key, Doc {
+                conversation_title: history.title.clone(),
+                role: message.role.clone(),
+                model: message.model.clone(),
+                timestamp: message.timestamp,
+                content: message.content.clone(),
+                terms,
+            });
+        }
+    }
+
+    pub fn remove(&mut self, conversation_id: &str) {
+        let keys: Vec<DocKey> = self.docs.keys().filter(|(c, _)| c == conversation_id).cloned().collect();
+        for key in keys {
+            if let Some(doc) = self.docs.remove(&key) {
+                for term in doc.terms {
+                    if let Some(set) = self.postings.get_mut(&term) {
+                        set.remove(&key);
+                        if set.is_empty() {
+                            self.postings.remove(&term);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns matching messages, best matches first.
+    pub fn search(&self, query: &SearchQuery) -> Vec<SearchHit> {
+        let (words, phrases) = split_query(&query.text);
+        let all_terms: Vec<&String> = words.iter().chain(phrases.iter().flatten()).collect();
+
+        // Candidates: intersection of the postings of every term, or all
+        // documents when only filters were given.
+        let candidates: Vec<&DocKey> = match all_terms.split_first() {
+            Some((first, rest)) => match self.postings.get(*first) {
+                Some(set) => set
+                    .iter()
+                    .filter(|key| rest.iter().all(|t| self.postings.get(*t).is_some_and(|s| s.contains(*key))))
+                    .collect(),
+                None => Vec::new(),
+            },
+            None => self.docs.keys().collect(),
+        };
+
+        let mut scored: Vec<(usize, &DocKey, &Doc)> = candidates
+            .into_iter()
+            .filter_map(|key| self.docs.get(key).map(|doc| (key, doc)))
+            .filter(|(_, doc)| matches_filters(doc, query))
+            .filter(|(_, doc)| phrases.iter().all(|p| contains_phrase(&doc.terms, p)))
+            .map(|(key, doc)| {
+                let score = doc.terms.iter().filter(|t| all_terms.contains(t)).count();
+                (score, key, doc)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(b.2.timestamp.cmp(&a.2.timestamp)));
+
+        scored
+            .into_iter()
+            .take(query.limit.unwrap_or(DEFAULT_LIMIT))
+            .map(|(_, (conversation_id, message_id), doc)| {
+                let (snippet, highlights) = snippet(&doc.content, &all_terms);
+                SearchHit {
+                    conversation_id: conversation_id.clone(),
+                    conversation_title: doc.conversation_title.clone(),
+                    message_id: *message_id,
+                    role: doc.role.clone(),
+                    model: doc.model.clone(),
+                    timestamp: doc.timestamp,
+                    snippet,
+                    highlights,
+                }
+            })
+            .collect()
+    }
+}
+
+fn matches_filters(doc: &Doc, query: &SearchQuery) -> bool {
+    if query.role.as_ref().is_some_and(|role| *role != doc.role) {
+        return false;
+    }
+    if let Some(model) = &query.model {
+        if doc.model.as_deref() != Some(model.as_str()) {
+            return false;
+        }
+    }
+    // Messages without a timestamp never match a date filter.
+    if query.after.is_some_and(|after| doc.timestamp.is_none_or(|t| t < after)) {
+        return false;
+    }
+    if query.before.is_some_and(|before| doc.timestamp.is_none_or(|t| t >= before)) {
+        return false;
+    }
+    true
+}
+
+/// Splits query text into plain words and quoted phrases, both tokenized.
+fn split_query(text: &str) -> (Vec<String>, Vec<Vec<String>>) {
+    let mut words = Vec::new();
+    let mut phrases = Vec::new();
+    for (i, part) in text.split('"').enumerate() {
+        let terms: Vec<String> = tokenize(part).into_iter().map(|(t, _)| t).collect();
+        // Odd parts are inside quotes.
+        if i % 2 == 1 && terms.len() > 1 {
+            phrases.push(terms);
+        } else {
+            words.extend(terms);
+        }
+    }
+    (words, phrases)
+}
+
+fn contains_phrase(terms: &[String], phrase: &[String]) -> bool {
+    terms.windows(phrase.len()).any(|w| w == phrase)
+}
+
+/// Splits text into lowercase words with their byte ranges.
+fn tokenize(text: &str) -> Vec<(String, (usize, usize))> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                tokens.push((text[s..i].to_lowercase(), (s, i)));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// Cuts an excerpt around the first matching term and locates all matches in it.
+fn snippet(content: &str, terms: &[&String]) -> (String, Vec<(usize, usize)>) {
+    let matches: Vec<(usize, usize)> = tokenize(content)
+        .into_iter()
+        .filter(|(t, _)| terms.contains(&t))
+        .map(|(_, range)| range)
+        .collect();
+
+    let first = matches.first().map(|(s, _)| *s).unwrap_or(0);
+    let mut start = first.saturating_sub(SNIPPET_LEN / 3);
+    while !content.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (start + SNIPPET_LEN).min(content.len());
+    while !content.is_char_boundary(end) {
+        end += 1;
+    }
+
+    let prefix = if start > 0 { "…" } else { "" };
+    let suffix = if end < content.len() { "…" } else { "" };
+    let snippet = format!("{}{}{}", prefix, &content[start..end], suffix).replace('\n', " ");
+    let offset = prefix.len();
+    let highlights = matches
+        .into_iter()
+        .filter(|(s, e)| *s >= start && *e <= end)
+        .map(|(s, e)| (s - start + offset, e - start + offset))
+        .collect();
+    (snippet, highlights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A conversation of `messages`, each `(role, content, timestamp)`.
+    fn conversation(id: &str, title: &str, messages: &[(Role, &str, u64)]) -> ChatHistory {
+        let mut history = ChatHistory::new("qwen");
+        history.id = id.to_string();
+        history.title = Some(title.to_string());
+        for (role, content, timestamp) in messages {
+            let message = history.push(role.clone(), *content);
+            history.get_mut(message).unwrap().timestamp = Some(*timestamp);
+        }
+        history
+    }
+
+    fn index() -> SearchIndex {
+        SearchIndex::build(&[
+            conversation("rust", "Rust", &[
+                (Role::User, "How do I borrow a value twice?", 100),
+                (Role::Assistant, "You can borrow a value immutably many times.", 200),
+            ]),
+            conversation("cooking", "Cooking", &[
+                (Role::User, "Can I borrow your recipe for borrow-free bread?", 300),
+            ]),
+        ])
+    }
+
+    fn search(index: &SearchIndex, query: &str) -> Vec<(String, MessageId)> {
+        index.search(&SearchQuery::parse(query)).into_iter().map(|hit| (hit.conversation_id, hit.message_id)).collect()
+    }
+
+    fn hit(conversation: &str, message: MessageId) -> (String, MessageId) {
+        (conversation.to_string(), message)
+    }
+
+    #[test]
+    fn tokenizes_into_lowercase_words_with_their_ranges() {
+        let tokens = tokenize("Hello, Wörld! x2-go");
+        assert_eq!(tokens, [
+            ("hello".to_string(), (0, 5)),
+            ("wörld".to_string(), (7, 13)),
+            ("x2".to_string(), (15, 17)),
+            ("go".to_string(), (18, 20)),
+        ]);
+        assert!(tokenize(" ... ").is_empty());
+    }
+
+    #[test]
+    fn splits_queries_into_words_and_phrases() {
+        let (words, phrases) = split_query(r#"Borrow "a Value" "twice" "unfinished phrase"#);
+        assert_eq!(words, ["borrow", "twice"]);
+        assert_eq!(phrases, [vec!["a", "value"], vec!["unfinished", "phrase"]]);
+    }
+
+    #[test]
+    fn finds_messages_with_every_word_best_first() {
+        let index = index();
+        // "borrow" twice in the recipe ranks it first.
+        assert_eq!(search(&index, "BORROW"), [hit("cooking", 1), hit("rust", 2), hit("rust", 1)]);
+        assert_eq!(search(&index, "borrow twice"), [hit("rust", 1)]);
+        assert_eq!(search(&index, r#""value twice""#), [hit("rust", 1)]);
+        assert_eq!(search(&index, r#""twice value""#), []);
+        assert!(search(&index, "nowhere").is_empty());
+
+        let hits = index.search(&SearchQuery { text: "borrow".to_string(), limit: Some(1), ..Default::default() });
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].conversation_title.as_deref(), Some("Cooking"));
+        assert_eq!(hits[0].role, Role::User);
+    }
+
+    #[test]
+    fn applies_filters() {
+        let index = index();
+        assert_eq!(search(&index, "borrow role:assistant"), [hit("rust", 2)]);
+        assert_eq!(search(&index, "role:assistant model:qwen"), [hit("rust", 2)]);
+        assert!(search(&index, "borrow model:llama").is_empty());
+
+        let query = |after, before| SearchQuery { text: "borrow".to_string(), after, before, ..Default::default() };
+        let ids = |hits: Vec<SearchHit>| hits.into_iter().map(|hit| hit.message_id).collect::<Vec<_>>();
+        assert_eq!(ids(index.search(&query(Some(200), None))), [1, 2]);
+        assert_eq!(ids(index.search(&query(None, Some(200)))), [1]);
+    }
+
+    #[test]
+    fn updates_and_removes_conversations() {
+        let mut index = index();
+        index.update(&conversation("rust", "Rust", &[(Role::User, "Lifetimes, then.", 400)]));
+        assert_eq!(search(&index, "borrow"), [hit("cooking", 1)]);
+        assert_eq!(search(&index, "lifetimes"), [hit("rust", 1)]);
+
+        index.remove("cooking");
+        assert!(search(&index, "borrow").is_empty());
+        assert!(!index.postings.contains_key("recipe"));
+    }
+
+    #[test]
+    fn cuts_snippets_around_the_first_match() {
+        let borrow = "borrow".to_string();
+        let (text, highlights) = snippet("Can I borrow it?\nBorrow!", &[&borrow]);
+        assert_eq!(text, "Can I borrow it? Borrow!");
+        assert_eq!(highlights, [(6, 12), (17, 23)]);
+
+        // Long content is cut, at character boundaries, and marked.
+        let content = format!("{} borrow {}", "é".repeat(100), "x ".repeat(200));
+        let (text, highlights) = snippet(&content, &[&borrow]);
+        assert!(text.starts_with('…') && text.ends_with('…'), "{}", text);
+        let (start, end) = highlights[0];
+        assert_eq!(&text[start..end], "borrow");
+        assert!(text.len() <= SNIPPET_LEN + 2 * '…'.len_utf8() + 1);
+    }
+}
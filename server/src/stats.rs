@@ -0,0 +1,57 @@
+//! Generation statistics of a streamed reply: time to first token measured
+//! here, token counts and speeds as reported by the backend.
+
+use std::time::{Duration, Instant};
+use shared::GenerationStats;
+use crate::openai::{StreamEvent, Timings, Usage};
+
+pub struct StatsRecorder {
+    started: Instant,
+    first_token: Option<Duration>,
+    usage: Option<Usage>,
+    timings: Option<Timings>,
+}
+
+impl StatsRecorder {
+    /// Starts timing; call right before sending the request.
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            first_token: None,
+            usage: None,
+            timings: None,
+        }
+    }
+
+    pub fn record(&mut self, event: &StreamEvent) {
+        match event {
+            StreamEvent::Token(_) => {
+                self.first_token.get_or_insert_with(|| self.started.elapsed());
+            }
+            StreamEvent::ToolCalls(_) => {}
+            StreamEvent::Usage(usage) => self.usage = Some(*usage),
+            StreamEvent::Timings(timings) => self.timings = Some(*timings),
+        }
+    }
+
+    pub fn finish(self) -> GenerationStats {
+        let total = self.started.elapsed();
+        let usage = self.usage.unwrap_or_default();
+        let timings = self.timings.unwrap_or_default();
+        let completion_tokens = usage.completion_tokens.or(timings.predicted_n);
+        // Without llama-server's timings, the speed is measured from the
+        // first token on, leaving out prompt processing.
+        let measured_speed = match (completion_tokens, self.first_token) {
+            (Some(tokens), Some(first)) if total > first => Some(tokens as f64 / (total - first).as_secs_f64()),
+            _ => None,
+        };
+        GenerationStats {
+            ttft_ms: self.first_token.map(|d| d.as_millis() as u64),
+            total_ms: total.as_millis() as u64,
+            prompt_tokens: usage.prompt_tokens.or(timings.prompt_n),
+            completion_tokens,
+            prompt_per_second: timings.prompt_per_second,
+            tokens_per_second: timings.predicted_per_second.or(measured_speed),
+        }
+    }
+}
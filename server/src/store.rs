@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{bail, Context, Result};
+use shared::ChatHistory;
+
+/// Directory-backed storage holding one JSON file per conversation.
+pub struct ConversationStore {
+    dir: PathBuf,
+}
+
+impl ConversationStore {
+    /// Opens the store at `dir`, creating the directory if needed.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create conversation directory {}", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    /// Creates a new, empty conversation with a fresh id. It is not saved yet.
+    pub fn create(&self, model: &str) -> ChatHistory {
+        let mut history = ChatHistory::new(model);
+        history.id = self.new_id();
+        history
+    }
+
+    /// Writes a conversation, assigning an id first if it has none.
+    pub fn save(&self, history: &mut ChatHistory) -> Result<()> {
+        if history.id.is_empty() {
+            history.id = self.new_id();
+        }
+        let path = self.path(&history.id)?;
+        let json = serde_json::to_string(history)?;
+        std::fs::write(&path, json)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn load(&self, id: &str) -> Result<ChatHistory> {
+        let path = self.path(id)?;
+        read_history(&path)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<()> {
+        let path = self.path(id)?;
+        std::fs::remove_file(&path).with_context(|| format!("Failed to delete {}", path.display()))
+    }
+
+    pub fn exists(&self, id: &str) -> bool {
+        self.path(id).is_ok_and(|path| path.exists())
+    }
+
+    /// Loads every stored conversation, most recently active first.
+    ///
+    /// Unreadable files are logged and skipped so one corrupt file does not
+    /// hide the rest.
+    pub fn list(&self) -> Result<Vec<ChatHistory>> {
+        let mut conversations = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_history(&path) {
+                Ok(history) => conversations.push(history),
+                Err(e) => tracing::warn!("Skipping conversation {}: {}", path.display(), e),
+            }
+        }
+        conversations.sort_by_key(|h| std::cmp::Reverse(h.updated_at()));
+        Ok(conversations)
+    }
+
+    /// Returns the most recently active conversation, if any.
+    pub fn latest(&self) -> Result<Option<ChatHistory>> {
+        Ok(self.list()?.into_iter().next())
+    }
+
+    /// Moves a pre-store `chat_history.json` into the store, once.
+    pub fn migrate_legacy(&self, legacy: &Path) -> Result<()> {
+        if !legacy.exists() || !self.list()?.is_empty() {
+            return Ok(());
+        }
+        let mut history = read_history(legacy)?;
+        self.save(&mut history)?;
+        tracing::info!("Migrated {} into conversation {}", legacy.display(), history.id);
+        Ok(())
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf> {
+        // Ids come from clients too; never let them escape the directory.
+        if !valid_id(id) {
+            bail!("Invalid conversation id '{}'", id);
+        }
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+
+    fn new_id(&self) -> String {
+        let mut n = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        loop {
+            let id = format!("{:x}", n);
+            if !self.dir.join(format!("{}.json", id)).exists() {
+                return id;
+            }
+            n += 1;
+        }
+    }
+}
+
+/// Whether `id` can be used as a conversation id (and thus a file name).
+pub fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn read_history(path: &Path) -> Result<ChatHistory> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut history: ChatHistory = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    history.migrate_linear();
+    Ok(history)
+}
@@ -0,0 +1,162 @@
+//! Prometheus metrics, served as text at `/metrics`.
+//!
+//! Code records through the `metrics` macros with the names below; the
+//! recorder installed by [`install`] aggregates them for scraping.
+
+use std::sync::{Arc, OnceLock};
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use shared::{ClientMessage, GenerationStats, ProcessUsage};
+use crate::AppState;
+
+pub const WS_CONNECTIONS: &str = "llamacpp_chat_ws_connections";
+pub const WS_REQUESTS: &str = "llamacpp_chat_ws_requests_total";
+pub const WS_QUEUED_MESSAGES: &str = "llamacpp_chat_ws_queued_messages";
+pub const BACKEND_REQUESTS: &str = "llamacpp_chat_backend_requests_total";
+pub const BACKEND_IN_FLIGHT: &str = "llamacpp_chat_backend_requests_in_flight";
+pub const GENERATIONS: &str = "llamacpp_chat_generations_total";
+pub const GENERATION_DURATION: &str = "llamacpp_chat_generation_duration_seconds";
+pub const TIME_TO_FIRST_TOKEN: &str = "llamacpp_chat_time_to_first_token_seconds";
+pub const PROMPT_TOKENS: &str = "llamacpp_chat_prompt_tokens_total";
+pub const COMPLETION_TOKENS: &str = "llamacpp_chat_completion_tokens_total";
+pub const BACKEND_RESTARTS: &str = "llamacpp_chat_backend_restarts_total";
+pub const MODEL_LOAD_DURATION: &str = "llamacpp_chat_model_load_duration_seconds";
+pub const MODEL_LOAD_FAILURES: &str = "llamacpp_chat_model_load_failures_total";
+pub const BACKEND_MEMORY: &str = "llamacpp_chat_backend_memory_rss_bytes";
+pub const BACKEND_CPU: &str = "llamacpp_chat_backend_cpu_seconds";
+pub const BACKEND_THREADS: &str = "llamacpp_chat_backend_threads";
+pub const MEMORY_AVAILABLE: &str = "llamacpp_chat_system_memory_available_bytes";
+pub const TOOL_CALLS: &str = "llamacpp_chat_tool_calls_total";
+pub const MCP_SERVER_STARTS: &str = "llamacpp_chat_mcp_server_starts_total";
+pub const MCP_SERVER_FAILURES: &str = "llamacpp_chat_mcp_server_failures_total";
+
+/// Latency buckets, from fast first tokens to large model loads.
+const SECONDS_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Installs the global recorder once; later calls, e.g. from tests
+/// starting several servers in one process, share it.
+pub fn install() -> PrometheusHandle {
+    HANDLE
+        .get_or_init(|| {
+            let recorder = PrometheusBuilder::new()
+                .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), SECONDS_BUCKETS)
+                .expect("buckets are not empty")
+                .build_recorder();
+            let handle = recorder.handle();
+            if let Err(e) = metrics::set_global_recorder(recorder) {
+                tracing::warn!("Metrics are not recorded: {}", e);
+            }
+            describe();
+            handle
+        })
+        .clone()
+}
+
+fn describe() {
+    describe_gauge!(WS_CONNECTIONS, "Open WebSocket connections");
+    describe_counter!(WS_REQUESTS, "WebSocket requests by message type");
+    describe_gauge!(WS_QUEUED_MESSAGES, "WebSocket requests waiting for a reply to finish streaming");
+    describe_counter!(BACKEND_REQUESTS, "Chat completion requests to backends by HTTP status");
+    describe_gauge!(BACKEND_IN_FLIGHT, "Chat completion requests being streamed from backends");
+    describe_counter!(GENERATIONS, "Replies generated by model and outcome");
+    describe_histogram!(GENERATION_DURATION, Unit::Seconds, "Time from request to the end of a reply");
+    describe_histogram!(TIME_TO_FIRST_TOKEN, Unit::Seconds, "Time from request to the first token of a reply");
+    describe_counter!(PROMPT_TOKENS, "Prompt tokens processed by model, as reported by the backend");
+    describe_counter!(COMPLETION_TOKENS, "Tokens generated by model, as reported by the backend");
+    describe_counter!(BACKEND_RESTARTS, "Backends started by model, including the first start");
+    describe_histogram!(MODEL_LOAD_DURATION, Unit::Seconds, "Time from starting a backend until it is ready");
+    describe_counter!(MODEL_LOAD_FAILURES, "Backends that failed to start or become ready");
+    describe_gauge!(BACKEND_MEMORY, Unit::Bytes, "Resident memory of the llama-server process by model");
+    describe_gauge!(BACKEND_CPU, Unit::Seconds, "CPU time used by the llama-server process by model");
+    describe_gauge!(BACKEND_THREADS, "Threads of the llama-server process by model");
+    describe_gauge!(MEMORY_AVAILABLE, Unit::Bytes, "Memory available for new processes");
+    describe_counter!(TOOL_CALLS, "Tool calls run for models by tool and outcome");
+    describe_counter!(MCP_SERVER_STARTS, "MCP servers started by server, including the first start");
+    describe_counter!(MCP_SERVER_FAILURES, "MCP servers that failed to start or answer the handshake");
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/metrics", get(render))
+}
+
+async fn render(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state.metrics.run_upkeep();
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], state.metrics.render())
+}
+
+/// Label for a request in [`WS_REQUESTS`].
+pub fn request_type(message: &ClientMessage) -> &'static str {
+    match message {
+        ClientMessage::Hello { .. } => "Hello",
+        ClientMessage::Text(_) => "Text",
+        ClientMessage::SetModel(_) => "SetModel",
+        ClientMessage::Regenerate => "Regenerate",
+        ClientMessage::Edit { .. } => "Edit",
+        ClientMessage::ListBranches(_) => "ListBranches",
+        ClientMessage::SwitchBranch(_) => "SwitchBranch",
+        ClientMessage::Export { .. } => "Export",
+        ClientMessage::Search(_) => "Search",
+        ClientMessage::OpenConversation(_) => "OpenConversation",
+        ClientMessage::NewConversation => "NewConversation",
+        ClientMessage::ListConversations => "ListConversations",
+        ClientMessage::SetConversationInfo { .. } => "SetConversationInfo",
+        ClientMessage::Stop => "Stop",
+        ClientMessage::RescanModels => "RescanModels",
+        ClientMessage::ApproveTool { .. } => "ApproveTool",
+        ClientMessage::SetAutoApprove(_) => "SetAutoApprove",
+    }
+}
+
+/// Records a finished reply of `model`: `"complete"`, `"stopped"`,
+/// `"disconnected"` or `"error"`.
+pub fn record_generation(model: &str, outcome: &'static str, stats: Option<&GenerationStats>) {
+    let model = model.to_string();
+    metrics::counter!(GENERATIONS, "model" => model.clone(), "outcome" => outcome).increment(1);
+    let Some(stats) = stats else {
+        return;
+    };
+    metrics::histogram!(GENERATION_DURATION, "model" => model.clone()).record(stats.total_ms as f64 / 1000.0);
+    if let Some(ttft) = stats.ttft_ms {
+        metrics::histogram!(TIME_TO_FIRST_TOKEN, "model" => model.clone()).record(ttft as f64 / 1000.0);
+    }
+    if let Some(tokens) = stats.prompt_tokens {
+        metrics::counter!(PROMPT_TOKENS, "model" => model.clone()).increment(tokens);
+    }
+    if let Some(tokens) = stats.completion_tokens {
+        metrics::counter!(COMPLETION_TOKENS, "model" => model).increment(tokens);
+    }
+}
+
+/// Exports a resource sample of the llama-server process.
+pub fn record_process(usage: &ProcessUsage) {
+    let model = usage.model.clone();
+    metrics::gauge!(BACKEND_MEMORY, "model" => model.clone()).set(usage.rss_bytes as f64);
+    metrics::gauge!(BACKEND_CPU, "model" => model.clone()).set(usage.cpu_seconds);
+    metrics::gauge!(BACKEND_THREADS, "model" => model).set(usage.threads as f64);
+}
+
+/// Zeroes the process gauges of a model that is no longer running.
+pub fn forget_process(model: &str) {
+    for name in [BACKEND_MEMORY, BACKEND_CPU, BACKEND_THREADS] {
+        metrics::gauge!(name, "model" => model.to_string()).set(0.0);
+    }
+}
+
+/// Adds one to a gauge until dropped.
+pub struct GaugeGuard(&'static str);
+
+impl GaugeGuard {
+    pub fn new(name: &'static str) -> Self {
+        metrics::gauge!(name).increment(1.0);
+        Self(name)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        metrics::gauge!(self.0).decrement(1.0);
+    }
+}
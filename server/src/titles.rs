@@ -0,0 +1,118 @@
+//! Automatic conversation titles and summaries.
+//!
+//! After the first exchange of a conversation the model is asked, outside
+//! the chat stream, for a short title and a one-paragraph summary.
+
+use anyhow::{anyhow, Result};
+use shared::{ChatHistory, Role};
+use crate::openai::{Message as OAIMessage, OAIClient};
+
+/// Longest excerpt of each message included in the prompt.
+const EXCERPT_LEN: usize = 2000;
+const MAX_TITLE_LEN: usize = 80;
+
+const INSTRUCTIONS: &str = "Write a title of at most six words for the conversation below, \
+then an empty line, then a one-paragraph summary of it. \
+Reply with the title and summary only, without labels or quotes.";
+
+/// Whether `history` should get a generated title now.
+pub fn needs_title(history: &ChatHistory) -> bool {
+    !history.user_titled
+        && history.title.is_none()
+        && history
+            .active_path()
+            .iter()
+            .any(|m| matches!(m.role, Role::Assistant) && !m.content.trim().is_empty())
+}
+
+/// Asks the model for a title and summary of the active branch.
+pub async fn generate(client: &OAIClient, history: &ChatHistory) -> Result<(String, String)> {
+    let mut transcript = String::new();
+    for message in history.active_path() {
+        let speaker = match message.role {
+            Role::User => "User",
+            Role::Assistant => "Assistant",
+            Role::Tool => "Tool",
+        };
+        transcript.push_str(&format!("{}: {}\n\n", speaker, excerpt(&message.content)));
+    }
+
+    let prompt = OAIMessage::new("user", format!("{}\n\n---\n\n{}", INSTRUCTIONS, transcript));
+    let reply = client.complete(vec![prompt]).await?;
+    parse_reply(&reply).ok_or_else(|| anyhow!("Model returned no usable title: {:?}", reply))
+}
+
+/// Splits a reply into title (first non-empty line) and summary (the rest).
+fn parse_reply(reply: &str) -> Option<(String, String)> {
+    let reply = reply.trim();
+    let (first, rest) = reply.split_once('\n').unwrap_or((reply, ""));
+
+    let title = first
+        .trim()
+        .trim_start_matches('#')
+        .trim()
+        .trim_start_matches("Title:")
+        .trim()
+        .trim_matches(|c| c == '"' || c == '*')
+        .trim();
+    if title.is_empty() {
+        return None;
+    }
+    let title: String = title.chars().take(MAX_TITLE_LEN).collect();
+
+    let summary = rest.trim().trim_start_matches("Summary:").trim();
+    Some((title, summary.split_whitespace().collect::<Vec<_>>().join(" ")))
+}
+
+fn excerpt(content: &str) -> &str {
+    match content.char_indices().nth(EXCERPT_LEN) {
+        Some((end, _)) => &content[..end],
+        None => content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_titles_and_summaries() {
+        let reply = "\n## Title: \"Borrowing in Rust\"\n\nSummary: The user asks\nabout   borrowing.\n";
+        assert_eq!(parse_reply(reply), Some(("Borrowing in Rust".to_string(), "The user asks about borrowing.".to_string())));
+        assert_eq!(parse_reply("**Just a title**"), Some(("Just a title".to_string(), String::new())));
+
+        let (title, _) = parse_reply(&"long ".repeat(40)).unwrap();
+        assert_eq!(title.chars().count(), MAX_TITLE_LEN);
+    }
+
+    #[test]
+    fn rejects_replies_without_a_title() {
+        assert_eq!(parse_reply(""), None);
+        assert_eq!(parse_reply("  \n\n "), None);
+        assert_eq!(parse_reply("Title: \"\"\nA summary without its title."), None);
+    }
+
+    #[test]
+    fn titles_answered_conversations_once() {
+        let mut history = ChatHistory::new("qwen");
+        history.push(Role::User, "Hello");
+        assert!(!needs_title(&history));
+        history.push(Role::Assistant, " ");
+        assert!(!needs_title(&history));
+        history.push(Role::Assistant, "Hi!");
+        assert!(needs_title(&history));
+
+        history.title = Some("Greetings".to_string());
+        assert!(!needs_title(&history));
+        history.title = None;
+        history.user_titled = true;
+        assert!(!needs_title(&history));
+    }
+
+    #[test]
+    fn cuts_excerpts_at_characters() {
+        assert_eq!(excerpt("short"), "short");
+        let long = "é".repeat(EXCERPT_LEN + 1);
+        assert_eq!(excerpt(&long).chars().count(), EXCERPT_LEN);
+    }
+}
@@ -0,0 +1,170 @@
+//! Tools models can call while replying.
+//!
+//! The [`ToolRegistry`] holds the tools this server offers. Models whose
+//! capabilities include tool calls are sent their schemas with each
+//! request; when a reply asks for calls, they are run here and their
+//! results sent back to the model, until it answers in text.
+//!
+//! Tools that reach beyond the server need the user's approval for each
+//! call: the file tools unless the conversation opted out, shell commands
+//! always, and tools of MCP servers as configured for their server.
+
+use std::collections::BTreeMap;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use shared::ToolCall;
+pub use crate::config::Approval;
+use crate::config::ToolsConfig;
+use crate::openai::ToolSpec;
+use crate::telemetry;
+
+mod clock;
+mod files;
+mod mcp;
+mod shell;
+
+#[async_trait]
+pub trait Tool: Send + Sync {
+    /// Name the model calls the tool by.
+    fn name(&self) -> &str;
+
+    /// What the tool does, for the model to decide when to call it.
+    fn description(&self) -> &str;
+
+    /// JSON schema of the arguments object.
+    fn parameters(&self) -> Value;
+
+    fn approval(&self) -> Approval {
+        Approval::Never
+    }
+
+    /// Refuses arguments the tool will not run with, before the user is
+    /// asked to approve the call.
+    fn check(&self, _arguments: &Value) -> Result<()> {
+        Ok(())
+    }
+
+    /// Runs the tool. Errors are reported to the model as the result.
+    async fn call(&self, arguments: Value) -> Result<String>;
+}
+
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, Box<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    /// The built-in tools and those of the configured MCP servers, unless
+    /// tools are disabled in `config`. Servers are started here to list
+    /// their tools; those that fail are left out.
+    pub async fn new(config: &ToolsConfig) -> Self {
+        let mut registry = Self::default();
+        if !config.enabled {
+            return registry;
+        }
+        registry.register(clock::CurrentTime);
+        if let Some(files) = &config.files {
+            match files::Workspace::open(files) {
+                Ok(workspace) => {
+                    tracing::info!("File tools see {}", files.root);
+                    registry.register(files::ListDirectory(workspace.clone()));
+                    registry.register(files::ReadFile(workspace.clone()));
+                    registry.register(files::Grep(workspace.clone()));
+                    registry.register(files::FileInfo(workspace));
+                }
+                Err(e) => tracing::warn!("File tools disabled: {:#}", e),
+            }
+        }
+        if let Some(shell) = &config.shell {
+            match shell::RunCommand::new(shell) {
+                Ok(tool) => {
+                    tracing::info!("Commands run in {}", shell.cwd);
+                    registry.register(tool);
+                }
+                Err(e) => tracing::warn!("Shell tool disabled: {:#}", e),
+            }
+        }
+        let servers = config.mcp.iter().map(|(name, server)| async move { (name, mcp::discover(name, server).await) });
+        for (name, tools) in futures::future::join_all(servers).await {
+            match tools {
+                Ok(tools) => tools.into_iter().for_each(|tool| registry.register(tool)),
+                Err(e) => tracing::warn!("Tools of the MCP server {} disabled: {:#}", name, e),
+            }
+        }
+        registry
+    }
+
+    /// Adds `tool`, replacing one of the same name.
+    pub fn register(&mut self, tool: impl Tool + 'static) {
+        self.tools.insert(tool.name().to_string(), Box::new(tool));
+    }
+
+    /// Schemas of all tools, sorted by name.
+    pub fn specs(&self) -> Vec<ToolSpec> {
+        self.tools
+            .values()
+            .map(|tool| ToolSpec::function(tool.name(), tool.description(), tool.parameters()))
+            .collect()
+    }
+
+    /// Whether `call` has to be approved before it runs, given whether its
+    /// conversation lets calls run without asking. Calls that fail without
+    /// running anything, like those of unknown tools, are not asked about.
+    pub fn needs_approval(&self, call: &ToolCall, auto_approve: bool) -> bool {
+        let Some(tool) = self.tools.get(&call.name) else {
+            return false;
+        };
+        let ask = match tool.approval() {
+            Approval::Never => false,
+            Approval::Ask => !auto_approve,
+            Approval::Always => true,
+        };
+        ask && parse_arguments(&call.arguments).and_then(|arguments| tool.check(&arguments)).is_ok()
+    }
+
+    /// Runs `call` and returns what the model is told: the tool's output,
+    /// or what went wrong.
+    pub async fn call(&self, call: &ToolCall) -> String {
+        let result = match self.tools.get(&call.name) {
+            Some(tool) => match parse_arguments(&call.arguments).and_then(|arguments| tool.check(&arguments).map(|_| arguments)) {
+                Ok(arguments) => tool.call(arguments).await,
+                Err(e) => Err(e),
+            },
+            None => Err(anyhow!("Unknown tool '{}'", call.name)),
+        };
+        self.record(call, if result.is_ok() { "ok" } else { "error" });
+        match result {
+            Ok(output) => output,
+            Err(e) => {
+                tracing::info!("Tool call {} failed: {:#}", call.summary(), e);
+                format!("Error: {:#}", e)
+            }
+        }
+    }
+
+    /// What the model is told about a call that did not run, and why.
+    pub fn refused(&self, call: &ToolCall, reason: &str) -> String {
+        self.record(call, "refused");
+        format!("Error: {}", reason)
+    }
+
+    fn record(&self, call: &ToolCall, outcome: &'static str) {
+        // Unknown names are made up by the model; they would grow the label set.
+        let tool = if self.tools.contains_key(&call.name) { call.name.clone() } else { "unknown".to_string() };
+        metrics::counter!(telemetry::TOOL_CALLS, "tool" => tool, "outcome" => outcome).increment(1);
+    }
+}
+
+/// Parses the arguments of a call. Models leave them empty for tools
+/// without parameters.
+fn parse_arguments(arguments: &str) -> Result<Value> {
+    if arguments.trim().is_empty() {
+        return Ok(Value::Object(Default::default()));
+    }
+    let value: Value = serde_json::from_str(arguments).context("Arguments are not valid JSON")?;
+    if !value.is_object() {
+        return Err(anyhow!("Arguments must be a JSON object"));
+    }
+    Ok(value)
+}
@@ -0,0 +1,30 @@
+//! The current date and time, which models cannot know otherwise.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat};
+use serde_json::{json, Value};
+use shared::unix_now;
+use super::Tool;
+
+pub struct CurrentTime;
+
+#[async_trait]
+impl Tool for CurrentTime {
+    fn name(&self) -> &str {
+        "current_time"
+    }
+
+    fn description(&self) -> &str {
+        "Returns the current date and time in UTC, in RFC 3339 format."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    async fn call(&self, _arguments: Value) -> Result<String> {
+        let now = DateTime::from_timestamp(unix_now() as i64, 0).ok_or_else(|| anyhow!("The clock is out of range"))?;
+        Ok(now.to_rfc3339_opts(SecondsFormat::Secs, true))
+    }
+}
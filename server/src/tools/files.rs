@@ -0,0 +1,423 @@
+//! Read-only file tools confined to a workspace directory.
+//!
+//! Paths from the model are taken relative to the workspace root. They are
+//! checked twice: lexically, so `..` cannot climb above the root, and after
+//! resolving symlinks, so a link cannot point out of it either. Directory
+//! walks do not follow symlinks at all.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat};
+use regex::RegexBuilder;
+use serde_json::{json, Value};
+use crate::config::FilesConfig;
+use super::{Approval, Tool};
+
+/// Bytes looked at to tell binary files from text.
+const SNIFF_BYTES: usize = 8 * 1024;
+/// Files larger than this are skipped by `grep`.
+const GREP_MAX_FILE_BYTES: u64 = 4 * 1024 * 1024;
+/// Matched lines are cut to this many characters.
+const GREP_MAX_LINE_CHARS: usize = 200;
+
+/// The directory the file tools work in, and their limits.
+pub struct Workspace {
+    root: PathBuf,
+    max_file_bytes: usize,
+    max_results: usize,
+}
+
+impl Workspace {
+    pub fn open(config: &FilesConfig) -> Result<Arc<Self>> {
+        let root = fs::canonicalize(&config.root)
+            .with_context(|| format!("Cannot open the workspace {}", config.root))?;
+        if !root.is_dir() {
+            bail!("The workspace {} is not a directory", root.display());
+        }
+        Ok(Arc::new(Self {
+            root,
+            max_file_bytes: config.max_file_bytes.max(1),
+            max_results: config.max_results.max(1),
+        }))
+    }
+
+    /// The existing file or directory `path` names, with symlinks resolved.
+    /// Fails for anything outside the root.
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let outside = || anyhow!("'{}' is outside the workspace", path);
+        let given = Path::new(path.trim());
+        // Models like to repeat the absolute path of a listing.
+        let relative = if given.is_absolute() { given.strip_prefix(&self.root).map_err(|_| outside())? } else { given };
+
+        let mut lexical = PathBuf::new();
+        for component in relative.components() {
+            match component {
+                Component::CurDir => {}
+                Component::Normal(part) => lexical.push(part),
+                Component::ParentDir => {
+                    if !lexical.pop() {
+                        return Err(outside());
+                    }
+                }
+                Component::RootDir | Component::Prefix(_) => return Err(outside()),
+            }
+        }
+        let real = fs::canonicalize(self.root.join(&lexical)).with_context(|| format!("Cannot access '{}'", path))?;
+        if !real.starts_with(&self.root) {
+            return Err(outside());
+        }
+        Ok(real)
+    }
+
+    /// `path` as the model should write it: relative to the root.
+    fn display(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => path.display().to_string(),
+        }
+    }
+}
+
+/// Runs file system work off the async runtime.
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(work).await?
+}
+
+fn string_argument<'a>(arguments: &'a Value, name: &str) -> Result<Option<&'a str>> {
+    match arguments.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(_) => Err(anyhow!("Argument '{}' must be a string", name)),
+    }
+}
+
+fn required_string<'a>(arguments: &'a Value, name: &str) -> Result<&'a str> {
+    string_argument(arguments, name)?.ok_or_else(|| anyhow!("Missing argument '{}'", name))
+}
+
+/// Whether the start of a file looks like binary data rather than text.
+fn is_binary(start: &[u8]) -> bool {
+    start.contains(&0)
+}
+
+fn is_hidden(name: &std::ffi::OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
+pub struct ListDirectory(pub Arc<Workspace>);
+
+#[async_trait]
+impl Tool for ListDirectory {
+    fn name(&self) -> &str {
+        "list_directory"
+    }
+
+    fn description(&self) -> &str {
+        "Lists a directory of the workspace. Subdirectories end with a slash."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "Directory relative to the workspace root. Defaults to the root." },
+            },
+        })
+    }
+
+    fn approval(&self) -> Approval {
+        Approval::Ask
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String> {
+        let workspace = self.0.clone();
+        let path = string_argument(&arguments, "path")?.unwrap_or(".").to_string();
+        blocking(move || {
+            let dir = workspace.resolve(&path)?;
+            if !dir.is_dir() {
+                bail!("'{}' is not a directory", path);
+            }
+            let mut entries: Vec<String> = fs::read_dir(&dir)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    match entry.file_type() {
+                        Ok(kind) if kind.is_dir() => format!("{}/", name),
+                        _ => name,
+                    }
+                })
+                .collect();
+            if entries.is_empty() {
+                return Ok(format!("{} is empty", workspace.display(&dir)));
+            }
+            entries.sort();
+            let more = entries.len().saturating_sub(workspace.max_results);
+            entries.truncate(workspace.max_results);
+            if more > 0 {
+                entries.push(format!("… and {} more entries", more));
+            }
+            Ok(entries.join("\n"))
+        })
+        .await
+    }
+}
+
+pub struct ReadFile(pub Arc<Workspace>);
+
+#[async_trait]
+impl Tool for ReadFile {
+    fn name(&self) -> &str {
+        "read_file"
+    }
+
+    fn description(&self) -> &str {
+        "Reads a text file of the workspace. Long files are returned in parts; \
+         the end of each part says which line to continue from."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "File relative to the workspace root." },
+                "offset": { "type": "integer", "description": "First line to return, counting from 1. Defaults to 1." },
+            },
+            "required": ["path"],
+        })
+    }
+
+    fn approval(&self) -> Approval {
+        Approval::Ask
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String> {
+        let workspace = self.0.clone();
+        let path = required_string(&arguments, "path")?.to_string();
+        let offset = match arguments.get("offset") {
+            None | Some(Value::Null) => 1,
+            Some(value) => value.as_u64().filter(|&n| n > 0).ok_or_else(|| anyhow!("Argument 'offset' must be a positive integer"))?,
+        };
+        blocking(move || {
+            let file = workspace.resolve(&path)?;
+            let metadata = fs::metadata(&file)?;
+            if metadata.is_dir() {
+                bail!("'{}' is a directory; list it with list_directory", path);
+            }
+            // Pipes and devices may never end.
+            if !metadata.is_file() {
+                bail!("'{}' is not a regular file", path);
+            }
+            let mut reader = BufReader::with_capacity(SNIFF_BYTES, fs::File::open(&file)?);
+            if is_binary(reader.fill_buf()?) {
+                bail!("'{}' is a binary file", path);
+            }
+
+            let mut content = String::new();
+            let mut line = Vec::new();
+            let mut number = 0;
+            // The line to continue from when the part is full.
+            let mut next = None;
+            let max = workspace.max_file_bytes;
+            loop {
+                line.clear();
+                // At most one byte more than fits, so long lines are not read whole.
+                if (&mut reader).take(max as u64 + 1).read_until(b'\n', &mut line)? == 0 {
+                    break;
+                }
+                number += 1;
+                if number < offset {
+                    if !line.ends_with(b"\n") {
+                        reader.skip_until(b'\n')?;
+                    }
+                    continue;
+                }
+                if content.len() + line.len() > max {
+                    // A single line over the limit is returned cut.
+                    if content.is_empty() {
+                        content.push_str(&String::from_utf8_lossy(&line[..max]));
+                        content.push('\n');
+                        number += 1;
+                    }
+                    next = Some(number);
+                    break;
+                }
+                content.push_str(&String::from_utf8_lossy(&line));
+            }
+            if offset > 1 && number < offset {
+                bail!("'{}' has only {} lines", path, number);
+            }
+            let Some(next) = next else {
+                return Ok(content);
+            };
+            if !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push_str(&format!("[Lines {}-{} of {}; the file goes on. Read on with offset {}.]", offset, next - 1, path, next));
+            Ok(content)
+        })
+        .await
+    }
+}
+
+pub struct Grep(pub Arc<Workspace>);
+
+#[async_trait]
+impl Tool for Grep {
+    fn name(&self) -> &str {
+        "grep"
+    }
+
+    fn description(&self) -> &str {
+        "Searches the text files of the workspace for lines matching a regular expression. \
+         Returns matches as path:line: text. Hidden files and directories are skipped."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "pattern": { "type": "string", "description": "Regular expression, e.g. \"fn main\"." },
+                "path": { "type": "string", "description": "File or directory to search, relative to the workspace root. Defaults to the root." },
+                "ignore_case": { "type": "boolean", "description": "Match upper and lower case alike." },
+            },
+            "required": ["pattern"],
+        })
+    }
+
+    fn approval(&self) -> Approval {
+        Approval::Ask
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String> {
+        let workspace = self.0.clone();
+        let pattern = required_string(&arguments, "pattern")?;
+        let ignore_case = arguments.get("ignore_case").and_then(Value::as_bool).unwrap_or(false);
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .context("Invalid pattern")?;
+        let path = string_argument(&arguments, "path")?.unwrap_or(".").to_string();
+        blocking(move || {
+            let start = workspace.resolve(&path)?;
+            let mut matches = Vec::new();
+            let mut pending = vec![start];
+            while let Some(path) = pending.pop() {
+                if path.is_dir() {
+                    let mut children: Vec<_> = fs::read_dir(&path)?
+                        .filter_map(|entry| entry.ok())
+                        .filter(|entry| !is_hidden(&entry.file_name()))
+                        .filter(|entry| entry.file_type().is_ok_and(|kind| !kind.is_symlink()))
+                        .map(|entry| entry.path())
+                        .collect();
+                    // Popped from the back: reverse to search in name order.
+                    children.sort_by(|a, b| b.cmp(a));
+                    pending.extend(children);
+                    continue;
+                }
+                if search_file(&workspace, &path, &regex, &mut matches).is_err() {
+                    continue;
+                }
+                if matches.len() >= workspace.max_results {
+                    matches.truncate(workspace.max_results);
+                    matches.push(format!("[Stopped after {} matches]", workspace.max_results));
+                    break;
+                }
+            }
+            if matches.is_empty() {
+                return Ok("No matches".to_string());
+            }
+            Ok(matches.join("\n"))
+        })
+        .await
+    }
+}
+
+/// Adds the lines of text file `path` that match `regex` to `matches`.
+fn search_file(workspace: &Workspace, path: &Path, regex: &regex::Regex, matches: &mut Vec<String>) -> Result<()> {
+    if fs::metadata(path)?.len() > GREP_MAX_FILE_BYTES {
+        return Ok(());
+    }
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    if is_binary(reader.fill_buf()?) {
+        return Ok(());
+    }
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let text = String::from_utf8_lossy(&bytes);
+    let name = workspace.display(path);
+    for (i, line) in text.lines().enumerate() {
+        if regex.is_match(line) {
+            let line: String = line.trim().chars().take(GREP_MAX_LINE_CHARS).collect();
+            matches.push(format!("{}:{}: {}", name, i + 1, line));
+            if matches.len() > workspace.max_results {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub struct FileInfo(pub Arc<Workspace>);
+
+#[async_trait]
+impl Tool for FileInfo {
+    fn name(&self) -> &str {
+        "file_info"
+    }
+
+    fn description(&self) -> &str {
+        "Describes a file or directory of the workspace: its type, size and modification time."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "File or directory relative to the workspace root." },
+            },
+            "required": ["path"],
+        })
+    }
+
+    fn approval(&self) -> Approval {
+        Approval::Ask
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String> {
+        let workspace = self.0.clone();
+        let path = required_string(&arguments, "path")?.to_string();
+        blocking(move || {
+            let real = workspace.resolve(&path)?;
+            let metadata = fs::metadata(&real)?;
+            let mut info = vec![format!("path: {}", workspace.display(&real))];
+            if metadata.is_dir() {
+                info.push("type: directory".to_string());
+                info.push(format!("entries: {}", fs::read_dir(&real)?.count()));
+            } else {
+                info.push("type: file".to_string());
+                info.push(format!("size: {} bytes", metadata.len()));
+                let mut start = Vec::with_capacity(SNIFF_BYTES);
+                fs::File::open(&real)?.take(SNIFF_BYTES as u64).read_to_end(&mut start)?;
+                info.push(format!("content: {}", if is_binary(&start) { "binary" } else { "text" }));
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .and_then(|age| DateTime::from_timestamp(age.as_secs() as i64, 0));
+            if let Some(modified) = modified {
+                info.push(format!("modified: {}", modified.to_rfc3339_opts(SecondsFormat::Secs, true)));
+            }
+            if metadata.permissions().readonly() {
+                info.push("read-only".to_string());
+            }
+            Ok(info.join("\n"))
+        })
+        .await
+    }
+}
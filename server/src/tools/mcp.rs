@@ -0,0 +1,74 @@
+//! Tools of MCP servers, offered to models next to the built-in ones.
+//!
+//! A server's tools are named `<server>__<tool>`, so tools of different
+//! servers, and of servers and this one, cannot be confused. Calls are
+//! approved as configured for their server.
+
+use std::sync::Arc;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+use crate::config::McpServerConfig;
+use crate::mcp::McpServer;
+use super::{Approval, Tool};
+
+/// Longest tool name OpenAI-style APIs accept.
+const MAX_NAME_LEN: usize = 64;
+
+pub struct McpTool {
+    server: Arc<McpServer>,
+    name: String,
+    /// Name of the tool on its server.
+    remote: String,
+    description: String,
+    parameters: Value,
+}
+
+#[async_trait]
+impl Tool for McpTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        self.parameters.clone()
+    }
+
+    fn approval(&self) -> Approval {
+        self.server.approval()
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String> {
+        self.server.call_tool(&self.remote, arguments).await
+    }
+}
+
+/// Starts server `name` and returns its tools.
+pub async fn discover(name: &str, config: &McpServerConfig) -> Result<Vec<McpTool>> {
+    let server = Arc::new(McpServer::new(name, config));
+    let tools = server.list_tools().await?;
+    tracing::info!("MCP server {} offers {} tools", server.name(), tools.len());
+    Ok(tools
+        .into_iter()
+        .map(|tool| McpTool {
+            server: server.clone(),
+            name: tool_name(name, &tool.name),
+            description: tool.description,
+            parameters: tool.input_schema,
+            remote: tool.name,
+        })
+        .collect())
+}
+
+/// `<server>__<tool>`, with characters tool names may not hold replaced.
+fn tool_name(server: &str, tool: &str) -> String {
+    format!("{}__{}", server, tool)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .take(MAX_NAME_LEN)
+        .collect()
+}
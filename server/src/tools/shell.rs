@@ -0,0 +1,343 @@
+//! Runs commands for the model, like `cargo check` or `git log`.
+//!
+//! Commands are split into words and run without a shell, so the allow and
+//! deny lists see exactly the program that runs: there are no pipes,
+//! redirections or substitutions to hide another command behind. Only
+//! programs on the allow list run, and programs that run others only if
+//! listed by name. Every call is approved by the user first.
+//!
+//! The lists choose programs; they do not sandbox them. An allowed program
+//! can do whatever its arguments tell it to, such as writing files.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use shared::format_bytes;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{Child, Command};
+use crate::config::ShellConfig;
+use super::{Approval, Tool};
+
+/// Programs that run other programs or scripts given as arguments, and so
+/// would run anything behind an allowed name.
+const WRAPPERS: &[&str] = &[
+    "sh", "bash", "dash", "zsh", "fish", "ksh", "csh", "tcsh", "env", "xargs", "nohup", "nice", "ionice", "timeout",
+    "time", "stdbuf", "setsid", "sudo", "doas", "su", "busybox", "python", "python3", "perl", "ruby", "node", "php",
+    "lua", "awk", "gawk",
+];
+
+/// Options that make a program run other programs or delete files, by
+/// program. They pass only if an allow rule names them.
+const RISKY_OPTIONS: &[(&str, &[&str])] = &[
+    ("find", &["-exec", "-execdir", "-ok", "-okdir", "-delete", "-fprint", "-fprint0", "-fprintf", "-fls"]),
+];
+
+/// Output read from a command, of stdout and stderr together, before it is
+/// stopped. Only `max_output_bytes` of each are kept; this ends commands
+/// like `yes` before the timeout does.
+const MAX_READ_BYTES: usize = 16 * 1024 * 1024;
+
+pub struct RunCommand {
+    cwd: PathBuf,
+    allow: Vec<String>,
+    deny: Vec<String>,
+    timeout: Duration,
+    max_output_bytes: usize,
+    description: String,
+}
+
+impl RunCommand {
+    pub fn new(config: &ShellConfig) -> Result<Self> {
+        if config.allow.is_empty() {
+            bail!("No commands are allowed; list them in `allow`");
+        }
+        let cwd = std::fs::canonicalize(&config.cwd)
+            .with_context(|| format!("Cannot open the command directory {}", config.cwd))?;
+        if !cwd.is_dir() {
+            bail!("The command directory {} is not a directory", cwd.display());
+        }
+        let description = format!(
+            "Runs a command in the project directory and returns its exit code and output. \
+            The command is a program and its arguments, run without a shell: pipes, redirections \
+            and variables are not available. The user approves every call. Allowed commands: {}.",
+            config.allow.join(", ")
+        );
+        Ok(Self {
+            cwd,
+            allow: config.allow.clone(),
+            deny: config.deny.clone(),
+            timeout: Duration::from_secs(config.timeout_secs.max(1)),
+            max_output_bytes: config.max_output_bytes.max(1),
+            description,
+        })
+    }
+
+    /// The words of the command in `arguments`, if the lists let it run.
+    fn command(&self, arguments: &Value) -> Result<Vec<String>> {
+        let command = arguments
+            .get("command")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Missing argument 'command'"))?;
+        let words = split_words(command)?;
+        if words.is_empty() {
+            bail!("The command is empty");
+        }
+        if self.deny.iter().any(|rule| denies(rule, &words)) {
+            bail!("'{}' is not allowed on this server", command);
+        }
+        let program = file_name(&words[0]);
+        let listed = |word: &str| self.allow.iter().any(|rule| rule.split_whitespace().any(|listed| listed == word));
+        let allowed_program = self.allow.iter().any(|rule| rule.split_whitespace().next() == Some(words[0].as_str()));
+        if WRAPPERS.contains(&program) && !allowed_program {
+            bail!("'{}' runs other programs, so it only runs if allowed by name", words[0]);
+        }
+        if !self.allow.iter().any(|rule| allows(rule, &words)) {
+            bail!("'{}' is not allowed on this server; allowed are: {}", command, self.allow.join(", "));
+        }
+        let mut risky = RISKY_OPTIONS.iter().filter(|(name, _)| *name == program).flat_map(|(_, options)| options.iter());
+        if let Some(option) = risky.find(|option| words[1..].iter().any(|word| word == *option) && !listed(option)) {
+            bail!("'{}' with {} is not allowed on this server", words[0], option);
+        }
+        Ok(words)
+    }
+}
+
+#[async_trait]
+impl Tool for RunCommand {
+    fn name(&self) -> &str {
+        "run_command"
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string", "description": "Program and arguments, e.g. \"cargo check\" or \"git log -n 5\"." },
+            },
+            "required": ["command"],
+        })
+    }
+
+    fn approval(&self) -> Approval {
+        Approval::Always
+    }
+
+    fn check(&self, arguments: &Value) -> Result<()> {
+        self.command(arguments).map(|_| ())
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String> {
+        let words = self.command(&arguments)?;
+        let command = arguments["command"].as_str().unwrap_or_default();
+        let mut cmd = Command::new(&words[0]);
+        cmd.args(&words[1..])
+            .current_dir(&self.cwd)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Also ends the command when the timeout drops it.
+            .kill_on_drop(true);
+        // In a group of its own, so that the processes it starts can be
+        // stopped with it.
+        #[cfg(unix)]
+        cmd.process_group(0);
+        let mut child = cmd.spawn().with_context(|| format!("Cannot run '{}'", words[0]))?;
+        let pid = child.id();
+        let mut stdout = Capture::new(self.max_output_bytes);
+        let mut stderr = Capture::new(self.max_output_bytes);
+        let status = match tokio::time::timeout(self.timeout, run(&mut child, &mut stdout, &mut stderr)).await {
+            Ok(status) => status?,
+            Err(_) => {
+                kill_group(pid);
+                bail!("'{}' did not finish within {} s and was stopped", command, self.timeout.as_secs())
+            }
+        };
+        if status.is_none() {
+            kill_group(pid);
+        }
+
+        let mut result = vec![format!("$ {}", command)];
+        result.push(match status.map(|status| status.code()) {
+            Some(Some(code)) => format!("exit code: {}", code),
+            Some(None) => "exit code: none, killed by a signal".to_string(),
+            None => format!("exit code: none, stopped after {} of output", format_bytes(MAX_READ_BYTES as u64)),
+        });
+        for (name, output) in [("stdout", &stdout), ("stderr", &stderr)] {
+            if output.total > 0 {
+                result.push(format!("{}:\n{}", name, output.text().trim_end()));
+            }
+        }
+        if stdout.total == 0 && stderr.total == 0 {
+            result.push("(no output)".to_string());
+        }
+        Ok(result.join("\n"))
+    }
+}
+
+/// Reads the output of `child` until it closes both pipes, then waits for
+/// it. Returns `None` once more than [`MAX_READ_BYTES`] were read, leaving
+/// the command running.
+async fn run(child: &mut Child, stdout: &mut Capture, stderr: &mut Capture) -> Result<Option<ExitStatus>> {
+    let (Some(mut out), Some(mut err)) = (child.stdout.take(), child.stderr.take()) else {
+        bail!("The command has no output pipes");
+    };
+    let (mut out_open, mut err_open) = (true, true);
+    while out_open || err_open {
+        tokio::select! {
+            open = stdout.read_from(&mut out), if out_open => out_open = open,
+            open = stderr.read_from(&mut err), if err_open => err_open = open,
+        }
+        if stdout.total + stderr.total > MAX_READ_BYTES {
+            return Ok(None);
+        }
+    }
+    Ok(Some(child.wait().await?))
+}
+
+/// What is kept of a pipe's output: its start and its end, without
+/// holding the middle in memory.
+struct Capture {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    head_max: usize,
+    tail_max: usize,
+    /// Bytes read in all.
+    total: usize,
+}
+
+impl Capture {
+    fn new(max: usize) -> Self {
+        Self { head: Vec::new(), tail: VecDeque::new(), head_max: max / 2, tail_max: max - max / 2, total: 0 }
+    }
+
+    /// Reads the next chunk of `pipe`. Returns whether it is still open.
+    async fn read_from(&mut self, pipe: &mut (impl AsyncRead + Unpin)) -> bool {
+        let mut buf = [0; 8192];
+        match pipe.read(&mut buf).await {
+            Ok(0) | Err(_) => false,
+            Ok(n) => {
+                self.push(&buf[..n]);
+                true
+            }
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.total += bytes.len();
+        let (head, rest) = bytes.split_at(self.head_max.saturating_sub(self.head.len()).min(bytes.len()));
+        self.head.extend_from_slice(head);
+        self.tail.extend(rest);
+        let excess = self.tail.len().saturating_sub(self.tail_max);
+        self.tail.drain(..excess);
+    }
+
+    /// The output as text, with the middle left out if it was too long.
+    fn text(&self) -> String {
+        let tail: Vec<u8> = self.tail.iter().copied().collect();
+        let left_out = self.total - self.head.len() - tail.len();
+        if left_out == 0 {
+            return String::from_utf8_lossy(&[self.head.as_slice(), &tail].concat()).into_owned();
+        }
+        format!(
+            "{}\n[… {} bytes left out …]\n{}",
+            String::from_utf8_lossy(&self.head),
+            left_out,
+            String::from_utf8_lossy(&tail)
+        )
+    }
+}
+
+/// Kills the process group led by `pid`: the command and whatever it
+/// started that still holds its output open, like the compilers of
+/// `cargo build`.
+#[cfg(unix)]
+fn kill_group(pid: Option<u32>) {
+    let Some(pid) = pid.and_then(|pid| libc::pid_t::try_from(pid).ok()) else {
+        return;
+    };
+    // SAFETY: killpg takes no pointers. The leader has not been waited for
+    // yet, so its pid cannot name another group.
+    if unsafe { libc::killpg(pid, libc::SIGKILL) } != 0 {
+        tracing::warn!("Cannot stop the processes of command {}: {}", pid, std::io::Error::last_os_error());
+    }
+}
+
+/// Elsewhere only the command itself is stopped, by `kill_on_drop`.
+#[cfg(not(unix))]
+fn kill_group(_pid: Option<u32>) {}
+
+/// Whether `words` starts with the words of `rule`, the program written
+/// as in the rule.
+fn allows(rule: &str, words: &[String]) -> bool {
+    let rule: Vec<&str> = rule.split_whitespace().collect();
+    !rule.is_empty() && words.len() >= rule.len() && rule.iter().zip(words).all(|(rule, word)| rule == word)
+}
+
+/// Whether the words of `rule` appear in `words` anywhere, the program
+/// matching by file name: a denied `"rm"` covers `/bin/rm` and `env rm`,
+/// `"cargo publish"` covers `cargo +stable publish`.
+fn denies(rule: &str, words: &[String]) -> bool {
+    let rule: Vec<&str> = rule.split_whitespace().collect();
+    let Some((program, rest)) = rule.split_first() else {
+        return false;
+    };
+    words.iter().enumerate().any(|(i, word)| {
+        let after = &words[i + 1..];
+        (word == program || file_name(word) == *program)
+            && (rest.is_empty() || after.windows(rest.len()).any(|window| window.iter().zip(rest).all(|(word, rule)| word == rule)))
+    })
+}
+
+fn file_name(word: &str) -> &str {
+    std::path::Path::new(word).file_name().and_then(|name| name.to_str()).unwrap_or(word)
+}
+
+/// Splits `command` into words, honouring quotes and backslashes the way a
+/// shell does. Shell syntax that needs a shell to mean anything is refused.
+fn split_words(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => {
+                let next = chars.next().ok_or_else(|| anyhow!("Unfinished escape in the command"))?;
+                let word = word.get_or_insert_with(String::new);
+                if !matches!(next, '"' | '\\' | '$' | '`') {
+                    word.push('\\');
+                }
+                word.push(next);
+            }
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, '\\') => {
+                let next = chars.next().ok_or_else(|| anyhow!("Unfinished escape in the command"))?;
+                word.get_or_insert_with(String::new).push(next);
+            }
+            (None, '|' | '&' | ';' | '<' | '>' | '$' | '`' | '(' | ')') => {
+                bail!("'{}' needs a shell, and commands run without one; run one program at a time", c)
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        bail!("Unfinished quote in the command");
+    }
+    words.extend(word);
+    Ok(words)
+}
+
@@ -0,0 +1,32 @@
+//! Browser chat UI.
+//!
+//! The page, script and stylesheet under `server/web/` are compiled into the
+//! binary and served from `/`. The page talks to `/ws` with the same
+//! `ClientMessage`/`ServerMessage` protocol as the TUI.
+
+use std::sync::Arc;
+use axum::{http::header, response::IntoResponse, routing::get, Router};
+use crate::AppState;
+
+const INDEX_HTML: &str = include_str!("../web/index.html");
+const APP_JS: &str = include_str!("../web/app.js");
+const STYLE_CSS: &str = include_str!("../web/style.css");
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(index))
+        .route("/app.js", get(app_js))
+        .route("/style.css", get(style_css))
+}
+
+async fn index() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], INDEX_HTML)
+}
+
+async fn app_js() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/javascript; charset=utf-8")], APP_JS)
+}
+
+async fn style_css() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/css; charset=utf-8")], STYLE_CSS)
+}
@@ -0,0 +1,84 @@
+//! Recording and replaying backend traffic. The fixtures in
+//! `tests/cassettes` also pin down the SSE parsing.
+
+mod common;
+
+use std::time::{Duration, Instant};
+use common::{cassette, TempDir, TestClient, TestServer};
+use serde_json::{json, Value};
+use shared::{ClientMessage, ServerMessage};
+
+/// Starts a server with model `replayed` serving `cassette`, and selects it.
+async fn replaying(dir: &TempDir, cassette: &str) -> (TestServer, TestClient) {
+    let models = json!({ "models": { "replayed": { "backend": "replay", "cassette": cassette } } });
+    let server = TestServer::start_with(dir, models).await;
+    let (mut client, _, _) = server.connect().await;
+    client.send(&ClientMessage::SetModel("replayed".to_string())).await;
+    match client.recv().await {
+        ServerMessage::ModelChanged(model) => assert_eq!(model, "replayed"),
+        other => panic!("expected ModelChanged, got {:?}", other),
+    }
+    (server, client)
+}
+
+#[tokio::test]
+async fn parses_events_split_across_chunks() {
+    let dir = TempDir::new();
+    let (_server, mut client) = replaying(&dir, &cassette("split_events.json")).await;
+
+    client.send(&ClientMessage::Text("Hi".to_string())).await;
+    let reply = client.reply().await;
+
+    assert!(reply.errors.is_empty(), "{:?}", reply.errors);
+    assert_eq!(reply.text(), "Héllo wörld 🎉");
+}
+
+#[tokio::test]
+async fn replays_with_original_timing() {
+    let dir = TempDir::new();
+    let (_server, mut client) = replaying(&dir, &cassette("timed.json")).await;
+
+    let started = Instant::now();
+    client.send(&ClientMessage::Text("Hi".to_string())).await;
+    let reply = client.reply().await;
+
+    assert_eq!(reply.text(), "one two three");
+    assert!(started.elapsed() >= Duration::from_millis(300), "{:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn replays_recorded_errors() {
+    let dir = TempDir::new();
+    let (_server, mut client) = replaying(&dir, &cassette("server_error.json")).await;
+
+    client.send(&ClientMessage::Text("Hi".to_string())).await;
+    let reply = client.reply().await;
+
+    assert!(reply.tokens.is_empty());
+    assert!(reply.errors.iter().any(|e| e.contains("context size exceeded")), "{:?}", reply.errors);
+}
+
+#[tokio::test]
+async fn records_exchanges_that_replay_identically() {
+    let dir = TempDir::new();
+    let recordings = dir.path().join("cassettes");
+    let original = {
+        let server = TestServer::start_with(&dir, json!({ "record": recordings })).await;
+        let (mut client, _, _) = server.connect().await;
+        client.send(&ClientMessage::Text("record this".to_string())).await;
+        client.reply().await.text()
+    };
+    assert_eq!(original, "alpha: record this");
+
+    let files: Vec<_> = std::fs::read_dir(&recordings).unwrap().map(|e| e.unwrap().path()).collect();
+    assert_eq!(files.len(), 1);
+    let recorded: Value = serde_json::from_str(&std::fs::read_to_string(&files[0]).unwrap()).unwrap();
+    assert_eq!(recorded["status"], 200);
+    assert_eq!(recorded["request"]["messages"][0]["content"], "record this");
+    assert!(recorded["chunks"].as_array().unwrap().len() > 1);
+
+    let replay_dir = TempDir::new();
+    let (_server, mut client) = replaying(&replay_dir, recordings.to_str().unwrap()).await;
+    client.send(&ClientMessage::Text("anything".to_string())).await;
+    assert_eq!(client.reply().await.text(), original);
+}
@@ -0,0 +1,479 @@
+//! Test harness: the server app on an ephemeral port, backed by an
+//! in-process fake OpenAI-compatible server, driven over WebSocket.
+
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use server::store::ConversationStore;
+use shared::{ClientFrame, ClientMessage, GenerationStats, ModelDescriptor, RequestId, ServerFrame, ServerMessage, ToolCall, PROTOCOL_VERSION};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream};
+
+/// How long to wait for any single server message.
+const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Prompts starting with this make the fake backend answer with an HTTP 500.
+pub const FAIL_PROMPT: &str = "fail";
+
+/// Prompts starting with this are answered slowly, one word every 100ms,
+/// leaving time to interrupt the reply.
+pub const SLOW_PROMPT: &str = "slow";
+
+/// Prompts like `call <tool> <arguments>` make the fake backend ask for
+/// that tool call, more lines like it for more calls; once the request
+/// ends with a result, the reply is `"<model>: <result>"`.
+pub const TOOL_PROMPT: &str = "call ";
+
+/// A directory removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "llamacpp-chat-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        let path = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+/// Writes a GGUF file with `metadata` and tensors of the given shapes, but
+/// no weights: enough for the header reader. JSON strings, numbers, bools
+/// and string arrays become GGUF strings, u32s, bools and arrays.
+pub fn write_gguf(path: &Path, metadata: Value, tensors: &[&[u64]]) {
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.extend((s.len() as u64).to_le_bytes());
+        out.extend(s.as_bytes());
+    }
+    let metadata = metadata.as_object().cloned().unwrap_or_default();
+    let mut out = b"GGUF".to_vec();
+    out.extend(3u32.to_le_bytes());
+    out.extend((tensors.len() as u64).to_le_bytes());
+    out.extend((metadata.len() as u64).to_le_bytes());
+    for (key, value) in &metadata {
+        string(&mut out, key);
+        match value {
+            Value::String(s) => {
+                out.extend(8u32.to_le_bytes());
+                string(&mut out, s);
+            }
+            Value::Number(n) => {
+                out.extend(4u32.to_le_bytes());
+                out.extend((n.as_u64().unwrap() as u32).to_le_bytes());
+            }
+            Value::Bool(b) => {
+                out.extend(7u32.to_le_bytes());
+                out.push(*b as u8);
+            }
+            Value::Array(items) => {
+                out.extend(9u32.to_le_bytes());
+                out.extend(8u32.to_le_bytes());
+                out.extend((items.len() as u64).to_le_bytes());
+                for item in items {
+                    string(&mut out, item.as_str().unwrap());
+                }
+            }
+            other => panic!("unsupported GGUF value {}", other),
+        }
+    }
+    for (i, dims) in tensors.iter().enumerate() {
+        string(&mut out, &format!("tensor.{}", i));
+        out.extend((dims.len() as u32).to_le_bytes());
+        for dim in *dims {
+            out.extend(dim.to_le_bytes());
+        }
+        out.extend(0u32.to_le_bytes()); // F32
+        out.extend(0u64.to_le_bytes());
+    }
+    std::fs::write(path, out).unwrap();
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Fake OpenAI-compatible server. Replies `"<model>: <last user message>"`,
+/// streamed word by word.
+pub struct FakeBackend {
+    pub url: String,
+    /// Every chat completion request body received, in order.
+    pub requests: Arc<Mutex<Vec<Value>>>,
+    task: JoinHandle<()>,
+}
+
+impl FakeBackend {
+    pub async fn start() -> Self {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route("/health", get(|| async { Json(json!({ "status": "ok" })) }))
+            .route("/v1/models", get(|| async { Json(json!({ "object": "list", "data": [] })) }))
+            .route("/v1/chat/completions", post(fake_completions))
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let task = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Self { url, requests, task }
+    }
+
+    /// The most recent chat completion request.
+    pub fn last_request(&self) -> Value {
+        self.requests.lock().unwrap().last().cloned().expect("no request received")
+    }
+}
+
+impl Drop for FakeBackend {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn fake_completions(State(requests): State<Arc<Mutex<Vec<Value>>>>, Json(body): Json<Value>) -> Response {
+    requests.lock().unwrap().push(body.clone());
+    let model = body["model"].as_str().unwrap_or("unknown").to_string();
+    let prompt = body["messages"]
+        .as_array()
+        .and_then(|messages| messages.iter().rev().find(|m| m["role"] == "user"))
+        .and_then(|m| m["content"].as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    if prompt.starts_with(FAIL_PROMPT) {
+        let error = json!({ "error": { "message": "backend exploded", "type": "server_error" } });
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+    }
+
+    let last = body["messages"].as_array().and_then(|messages| messages.last()).cloned().unwrap_or_default();
+    if prompt.starts_with(TOOL_PROMPT) && last["role"] != "tool" {
+        let calls: Vec<(&str, &str)> = prompt
+            .lines()
+            .filter_map(|line| line.strip_prefix(TOOL_PROMPT))
+            .map(|call| call.split_once(' ').unwrap_or((call, "")))
+            .collect();
+        return fake_tool_calls(&calls);
+    }
+    let reply = match last["role"].as_str() {
+        Some("tool") => format!("{}: {}", model, last["content"].as_str().unwrap_or_default()),
+        _ => format!("{}: {}", model, prompt),
+    };
+    if !body["stream"].as_bool().unwrap_or(false) {
+        return Json(json!({ "choices": [{ "message": { "role": "assistant", "content": reply }, "finish_reason": "stop" }] }))
+            .into_response();
+    }
+
+    let mut events: Vec<String> = reply
+        .split_inclusive(' ')
+        .map(|token| format!("data: {}\n\n", json!({ "choices": [{ "delta": { "content": token }, "finish_reason": null }] })))
+        .collect();
+    // llama-server sends its timings with the last event, and usage on request.
+    let tokens = events.len();
+    let last = json!({ "choices": [{ "delta": {}, "finish_reason": "stop" }], "timings": FAKE_TIMINGS.with(tokens) });
+    events.push(format!("data: {}\n\n", last));
+    if body.pointer("/stream_options/include_usage").and_then(Value::as_bool).unwrap_or(false) {
+        let usage = json!({ "choices": [], "usage": { "prompt_tokens": FAKE_PROMPT_TOKENS, "completion_tokens": tokens } });
+        events.push(format!("data: {}\n\n", usage));
+    }
+    events.push("data: [DONE]\n\n".to_string());
+    // Pauses keep the events in separate chunks, like a real model would.
+    let pause = Duration::from_millis(if prompt.starts_with(SLOW_PROMPT) { 100 } else { 5 });
+    let stream = futures::stream::iter(events).then(move |event| async move {
+        tokio::time::sleep(pause).await;
+        Ok::<_, std::io::Error>(Bytes::from(event))
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from_stream(stream))
+        .unwrap()
+}
+
+/// Streams calls of tools, `call_0`, `call_1` and so on, each with its
+/// arguments split in two events.
+fn fake_tool_calls(calls: &[(&str, &str)]) -> Response {
+    let mut events = Vec::new();
+    for (index, (tool, arguments)) in calls.iter().enumerate() {
+        let (head, tail) = arguments.split_at(arguments.len() / 2);
+        let id = format!("call_{}", index);
+        events.push(json!({ "choices": [{ "delta": { "tool_calls": [
+            { "index": index, "id": id, "type": "function", "function": { "name": tool, "arguments": head } }
+        ] }, "finish_reason": null }] }));
+        events.push(json!({ "choices": [{ "delta": { "tool_calls": [{ "index": index, "function": { "arguments": tail } }] }, "finish_reason": null }] }));
+    }
+    events.push(json!({ "choices": [{ "delta": {}, "finish_reason": "tool_calls" }] }));
+    let mut events: Vec<String> = events.iter().map(|event| format!("data: {}\n\n", event)).collect();
+    events.push("data: [DONE]\n\n".to_string());
+    let stream = futures::stream::iter(events).then(|event| async move {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        Ok::<_, std::io::Error>(Bytes::from(event))
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from_stream(stream))
+        .unwrap()
+}
+
+/// Prompt token count the fake backend reports.
+pub const FAKE_PROMPT_TOKENS: u64 = 7;
+
+/// Speeds the fake backend reports in its llama-server style timings.
+pub const FAKE_TIMINGS: FakeTimings = FakeTimings { prompt_per_second: 250.0, predicted_per_second: 42.5 };
+
+pub struct FakeTimings {
+    pub prompt_per_second: f64,
+    pub predicted_per_second: f64,
+}
+
+impl FakeTimings {
+    fn with(&self, predicted_n: usize) -> Value {
+        json!({
+            "prompt_n": FAKE_PROMPT_TOKENS,
+            "prompt_per_second": self.prompt_per_second,
+            "predicted_n": predicted_n,
+            "predicted_per_second": self.predicted_per_second,
+        })
+    }
+}
+
+/// The chat server with models `alpha` (default) and `beta` on a [`FakeBackend`].
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub backend: FakeBackend,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Starts a server storing its conversations in `dir`.
+    pub async fn start(dir: &TempDir) -> Self {
+        Self::start_with(dir, json!({})).await
+    }
+
+    /// Like [`start`](Self::start), with `extra` merged into the config:
+    /// its `models` are added to `alpha` and `beta`, or add fields to them,
+    /// other keys replace the defaults.
+    pub async fn start_with(dir: &TempDir, extra: Value) -> Self {
+        let backend = FakeBackend::start().await;
+        let mut config = json!({
+            "models": {
+                "alpha": { "backend": "openai", "url": backend.url },
+                "beta": { "backend": "openai", "url": backend.url },
+            },
+            "default": "alpha",
+            // Background title requests would interleave with the tested ones.
+            "titles": { "enabled": false },
+        });
+        for (key, value) in extra.as_object().cloned().unwrap_or_default() {
+            match (key.as_str(), value) {
+                ("models", Value::Object(models)) => {
+                    for (name, model) in models {
+                        match (&mut config["models"][&name], model) {
+                            (Value::Object(existing), Value::Object(fields)) => existing.extend(fields),
+                            (_, model) => config["models"][&name] = model,
+                        }
+                    }
+                }
+                (_, value) => config[key] = value,
+            }
+        }
+        let config = serde_json::from_value(config).unwrap();
+        let store = ConversationStore::open(dir.path().join("conversations")).unwrap();
+        let app = server::router(server::start(config, store).await);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Self { addr, backend, task }
+    }
+
+    /// Connects a client, performs the handshake and consumes the initial
+    /// `Welcome`, `History` and `AvailableModels`.
+    pub async fn connect(&self) -> (TestClient, shared::ChatHistory, Vec<ModelDescriptor>) {
+        let mut client = self.connect_raw().await;
+        client.hello(PROTOCOL_VERSION).await;
+        match client.recv().await {
+            ServerMessage::Welcome { .. } => {}
+            other => panic!("expected Welcome, got {:?}", other),
+        }
+        let history = match client.recv().await {
+            ServerMessage::History(history) => history,
+            other => panic!("expected History, got {:?}", other),
+        };
+        let models = match client.recv().await {
+            ServerMessage::AvailableModels(models) => models,
+            other => panic!("expected AvailableModels, got {:?}", other),
+        };
+        (client, history, models)
+    }
+
+    /// Connects without a handshake; messages are sent bare until [`TestClient::hello`].
+    pub async fn connect_raw(&self) -> TestClient {
+        let (ws, _) = connect_async(format!("ws://{}/ws", self.addr)).await.unwrap();
+        TestClient { ws, framed: false, next_id: 1 }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+pub struct TestClient {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    /// Whether messages are sent in `ClientFrame`s, i.e. after saying hello.
+    framed: bool,
+    next_id: RequestId,
+}
+
+impl TestClient {
+    /// Opens the handshake with request id 0, claiming protocol `version`.
+    pub async fn hello(&mut self, version: u32) {
+        let hello = ClientMessage::Hello { version, client: "tests".to_string(), capabilities: Vec::new() };
+        let frame = ClientFrame { id: Some(0), message: hello };
+        self.send_raw(&serde_json::to_string(&frame).unwrap()).await;
+        self.framed = true;
+    }
+
+    /// Sends `message`, with a fresh request id once framed; returns the id.
+    pub async fn send(&mut self, message: &ClientMessage) -> RequestId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let json = if self.framed {
+            serde_json::to_string(&ClientFrame { id: Some(id), message: message.clone() })
+        } else {
+            serde_json::to_string(message)
+        };
+        self.ws.send(WsMessage::Text(json.unwrap())).await.unwrap();
+        id
+    }
+
+    /// Sends a raw text frame, bypassing the protocol types.
+    pub async fn send_raw(&mut self, text: &str) {
+        self.ws.send(WsMessage::Text(text.to_string())).await.unwrap();
+    }
+
+    /// The next server message; panics after [`RECV_TIMEOUT`].
+    pub async fn recv(&mut self) -> ServerMessage {
+        self.recv_frame().await.message
+    }
+
+    /// The next server message with the request it answers. Bare messages,
+    /// as sent to legacy or rejected clients, answer no request.
+    pub async fn recv_frame(&mut self) -> ServerFrame {
+        let text = self.recv_text().await;
+        serde_json::from_str(&text).unwrap_or_else(|_| ServerFrame {
+            reply_to: None,
+            message: serde_json::from_str(&text).unwrap(),
+        })
+    }
+
+    /// The next server message as JSON, without assuming its shape.
+    pub async fn recv_json(&mut self) -> Value {
+        serde_json::from_str(&self.recv_text().await).unwrap()
+    }
+
+    async fn recv_text(&mut self) -> String {
+        loop {
+            let frame = tokio::time::timeout(RECV_TIMEOUT, self.ws.next())
+                .await
+                .expect("timed out waiting for the server")
+                .expect("connection closed")
+                .unwrap();
+            if let WsMessage::Text(text) = frame {
+                return text;
+            }
+        }
+    }
+
+    /// Whether the server closes the connection without sending anything else.
+    pub async fn closed(&mut self) -> bool {
+        loop {
+            match tokio::time::timeout(RECV_TIMEOUT, self.ws.next()).await {
+                Ok(None) | Ok(Some(Err(_))) | Ok(Some(Ok(WsMessage::Close(_)))) => return true,
+                Ok(Some(Ok(WsMessage::Text(_)))) | Err(_) => return false,
+                Ok(Some(Ok(_))) => {}
+            }
+        }
+    }
+
+    /// Collects a streamed reply up to `EndOfMessage`: the tokens, any
+    /// errors, the stats and tool calls. Approval requests fail the test.
+    pub async fn reply(&mut self) -> Reply {
+        self.collect_reply(None).await
+    }
+
+    /// Like [`reply`](Self::reply), answering every approval request with
+    /// `approved`.
+    pub async fn reply_approving(&mut self, approved: bool) -> Reply {
+        self.collect_reply(Some(approved)).await
+    }
+
+    async fn collect_reply(&mut self, approve: Option<bool>) -> Reply {
+        let mut reply = Reply::default();
+        loop {
+            match self.recv().await {
+                ServerMessage::Token(token) => reply.tokens.push(token),
+                ServerMessage::Error(error) => reply.errors.push(error),
+                ServerMessage::Stats(stats) => reply.stats = Some(stats),
+                ServerMessage::ToolCalls(calls) => reply.tool_calls.extend(calls),
+                ServerMessage::ToolApproval(call) if approve.is_some() => {
+                    let approved = approve.unwrap_or_default();
+                    self.send(&ClientMessage::ApproveTool { call_id: call.id.clone(), approved }).await;
+                    reply.approvals.push(call);
+                }
+                ServerMessage::ToolResult { call_id, content } => reply.tool_results.push((call_id, content)),
+                ServerMessage::EndOfMessage => return reply,
+                other => panic!("unexpected message while streaming: {:?}", other),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Reply {
+    pub tokens: Vec<String>,
+    pub errors: Vec<String>,
+    pub stats: Option<GenerationStats>,
+    /// Tools called on the way, and their results by call id.
+    pub tool_calls: Vec<ToolCall>,
+    pub tool_results: Vec<(String, String)>,
+    /// Calls the server asked to approve.
+    pub approvals: Vec<ToolCall>,
+}
+
+impl Reply {
+    pub fn text(&self) -> String {
+        self.tokens.concat()
+    }
+}
+
+/// Path of a fixture in `server/tests/cassettes`.
+pub fn cassette(name: &str) -> String {
+    format!("{}/tests/cassettes/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
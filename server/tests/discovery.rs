@@ -0,0 +1,114 @@
+//! Models found in `models_dirs`: scanning, launch profiles, precedence of
+//! `models.json` and rescans.
+
+mod common;
+
+use std::path::Path;
+use common::{write_gguf, TempDir, TestServer};
+use serde_json::{json, Value};
+use shared::{Capability, ClientMessage, ModelDescriptor, ServerMessage};
+
+/// Writes a model header trained for `context` tokens with a chat template.
+fn model(path: &Path, context: u64) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    write_gguf(
+        path,
+        json!({
+            "general.architecture": "llama",
+            "llama.context_length": context,
+            "tokenizer.chat_template": "{{ messages }}",
+        }),
+        &[&[1000, 10]],
+    );
+}
+
+fn names(models: &[ModelDescriptor]) -> Vec<&str> {
+    models.iter().map(|m| m.name.as_str()).collect()
+}
+
+async fn start(dir: &TempDir, models: &Path) -> TestServer {
+    TestServer::start_with(dir, json!({ "models_dirs": [models] })).await
+}
+
+#[tokio::test]
+async fn registers_models_found_in_models_dirs() {
+    let dir = TempDir::new();
+    let models = dir.path().join("models");
+    model(&models.join("qwen/qwen-7b.gguf"), 32768);
+    model(&models.join("big-00001-of-00002.gguf"), 4096);
+    model(&models.join("big-00002-of-00002.gguf"), 4096);
+    model(&models.join("alpha.gguf"), 4096);
+    model(&models.join("configured.gguf"), 4096);
+    write_gguf(&models.join("mmproj.gguf"), json!({ "general.type": "mmproj" }), &[]);
+    std::fs::write(models.join("partial.gguf"), b"GGUF").unwrap();
+
+    let server = TestServer::start_with(&dir, json!({
+        "models_dirs": [models],
+        "models": { "mine": { "backend": "mock", "path": models.join("configured.gguf") } },
+    }))
+    .await;
+    let (_client, _, listed) = server.connect().await;
+
+    // `alpha` and `configured.gguf` are taken by models.json entries.
+    assert_eq!(names(&listed), ["alpha", "beta", "big", "mine", "qwen/qwen-7b"]);
+    assert_eq!(listed[0].metadata, None);
+    let big = listed[2].metadata.as_ref().unwrap();
+    assert_eq!(big.parameters, Some(2 * 1000 * 10), "both shards count");
+
+    let rest: Value = reqwest::get(format!("http://{}/api/models", server.addr)).await.unwrap().json().await.unwrap();
+    let qwen = rest.as_array().unwrap().iter().find(|m| m["name"] == "qwen/qwen-7b").unwrap();
+    assert_eq!(qwen["backend"], "managed");
+    assert_eq!(qwen["discovered"], true);
+    assert_eq!(qwen["args"], json!(["-c", "8192", "--jinja"]));
+    let big = rest.as_array().unwrap().iter().find(|m| m["name"] == "big").unwrap();
+    assert!(big["path"].as_str().unwrap().ends_with("big-00001-of-00002.gguf"), "{}", big);
+    // The projector next to it makes it see images.
+    let projector = models.join("mmproj.gguf");
+    assert_eq!(big["args"], json!(["-c", "4096", "--jinja", "--mmproj", projector]));
+    assert_eq!(listed[2].capabilities, [Capability::Vision]);
+    assert_eq!(listed[4].capabilities, []);
+    assert_eq!(listed[4].context_size, Some(8192));
+}
+
+#[tokio::test]
+async fn rescans_on_request() {
+    let dir = TempDir::new();
+    let models = dir.path().join("models");
+    std::fs::create_dir_all(&models).unwrap();
+    let server = start(&dir, &models).await;
+    let (mut client, _, listed) = server.connect().await;
+    assert_eq!(names(&listed), ["alpha", "beta"]);
+
+    model(&models.join("new.gguf"), 2048);
+    let id = client.send(&ClientMessage::RescanModels).await;
+    loop {
+        let frame = client.recv_frame().await;
+        // Triggered by the watcher as well; either announcement will do.
+        if let ServerMessage::AvailableModels(listed) = frame.message {
+            assert_eq!(names(&listed), ["alpha", "beta", "new"]);
+            assert!(frame.reply_to.is_none() || frame.reply_to == Some(id));
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn rescans_when_files_change() {
+    let dir = TempDir::new();
+    let models = dir.path().join("models");
+    std::fs::create_dir_all(&models).unwrap();
+    let server = start(&dir, &models).await;
+    let (mut client, _, _) = server.connect().await;
+
+    model(&models.join("downloaded.gguf"), 2048);
+    match client.recv().await {
+        ServerMessage::AvailableModels(listed) => assert_eq!(names(&listed), ["alpha", "beta", "downloaded"]),
+        other => panic!("expected AvailableModels, got {:?}", other),
+    }
+
+    std::fs::remove_file(models.join("downloaded.gguf")).unwrap();
+    match client.recv().await {
+        ServerMessage::AvailableModels(listed) => assert_eq!(names(&listed), ["alpha", "beta"]),
+        other => panic!("expected AvailableModels, got {:?}", other),
+    }
+}
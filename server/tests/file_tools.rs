@@ -0,0 +1,255 @@
+//! The file tools: approval of each call, what they return and that they
+//! stay inside the workspace.
+
+mod common;
+
+use std::path::PathBuf;
+use common::{TempDir, TestClient, TestServer, TOOL_PROMPT};
+use serde_json::{json, Value};
+use shared::{ClientMessage, Role, ServerMessage};
+
+/// A server whose model `alpha` can call the file tools on a small
+/// workspace, next to a file outside of it.
+async fn start(dir: &TempDir, files: Value) -> (TestServer, PathBuf) {
+    let workspace = dir.path().join("workspace");
+    std::fs::create_dir_all(workspace.join("src")).unwrap();
+    std::fs::write(workspace.join("notes.txt"), "first line\nsecond line\n").unwrap();
+    std::fs::write(workspace.join("src/main.rs"), "fn main() {\n    println!(\"hello\");\n}\n").unwrap();
+    std::fs::write(workspace.join("logo.png"), b"\x89PNG\r\n\x1a\n\0\0").unwrap();
+    std::fs::write(dir.path().join("secret.txt"), "do not read").unwrap();
+
+    let mut files = files;
+    files["root"] = json!(workspace);
+    let server = TestServer::start_with(dir, json!({
+        "models": { "alpha": { "capabilities": ["tools"] } },
+        "tools": { "files": files },
+    }))
+    .await;
+    (server, workspace)
+}
+
+fn call(tool: &str, arguments: Value) -> ClientMessage {
+    ClientMessage::Text(format!("{}{} {}", TOOL_PROMPT, tool, arguments))
+}
+
+/// What an auto-approved call of `tool` returned.
+async fn result(client: &mut TestClient, tool: &str, arguments: Value) -> String {
+    client.send(&call(tool, arguments)).await;
+    client.reply().await.tool_results.remove(0).1
+}
+
+#[tokio::test]
+async fn runs_calls_the_user_approves() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({})).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&call("read_file", json!({ "path": "notes.txt" }))).await;
+    let reply = client.reply_approving(true).await;
+    assert_eq!(reply.approvals.len(), 1);
+    assert_eq!(reply.approvals[0].name, "read_file");
+    assert_eq!(reply.tool_results[0].1, "first line\nsecond line\n");
+    assert!(reply.text().starts_with("alpha: first line"), "{}", reply.text());
+
+    client.send(&call("read_file", json!({ "path": "notes.txt" }))).await;
+    let reply = client.reply_approving(false).await;
+    assert_eq!(reply.tool_results[0].1, "Error: The user refused this call");
+    assert_eq!(reply.text(), "alpha: Error: The user refused this call");
+
+    // Every file tool is offered, and asks first.
+    let tools: Vec<String> = server.backend.last_request()["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tool| tool["function"]["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(tools, ["current_time", "file_info", "grep", "list_directory", "read_file"]);
+}
+
+#[tokio::test]
+async fn runs_calls_without_asking_once_the_conversation_allows_it() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({})).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::SetAutoApprove(true)).await;
+    match client.recv().await {
+        ServerMessage::History(history) => assert!(history.auto_approve),
+        other => panic!("expected History, got {:?}", other),
+    }
+    // `reply` fails on approval requests.
+    client.send(&call("list_directory", json!({}))).await;
+    let reply = client.reply().await;
+    assert_eq!(reply.tool_results[0].1, "logo.png\nnotes.txt\nsrc/");
+
+    // The setting belongs to the conversation.
+    client.send(&ClientMessage::NewConversation).await;
+    match client.recv().await {
+        ServerMessage::History(history) => assert!(!history.auto_approve),
+        other => panic!("expected History, got {:?}", other),
+    }
+    client.send(&call("list_directory", json!({}))).await;
+    assert_eq!(client.reply_approving(true).await.approvals.len(), 1);
+}
+
+#[tokio::test]
+async fn stop_refuses_the_call_and_ends_the_reply() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({})).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&call("read_file", json!({ "path": "notes.txt" }))).await;
+    loop {
+        if let ServerMessage::ToolApproval(_) = client.recv().await {
+            break;
+        }
+    }
+    client.send(&ClientMessage::Stop).await;
+    let reply = client.reply().await;
+    assert_eq!(reply.tool_results[0].1, "Error: The user stopped the reply before this call ran");
+    assert!(reply.text().is_empty());
+    assert_eq!(server.backend.requests.lock().unwrap().len(), 1, "the model was not asked again");
+
+    let (_other, history, _) = server.connect().await;
+    let roles: Vec<Role> = history.active_path().iter().map(|m| m.role.clone()).collect();
+    assert_eq!(roles, [Role::User, Role::Assistant, Role::Tool, Role::Assistant]);
+}
+
+#[tokio::test]
+async fn refuses_every_call_left_when_the_user_leaves() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({})).await;
+    let (mut client, _, _) = server.connect().await;
+
+    let first = format!("{}read_file {}", TOOL_PROMPT, json!({ "path": "notes.txt" }));
+    let second = format!("{}list_directory {}", TOOL_PROMPT, json!({}));
+    client.send(&ClientMessage::Text(format!("{}\n{}", first, second))).await;
+    loop {
+        if let ServerMessage::ToolApproval(_) = client.recv().await {
+            break;
+        }
+    }
+    drop(client);
+
+    // Both calls get a result, so the conversation can go on.
+    for _ in 0..50 {
+        let (_other, history, _) = server.connect().await;
+        let path = history.active_path();
+        if path.len() == 4 {
+            assert_eq!(path[1].tool_calls.len(), 2);
+            for (message, call) in path[2..].iter().zip(["call_0", "call_1"]) {
+                assert_eq!(message.role, Role::Tool);
+                assert_eq!(message.tool_call_id.as_deref(), Some(call));
+                assert_eq!(message.content, "Error: The user left before this call ran");
+            }
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("the calls got no results");
+}
+
+#[tokio::test]
+async fn answers_approvals_only_while_asking() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({})).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::ApproveTool { call_id: "call_0".to_string(), approved: true }).await;
+    match client.recv().await {
+        ServerMessage::Error(e) => assert!(e.contains("not waiting for approval"), "{}", e),
+        other => panic!("expected Error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn lists_searches_and_describes_files() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({})).await;
+    let (mut client, _, _) = server.connect().await;
+    client.send(&ClientMessage::SetAutoApprove(true)).await;
+    client.recv().await;
+
+    assert_eq!(result(&mut client, "list_directory", json!({ "path": "src" })).await, "main.rs");
+    assert_eq!(result(&mut client, "grep", json!({ "pattern": "LINE", "ignore_case": true })).await, "notes.txt:1: first line\nnotes.txt:2: second line");
+    assert_eq!(result(&mut client, "grep", json!({ "pattern": "println", "path": "src" })).await, "src/main.rs:2: println!(\"hello\");");
+    assert_eq!(result(&mut client, "grep", json!({ "pattern": "nothing like this" })).await, "No matches");
+    assert!(result(&mut client, "grep", json!({ "pattern": "(" })).await.starts_with("Error: Invalid pattern"));
+
+    let info = result(&mut client, "file_info", json!({ "path": "src/main.rs" })).await;
+    assert!(info.starts_with("path: src/main.rs\ntype: file\nsize: 37 bytes\ncontent: text\nmodified: "), "{}", info);
+    let info = result(&mut client, "file_info", json!({ "path": "." })).await;
+    assert!(info.starts_with("path: .\ntype: directory\nentries: 3\n"), "{}", info);
+
+    assert_eq!(result(&mut client, "read_file", json!({ "path": "src/main.rs", "offset": 2 })).await, "    println!(\"hello\");\n}\n");
+    assert_eq!(result(&mut client, "read_file", json!({ "path": "logo.png" })).await, "Error: 'logo.png' is a binary file");
+    assert_eq!(result(&mut client, "read_file", json!({ "path": "notes.txt", "offset": 5 })).await, "Error: 'notes.txt' has only 2 lines");
+    assert_eq!(result(&mut client, "read_file", json!({})).await, "Error: Missing argument 'path'");
+}
+
+#[tokio::test]
+async fn returns_long_files_in_parts() {
+    let dir = TempDir::new();
+    let (server, workspace) = start(&dir, json!({ "max_file_bytes": 16, "max_results": 2 })).await;
+    std::fs::write(workspace.join("long.txt"), "one\ntwo\nthree\nfour\nfive\n").unwrap();
+    let (mut client, _, _) = server.connect().await;
+    client.send(&ClientMessage::SetAutoApprove(true)).await;
+    client.recv().await;
+
+    let part = result(&mut client, "read_file", json!({ "path": "long.txt" })).await;
+    assert_eq!(part, "one\ntwo\nthree\n[Lines 1-3 of long.txt; the file goes on. Read on with offset 4.]");
+    assert_eq!(result(&mut client, "read_file", json!({ "path": "long.txt", "offset": 4 })).await, "four\nfive\n");
+
+    let entries = result(&mut client, "list_directory", json!({})).await;
+    assert_eq!(entries, "logo.png\nlong.txt\n… and 2 more entries");
+    let matches = result(&mut client, "grep", json!({ "pattern": "e" })).await;
+    assert_eq!(matches.lines().count(), 3, "{}", matches);
+    assert!(matches.ends_with("[Stopped after 2 matches]"), "{}", matches);
+
+    // Lines over the limit are cut, and skipped whole when reading on.
+    std::fs::write(workspace.join("wide.txt"), format!("{}\nend\n", "x".repeat(100))).unwrap();
+    let part = result(&mut client, "read_file", json!({ "path": "wide.txt" })).await;
+    assert_eq!(part, format!("{}\n[Lines 1-1 of wide.txt; the file goes on. Read on with offset 2.]", "x".repeat(16)));
+    assert_eq!(result(&mut client, "read_file", json!({ "path": "wide.txt", "offset": 2 })).await, "end\n");
+
+    // A pipe would never end.
+    #[cfg(unix)]
+    {
+        assert!(std::process::Command::new("mkfifo").arg(workspace.join("pipe")).status().unwrap().success());
+        let output = result(&mut client, "read_file", json!({ "path": "pipe" })).await;
+        assert_eq!(output, "Error: 'pipe' is not a regular file");
+    }
+}
+
+#[tokio::test]
+async fn stays_inside_the_workspace() {
+    let dir = TempDir::new();
+    let (server, workspace) = start(&dir, json!({})).await;
+    let (mut client, _, _) = server.connect().await;
+    client.send(&ClientMessage::SetAutoApprove(true)).await;
+    client.recv().await;
+
+    let secret = dir.path().join("secret.txt");
+    let mut paths = vec![
+        "../secret.txt".to_string(),
+        "src/../../secret.txt".to_string(),
+        secret.display().to_string(),
+        "/etc/passwd".to_string(),
+    ];
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(&secret, workspace.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), workspace.join("parent")).unwrap();
+        paths.push("link.txt".to_string());
+        paths.push("parent/secret.txt".to_string());
+    }
+    for path in paths {
+        let output = result(&mut client, "read_file", json!({ "path": path })).await;
+        assert_eq!(output, format!("Error: '{}' is outside the workspace", path));
+    }
+
+    // Inside paths may be absolute, and searches do not follow links out.
+    let inside = workspace.canonicalize().unwrap().join("notes.txt");
+    assert_eq!(result(&mut client, "read_file", json!({ "path": inside })).await, "first line\nsecond line\n");
+    assert_eq!(result(&mut client, "grep", json!({ "pattern": "do not read" })).await, "No matches");
+}
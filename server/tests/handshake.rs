@@ -0,0 +1,132 @@
+//! Protocol handshake, versioning and request ids.
+
+mod common;
+
+use std::time::Duration;
+use common::{TempDir, TestServer};
+use serde_json::json;
+use shared::{ClientMessage, ServerMessage, PROTOCOL_VERSION};
+
+#[tokio::test]
+async fn welcomes_clients_of_the_same_version() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let mut client = server.connect_raw().await;
+    client.hello(PROTOCOL_VERSION).await;
+
+    let welcome = client.recv_frame().await;
+    assert_eq!(welcome.reply_to, Some(0));
+    match welcome.message {
+        ServerMessage::Welcome { version, capabilities, .. } => {
+            assert_eq!(version, PROTOCOL_VERSION);
+            assert!(capabilities.iter().any(|c| c == "stop"), "{:?}", capabilities);
+        }
+        other => panic!("expected Welcome, got {:?}", other),
+    }
+    assert!(matches!(client.recv().await, ServerMessage::History(_)));
+    assert!(matches!(client.recv().await, ServerMessage::AvailableModels(_)));
+}
+
+#[tokio::test]
+async fn rejects_other_versions() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let mut client = server.connect_raw().await;
+    client.hello(PROTOCOL_VERSION + 1).await;
+
+    match client.recv().await {
+        ServerMessage::Error(e) => assert!(e.contains("Incompatible protocol version"), "{}", e),
+        other => panic!("expected Error, got {:?}", other),
+    }
+    assert!(client.closed().await);
+}
+
+#[tokio::test]
+async fn rejects_clients_that_skip_the_handshake() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let mut client = server.connect_raw().await;
+    client.send_raw("what is the capital of France?").await;
+
+    match client.recv().await {
+        ServerMessage::Error(e) => assert!(e.contains("Expected Hello"), "{}", e),
+        other => panic!("expected Error, got {:?}", other),
+    }
+    assert!(client.closed().await);
+    assert!(server.backend.requests.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn reports_invalid_messages_instead_of_prompting() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+    client.send_raw(r#"{"Text": "not in a frame"}"#).await;
+
+    match client.recv().await {
+        ServerMessage::Error(e) => assert!(e.contains("Invalid message"), "{}", e),
+        other => panic!("expected Error, got {:?}", other),
+    }
+    assert!(server.backend.requests.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn responses_carry_the_request_id() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    let id = client.send(&ClientMessage::Text("hi".to_string())).await;
+    loop {
+        let frame = client.recv_frame().await;
+        assert_eq!(frame.reply_to, Some(id), "{:?}", frame.message);
+        if matches!(frame.message, ServerMessage::EndOfMessage) {
+            break;
+        }
+    }
+
+    let id = client.send(&ClientMessage::ListConversations).await;
+    let frame = client.recv_frame().await;
+    assert_eq!(frame.reply_to, Some(id));
+    assert!(matches!(frame.message, ServerMessage::Conversations(_)));
+}
+
+#[tokio::test]
+async fn legacy_protocol_serves_clients_without_a_handshake() {
+    let dir = TempDir::new();
+    let server = TestServer::start_with(&dir, json!({ "legacy_protocol": true })).await;
+    let mut client = server.connect_raw().await;
+
+    // A first frame that is not a hello makes the client a legacy one; it
+    // is sent bare messages and then answered.
+    client.send_raw("plain text prompt").await;
+    assert!(matches!(client.recv().await, ServerMessage::History(_)));
+    // Model names only, as before model details existed.
+    assert_eq!(client.recv_json().await, json!({ "AvailableModels": ["alpha", "beta"] }));
+    assert_eq!(client.reply().await.text(), "alpha: plain text prompt");
+}
+
+#[tokio::test]
+async fn legacy_protocol_waits_for_slow_hellos() {
+    let dir = TempDir::new();
+    let server = TestServer::start_with(&dir, json!({ "legacy_protocol": true })).await;
+    let mut client = server.connect_raw().await;
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    client.hello(PROTOCOL_VERSION).await;
+    assert!(matches!(client.recv().await, ServerMessage::Welcome { .. }));
+    assert!(matches!(client.recv().await, ServerMessage::History(_)));
+}
+
+#[tokio::test]
+async fn legacy_protocol_serves_silent_clients_soon() {
+    let dir = TempDir::new();
+    let server = TestServer::start_with(&dir, json!({ "legacy_protocol": true })).await;
+    let mut client = server.connect_raw().await;
+
+    // Old clients wait for the server to speak first.
+    let started = std::time::Instant::now();
+    assert!(matches!(client.recv().await, ServerMessage::History(_)));
+    assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+    assert_eq!(client.recv_json().await, json!({ "AvailableModels": ["alpha", "beta"] }));
+}
@@ -0,0 +1,177 @@
+//! Tools of MCP servers: discovery, calls, approval as configured per
+//! server, and servers that exit or hang.
+#![cfg(unix)]
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use common::{TempDir, TestClient, TestServer, TOOL_PROMPT};
+use serde_json::{json, Value};
+use shared::{ClientMessage, ServerMessage};
+
+/// An MCP server speaking just enough of the protocol for the tests. It
+/// appends every line it reads to `$LOG`, lists its tools in two pages (the
+/// second pointing on to `$CURSOR`, if set) and pings the client before
+/// echoing.
+const FAKE_SERVER: &str = r#"#!/bin/sh
+reply() { printf '{"jsonrpc":"2.0","id":%s,"result":%s}\n' "$id" "$1"; }
+echo "fake server started" >&2
+while IFS= read -r line; do
+  printf '%s\n' "$line" >> "$LOG"
+  id=$(printf '%s' "$line" | sed -n 's/^{"id":\([0-9]*\),.*/\1/p')
+  method=$(printf '%s' "$line" | sed -n 's/.*"method":"\([^"]*\)".*/\1/p')
+  case "$method" in
+    initialize)
+      reply '{"protocolVersion":"2024-11-05","capabilities":{"tools":{}},"serverInfo":{"name":"fake","version":"1.0"}}' ;;
+    tools/list)
+      case "$line" in
+        *'"cursor":"2"'*) reply '{"tools":[{"name":"fail","inputSchema":{"type":"object"}},{"name":"crash"},{"name":"slow.down"}]'"${CURSOR:+,\"nextCursor\":\"$CURSOR\"}"'}' ;;
+        *) reply '{"tools":[{"name":"echo","description":"Echoes text","inputSchema":{"type":"object","properties":{"text":{"type":"string"}}}}],"nextCursor":"2"}' ;;
+      esac ;;
+    tools/call)
+      case "$line" in
+        *'"name":"echo"'*)
+          printf '{"jsonrpc":"2.0","id":"ping-1","method":"ping"}\n'
+          IFS= read -r pong
+          printf '%s\n' "$pong" >> "$LOG"
+          text=$(printf '%s' "$line" | sed -n 's/.*"text":"\([^"]*\)".*/\1/p')
+          reply '{"content":[{"type":"text","text":"'"$text"'"},{"type":"image","data":"","mimeType":"image/png"}]}' ;;
+        *'"name":"fail"'*) reply '{"content":[{"type":"text","text":"it broke"}],"isError":true}' ;;
+        *'"name":"crash"'*) exit 1 ;;
+        *'"name":"slow.down"'*) sleep 2; reply '{"content":[]}' ;;
+      esac ;;
+  esac
+done
+"#;
+
+/// A server whose model `alpha` can call the tools of `servers`, each
+/// running the fake server unless it has a `command`. Returns the logs of
+/// the fake servers by name.
+async fn start(dir: &TempDir, servers: Value) -> (TestServer, impl Fn(&str) -> String) {
+    let script = dir.path().join("fake-mcp.sh");
+    std::fs::write(&script, FAKE_SERVER).unwrap();
+
+    let mut mcp = servers;
+    for (name, server) in mcp.as_object_mut().unwrap() {
+        if server.get("command").is_none() {
+            server["command"] = json!("sh");
+            server["args"] = json!([script]);
+            let mut env = server.get("env").cloned().unwrap_or_else(|| json!({}));
+            env["LOG"] = json!(log_path(dir.path(), name));
+            server["env"] = env;
+        }
+    }
+    let server = TestServer::start_with(dir, json!({
+        "models": { "alpha": { "capabilities": ["tools"] } },
+        "tools": { "mcp": mcp },
+    }))
+    .await;
+    let root = dir.path().to_path_buf();
+    (server, move |name: &str| std::fs::read_to_string(log_path(&root, name)).unwrap_or_default())
+}
+
+fn log_path(dir: &Path, server: &str) -> PathBuf {
+    dir.join(format!("{}.log", server))
+}
+
+async fn call(client: &mut TestClient, tool: &str, arguments: Value) {
+    client.send(&ClientMessage::Text(format!("{}{} {}", TOOL_PROMPT, tool, arguments))).await;
+}
+
+/// What a call of `tool` returned, approving it if asked.
+async fn result(client: &mut TestClient, tool: &str, arguments: Value) -> String {
+    call(client, tool, arguments).await;
+    client.reply_approving(true).await.tool_results.remove(0).1
+}
+
+#[tokio::test]
+async fn offers_and_calls_the_tools_of_each_server() {
+    let dir = TempDir::new();
+    let (server, log) = start(&dir, json!({ "fake": {}, "broken": { "command": "/no/such/mcp-server" } })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    call(&mut client, "fake__echo", json!({ "text": "hello" })).await;
+    let reply = client.reply_approving(true).await;
+    assert_eq!(reply.approvals.len(), 1);
+    assert_eq!(reply.tool_results[0].1, "hello\n[image content left out]");
+    assert_eq!(result(&mut client, "fake__fail", json!({})).await, "Error: it broke");
+
+    // Both pages of tools are offered, named after their server; the
+    // server that did not start is left out.
+    let request = server.backend.last_request();
+    let tools: Vec<&Value> = request["tools"].as_array().unwrap().iter().map(|tool| &tool["function"]).collect();
+    let names: Vec<&str> = tools.iter().map(|tool| tool["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["current_time", "fake__crash", "fake__echo", "fake__fail", "fake__slow_down"]);
+    assert_eq!(tools[2]["description"], "Echoes text");
+    assert_eq!(tools[2]["parameters"]["properties"]["text"]["type"], "string");
+
+    // The handshake came first, and the server's ping was answered.
+    let log = log("fake");
+    let lines: Vec<Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines[0]["method"], "initialize");
+    assert_eq!(lines[0]["params"]["protocolVersion"], "2024-11-05");
+    assert_eq!(lines[1]["method"], "notifications/initialized");
+    assert!(lines.iter().any(|line| line["method"] == "tools/call" && line["params"]["name"] == "echo"));
+    assert!(lines.contains(&json!({ "jsonrpc": "2.0", "id": "ping-1", "result": {} })), "{}", log);
+}
+
+#[tokio::test]
+async fn approves_calls_as_configured_for_their_server() {
+    let dir = TempDir::new();
+    let servers = json!({
+        "asking": {},
+        "careful": { "approval": "always" },
+        "trusted": { "approval": "never" },
+    });
+    let (server, _) = start(&dir, servers).await;
+    let (mut client, _, _) = server.connect().await;
+
+    // `reply` fails on approval requests.
+    call(&mut client, "trusted__echo", json!({ "text": "hi" })).await;
+    assert_eq!(client.reply().await.tool_results[0].1, "hi\n[image content left out]");
+    call(&mut client, "asking__echo", json!({ "text": "hi" })).await;
+    let reply = client.reply_approving(false).await;
+    assert_eq!(reply.approvals.len(), 1);
+    assert_eq!(reply.tool_results[0].1, "Error: The user refused this call");
+
+    client.send(&ClientMessage::SetAutoApprove(true)).await;
+    assert!(matches!(client.recv().await, ServerMessage::History(_)));
+    call(&mut client, "asking__echo", json!({ "text": "hi" })).await;
+    assert_eq!(client.reply().await.tool_results[0].1, "hi\n[image content left out]");
+    call(&mut client, "careful__echo", json!({ "text": "hi" })).await;
+    assert_eq!(client.reply_approving(true).await.approvals.len(), 1);
+}
+
+#[tokio::test]
+async fn starts_servers_again_after_they_exit() {
+    let dir = TempDir::new();
+    let (server, log) = start(&dir, json!({ "fake": { "approval": "never" } })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    assert_eq!(result(&mut client, "fake__crash", json!({})).await, "Error: The MCP server fake exited before answering");
+    assert_eq!(result(&mut client, "fake__echo", json!({ "text": "back" })).await, "back\n[image content left out]");
+    assert_eq!(log("fake").matches(r#""method":"initialize""#).count(), 2);
+}
+
+#[tokio::test]
+async fn gives_up_on_servers_that_do_not_answer() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({ "fake": { "approval": "never", "timeout_secs": 1 } })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    assert_eq!(result(&mut client, "fake__slow_down", json!({})).await, "Error: The MCP server fake did not answer within 1 s");
+}
+
+#[tokio::test]
+async fn stops_listing_when_a_cursor_comes_back() {
+    let dir = TempDir::new();
+    let (server, log) = start(&dir, json!({ "looping": { "env": { "CURSOR": "2" } } })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    call(&mut client, "looping__echo", json!({ "text": "hi" })).await;
+    client.reply_approving(true).await;
+    let request = server.backend.last_request();
+    let names: Vec<&str> = request["tools"].as_array().unwrap().iter().map(|tool| tool["function"]["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["current_time", "looping__crash", "looping__echo", "looping__fail", "looping__slow_down"]);
+    assert_eq!(log("looping").matches(r#""method":"tools/list""#).count(), 2);
+}
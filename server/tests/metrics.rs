@@ -0,0 +1,53 @@
+//! The Prometheus endpoint.
+
+mod common;
+
+use common::{TempDir, TestServer};
+use serde_json::json;
+use shared::{ClientMessage, ServerMessage};
+
+async fn scrape(server: &TestServer) -> String {
+    let res = reqwest::get(format!("http://{}/metrics", server.addr)).await.unwrap();
+    assert!(res.status().is_success());
+    let content_type = res.headers()["content-type"].to_str().unwrap().to_string();
+    assert!(content_type.starts_with("text/plain"), "{}", content_type);
+    res.text().await.unwrap()
+}
+
+/// Value of the sample `series`, e.g. `name{label="value"}`.
+fn sample(metrics: &str, series: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+#[tokio::test]
+async fn exports_request_generation_and_backend_metrics() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::Text("one two three".to_string())).await;
+    let reply = client.reply().await;
+    // Unlike WebSocket switches, the REST API waits for the model to load.
+    let res = reqwest::Client::new()
+        .put(format!("http://{}/api/model", server.addr))
+        .json(&json!({ "name": "beta" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    assert!(matches!(client.recv().await, ServerMessage::ModelChanged(_)));
+
+    let metrics = scrape(&server).await;
+    assert!(metrics.contains("# TYPE llamacpp_chat_generation_duration_seconds histogram"), "{}", metrics);
+    assert!(sample(&metrics, r#"llamacpp_chat_ws_requests_total{type="Text"}"#) >= Some(1.0), "{}", metrics);
+    assert!(sample(&metrics, r#"llamacpp_chat_generations_total{model="alpha",outcome="complete"}"#) >= Some(1.0));
+    assert!(sample(&metrics, r#"llamacpp_chat_completion_tokens_total{model="alpha"}"#) >= Some(reply.tokens.len() as f64));
+    assert!(sample(&metrics, r#"llamacpp_chat_backend_requests_total{status="200"}"#) >= Some(1.0));
+    assert!(sample(&metrics, r#"llamacpp_chat_backend_restarts_total{model="beta"}"#) >= Some(1.0));
+    assert!(sample(&metrics, r#"llamacpp_chat_model_load_duration_seconds_count{model="beta"}"#) >= Some(1.0));
+    assert!(sample(&metrics, "llamacpp_chat_ws_connections") >= Some(1.0));
+    assert_eq!(sample(&metrics, "llamacpp_chat_backend_requests_in_flight"), Some(0.0));
+}
@@ -0,0 +1,179 @@
+//! Model metadata read from GGUF headers, and fitting conversations into
+//! the context length it reports.
+
+mod common;
+
+use common::{write_gguf, FakeBackend, TempDir, TestServer};
+use serde_json::{json, Value};
+use shared::{Capability, ClientMessage, GgufMetadata, ModelState, ServerMessage};
+
+/// A mock model whose `path` is a GGUF header trained for `context` tokens.
+fn mock_with_header(dir: &TempDir, context: u64) -> Value {
+    let path = dir.path().join("tiny.gguf");
+    write_gguf(
+        &path,
+        json!({
+            "general.architecture": "llama",
+            "general.name": "Tiny Llama",
+            "general.file_type": 15,
+            "llama.context_length": context,
+            "tokenizer.ggml.model": "llama",
+            "tokenizer.ggml.tokens": ["<s>", "</s>", "hello"],
+            "tokenizer.chat_template": "{% for m in messages %}{{ m.content }}{% endfor %}",
+        }),
+        &[&[4096, 32000], &[4096]],
+    );
+    json!({ "models": { "tiny": { "backend": "mock", "path": path } } })
+}
+
+#[tokio::test]
+async fn lists_models_with_their_gguf_metadata() {
+    let dir = TempDir::new();
+    let server = TestServer::start_with(&dir, mock_with_header(&dir, 2048)).await;
+    let (_client, _, models) = server.connect().await;
+
+    let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["alpha", "beta", "tiny"]);
+    assert_eq!(models[0].metadata, None);
+    assert_eq!(
+        models[2].metadata,
+        Some(GgufMetadata {
+            architecture: Some("llama".to_string()),
+            name: Some("Tiny Llama".to_string()),
+            parameters: Some(4096 * 32000 + 4096),
+            quantization: Some("Q4_K_M".to_string()),
+            context_length: Some(2048),
+            chat_template: Some("{% for m in messages %}{{ m.content }}{% endfor %}".to_string()),
+            tokenizer: Some("llama".to_string()),
+            vocab_size: Some(3),
+            embedding: false,
+        })
+    );
+
+    let rest: Value = reqwest::get(format!("http://{}/api/models", server.addr)).await.unwrap().json().await.unwrap();
+    let tiny = rest.as_array().unwrap().iter().find(|m| m["name"] == "tiny").unwrap();
+    assert_eq!(tiny["metadata"]["quantization"], "Q4_K_M");
+}
+
+#[tokio::test]
+async fn describes_models_for_selection() {
+    let dir = TempDir::new();
+    let mut config = mock_with_header(&dir, 2048);
+    config["models"]["tiny"]["tags"] = json!(["small"]);
+    config["models"]["labelled"] = json!({
+        "backend": "mock",
+        "display_name": "Labelled",
+        "description": "Configured by hand",
+        "capabilities": ["tools", "vision"],
+    });
+    let server = TestServer::start_with(&dir, config).await;
+    let (mut client, _, models) = server.connect().await;
+
+    let tiny = models.iter().find(|m| m.name == "tiny").unwrap();
+    assert_eq!(tiny.display_name, "Tiny Llama", "named by its header");
+    assert_eq!(tiny.tags, ["small"]);
+    assert_eq!(tiny.backend, "mock");
+    assert_eq!(tiny.capabilities, [], "its template does not render tools");
+    assert_eq!(tiny.context_size, Some(2048));
+    assert!(tiny.file_size.is_some_and(|size| size > 0));
+    assert_eq!(tiny.state, ModelState::Available);
+
+    let labelled = models.iter().find(|m| m.name == "labelled").unwrap();
+    assert_eq!(labelled.display_name, "Labelled");
+    assert_eq!(labelled.description.as_deref(), Some("Configured by hand"));
+    assert_eq!(labelled.capabilities, [Capability::Tools, Capability::Vision]);
+    assert_eq!(labelled.context_size, None);
+
+    let alpha = models.iter().find(|m| m.name == "alpha").unwrap();
+    assert_eq!(alpha.display_name, "alpha");
+    assert_eq!(alpha.state, ModelState::Loaded);
+
+    client.send(&ClientMessage::SetModel("tiny".to_string())).await;
+    assert!(matches!(client.recv().await, ServerMessage::ModelChanged(model) if model == "tiny"));
+    client.send(&ClientMessage::RescanModels).await;
+    match client.recv().await {
+        ServerMessage::AvailableModels(models) => {
+            let states: Vec<_> = models.iter().map(|m| (m.name.as_str(), m.state)).collect();
+            assert_eq!(
+                states,
+                [("alpha", ModelState::Available), ("beta", ModelState::Available), ("labelled", ModelState::Available), ("tiny", ModelState::Loaded)]
+            );
+        }
+        other => panic!("expected AvailableModels, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn leaves_out_old_messages_beyond_the_trained_context() {
+    let dir = TempDir::new();
+    let server = TestServer::start_with(&dir, mock_with_header(&dir, 64)).await;
+    let (mut client, _, _) = server.connect().await;
+    client.send(&ClientMessage::SetModel("tiny".to_string())).await;
+    client.recv().await;
+
+    // Ten words, about twenty tokens with the template: two messages and
+    // the reply they get no longer fit 64 tokens.
+    let prompt = "alpha bravo charlie delta echo foxtrot golf hotel india juliet";
+    client.send(&ClientMessage::Text(prompt.to_string())).await;
+    let first = client.reply().await;
+    assert_eq!(first.stats.unwrap().prompt_tokens, Some(10));
+
+    client.send(&ClientMessage::Text(prompt.to_string())).await;
+    let second = client.reply().await;
+    assert!(second.errors.is_empty(), "{:?}", second.errors);
+    assert_eq!(second.stats.unwrap().prompt_tokens, Some(10));
+}
+
+#[tokio::test]
+async fn marks_a_default_model_serving_nothing_as_failed() {
+    let dir = TempDir::new();
+    // The fake backend lists no model in /v1/models.
+    let external = FakeBackend::start().await;
+    let config = json!({
+        "models": { "attached": { "backend": "external", "url": external.url } },
+        "default": "attached",
+    });
+    let server = TestServer::start_with(&dir, config).await;
+    let (mut client, _, mut models) = server.connect().await;
+
+    // Checked in the background, so possibly only after connecting.
+    let attached = loop {
+        let attached = models.iter().find(|m| m.name == "attached").unwrap().clone();
+        if attached.state != ModelState::Loaded {
+            break attached;
+        }
+        models = match client.recv().await {
+            ServerMessage::AvailableModels(models) => models,
+            other => panic!("expected AvailableModels, got {:?}", other),
+        };
+    };
+    assert_eq!(attached.state, ModelState::Failed);
+    assert!(attached.error.unwrap().contains("does not list a loaded model"));
+}
+
+#[tokio::test]
+async fn offers_tools_to_managed_models_only_with_jinja() {
+    let dir = TempDir::new();
+    let path = dir.path().join("caller.gguf");
+    write_gguf(
+        &path,
+        json!({
+            "general.architecture": "llama",
+            "tokenizer.chat_template": "{% if tools %}{{ tools | tojson }}{% endif %}",
+        }),
+        &[&[16]],
+    );
+    let server = TestServer::start_with(&dir, json!({
+        "models": {
+            "plain": { "path": path },
+            "jinja": { "path": path, "args": ["--jinja"] },
+        },
+    }))
+    .await;
+    let (_client, _, models) = server.connect().await;
+
+    // Without `--jinja` llama-server refuses requests with tools.
+    let capabilities = |name: &str| models.iter().find(|m| m.name == name).unwrap().capabilities.clone();
+    assert_eq!(capabilities("plain"), []);
+    assert_eq!(capabilities("jinja"), [Capability::Tools]);
+}
@@ -0,0 +1,116 @@
+//! Resource monitoring of managed llama-server processes and the memory
+//! check before loading one.
+
+mod common;
+
+use std::time::Duration;
+use common::{TempDir, TestServer};
+use serde_json::{json, Value};
+use shared::{ClientMessage, ModelState, ServerMessage};
+
+async fn status(server: &TestServer) -> Value {
+    reqwest::get(format!("http://{}/api/status", server.addr)).await.unwrap().json().await.unwrap()
+}
+
+#[tokio::test]
+async fn refuses_models_that_do_not_fit_in_memory() {
+    let dir = TempDir::new();
+    let model = dir.path().join("huge.gguf");
+    std::fs::write(&model, b"GGUF").unwrap();
+    let server = TestServer::start_with(&dir, json!({
+        "memory_check": true,
+        "models": {
+            "huge": { "path": model, "args": ["-c", "100000000000"] },
+        },
+    }))
+    .await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::SetModel("huge".to_string())).await;
+    match client.recv().await {
+        ServerMessage::Error(e) => assert!(e.contains("needs about"), "{}", e),
+        other => panic!("expected Error, got {:?}", other),
+    }
+    // The model list shows why.
+    match client.recv().await {
+        ServerMessage::AvailableModels(models) => {
+            let huge = models.iter().find(|m| m.name == "huge").unwrap();
+            assert_eq!(huge.state, ModelState::Failed);
+            assert!(huge.error.as_deref().is_some_and(|e| e.contains("needs about")), "{:?}", huge.error);
+            assert_eq!(models.iter().find(|m| m.name == "alpha").unwrap().state, ModelState::Loaded);
+        }
+        other => panic!("expected AvailableModels, got {:?}", other),
+    }
+
+    // The previous model keeps serving.
+    client.send(&ClientMessage::Text("still here".to_string())).await;
+    assert_eq!(client.reply().await.text(), "alpha: still here");
+    assert_eq!(status(&server).await["model"], "alpha");
+}
+
+/// A server whose default model `tiny` runs on a stand-in llama-server
+/// that stays alive but never becomes ready.
+async fn start_with_stand_in(dir: &TempDir) -> TestServer {
+    let bin = dir.path().join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let script = bin.join("llama-server");
+    std::fs::write(&script, "#!/bin/sh\nexec sleep 60\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var("PATH", format!("{}:{}", bin.display(), path));
+
+    let model = dir.path().join("tiny.gguf");
+    std::fs::write(&model, b"GGUF").unwrap();
+    TestServer::start_with(dir, json!({
+        "default": "tiny",
+        "models": { "tiny": { "path": model } },
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn samples_the_managed_process() {
+    let dir = TempDir::new();
+    let server = start_with_stand_in(&dir).await;
+
+    // The first sample is taken right after start, in the background.
+    let mut usage = Value::Null;
+    for _ in 0..50 {
+        usage = status(&server).await["usage"].clone();
+        if !usage.is_null() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(usage["model"], "tiny", "{}", usage);
+    assert!(usage["rss_bytes"].as_u64().unwrap() > 0, "{}", usage);
+    assert!(usage["threads"].as_u64().unwrap() >= 1, "{}", usage);
+
+    // Clients get the next sample pushed.
+    let (mut client, _, _) = server.connect().await;
+    match tokio::time::timeout(Duration::from_secs(10), client.recv()).await.unwrap() {
+        ServerMessage::ResourceUsage(usage) => {
+            assert_eq!(usage.model, "tiny");
+            assert!(usage.cpu_percent.is_some(), "{:?}", usage);
+        }
+        other => panic!("expected ResourceUsage, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn serves_while_the_default_model_loads() {
+    let dir = TempDir::new();
+    let server = start_with_stand_in(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    // Checking the default model in the background holds up nothing.
+    assert_eq!(status(&server).await["model"], "tiny");
+    client.send(&ClientMessage::SetModel("alpha".to_string())).await;
+    assert!(matches!(client.recv().await, ServerMessage::ModelChanged(model) if model == "alpha"));
+    client.send(&ClientMessage::Text("hello".to_string())).await;
+    assert_eq!(client.reply().await.text(), "alpha: hello");
+}
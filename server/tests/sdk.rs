@@ -0,0 +1,108 @@
+//! The client library against a real server: handshake, streaming and
+//! stopping a reply.
+
+mod common;
+
+use std::time::Duration;
+use common::{TempDir, TestServer, SLOW_PROMPT};
+use sdk::{ChatClient, Events};
+use shared::{Role, ServerMessage};
+
+async fn connect(server: &TestServer) -> (ChatClient, sdk::Session, Events) {
+    ChatClient::connect(&format!("ws://{}/ws", server.addr)).await.unwrap()
+}
+
+async fn next(events: &mut Events) -> ServerMessage {
+    tokio::time::timeout(Duration::from_secs(10), events.next())
+        .await
+        .expect("timed out waiting for the server")
+        .expect("connection closed")
+        .unwrap()
+        .message
+}
+
+/// Collects tokens up to `EndOfMessage`.
+async fn reply(events: &mut Events) -> String {
+    let mut text = String::new();
+    loop {
+        match next(events).await {
+            ServerMessage::Token(token) => text.push_str(&token),
+            ServerMessage::Stats(_) => {}
+            ServerMessage::EndOfMessage => return text,
+            other => panic!("unexpected message while streaming: {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn handshake_returns_history_and_models() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (_client, session, _events) = connect(&server).await;
+
+    assert!(session.capabilities.iter().any(|c| c == "stop"));
+    assert_eq!(session.history.current_model, "alpha");
+    assert!(session.history.messages.is_empty());
+    let names: Vec<&str> = session.models.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["alpha", "beta"]);
+}
+
+#[tokio::test]
+async fn streams_replies_as_events() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (client, _, mut events) = connect(&server).await;
+
+    client.send_text("hello there").await.unwrap();
+    assert_eq!(reply(&mut events).await, "alpha: hello there");
+
+    client.set_model("beta").await.unwrap();
+    match next(&mut events).await {
+        ServerMessage::ModelChanged(model) => assert_eq!(model, "beta"),
+        other => panic!("expected ModelChanged, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn stop_keeps_the_partial_reply() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (client, _, mut events) = connect(&server).await;
+
+    let prompt = format!("{} one two three four five six seven eight nine ten", SLOW_PROMPT);
+    client.send_text(prompt.as_str()).await.unwrap();
+    let first = match next(&mut events).await {
+        ServerMessage::Token(token) => token,
+        other => panic!("expected Token, got {:?}", other),
+    };
+    client.stop().await.unwrap();
+    let partial = format!("{}{}", first, reply(&mut events).await);
+
+    let full = format!("alpha: {}", prompt);
+    assert!(partial.len() < full.len(), "{:?}", partial);
+    assert!(full.starts_with(&partial), "{:?}", partial);
+
+    // The partial reply is stored like a complete one.
+    drop(client);
+    let (_client, session, _events) = connect(&server).await;
+    let path = session.history.active_path();
+    assert_eq!(path.len(), 2);
+    assert_eq!(path[1].role, Role::Assistant);
+    assert_eq!(path[1].content, partial);
+}
+
+#[tokio::test]
+async fn messages_sent_while_streaming_are_handled_afterwards() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (client, _, mut events) = connect(&server).await;
+
+    client.send_text(format!("{} a b c", SLOW_PROMPT)).await.unwrap();
+    client.list_conversations().await.unwrap();
+
+    assert_eq!(reply(&mut events).await, format!("alpha: {} a b c", SLOW_PROMPT));
+    match next(&mut events).await {
+        ServerMessage::Conversations(conversations) => assert_eq!(conversations.len(), 1),
+        other => panic!("expected Conversations, got {:?}", other),
+    }
+}
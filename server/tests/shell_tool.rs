@@ -0,0 +1,201 @@
+//! The command tool: approval of every call, the allow and deny lists,
+//! and what is kept of a command's run.
+#![cfg(unix)]
+
+mod common;
+
+use std::path::PathBuf;
+use common::{TempDir, TestClient, TestServer, TOOL_PROMPT};
+use serde_json::{json, Value};
+use shared::{ClientMessage, Role, ServerMessage};
+
+/// A server whose model `alpha` can run commands in a directory holding
+/// one file.
+async fn start(dir: &TempDir, shell: Value) -> (TestServer, PathBuf) {
+    let cwd = dir.path().join("project");
+    std::fs::create_dir_all(&cwd).unwrap();
+    std::fs::write(cwd.join("Cargo.toml"), "").unwrap();
+
+    let mut shell = shell;
+    shell["cwd"] = json!(cwd);
+    let server = TestServer::start_with(dir, json!({
+        "models": { "alpha": { "capabilities": ["tools"] } },
+        "tools": { "shell": shell },
+    }))
+    .await;
+    (server, cwd)
+}
+
+async fn run(client: &mut TestClient, command: &str) {
+    let arguments = json!({ "command": command });
+    client.send(&ClientMessage::Text(format!("{}run_command {}", TOOL_PROMPT, arguments))).await;
+}
+
+/// What an approved run of `command` returned.
+async fn output(client: &mut TestClient, command: &str) -> String {
+    run(client, command).await;
+    client.reply_approving(true).await.tool_results.remove(0).1
+}
+
+#[tokio::test]
+async fn asks_every_time_and_keeps_the_run() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({ "allow": ["ls"] })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    // Even where other tools run without asking.
+    client.send(&ClientMessage::SetAutoApprove(true)).await;
+    assert!(matches!(client.recv().await, ServerMessage::History(_)));
+    run(&mut client, "ls").await;
+    let reply = client.reply_approving(true).await;
+    assert_eq!(reply.approvals.len(), 1);
+    assert_eq!(reply.approvals[0].name, "run_command");
+    let result = "$ ls\nexit code: 0\nstdout:\nCargo.toml";
+    assert_eq!(reply.tool_results[0].1, result);
+
+    // Command, exit code and output are part of the conversation.
+    let (_other, history, _) = server.connect().await;
+    let path = history.active_path();
+    assert_eq!(path[1].tool_calls[0].arguments, r#"{"command":"ls"}"#);
+    assert_eq!(path[2].role, Role::Tool);
+    assert_eq!(path[2].content, result);
+}
+
+#[tokio::test]
+async fn refused_commands_do_not_run() {
+    let dir = TempDir::new();
+    let (server, cwd) = start(&dir, json!({ "allow": ["touch"] })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    run(&mut client, "touch ran").await;
+    let reply = client.reply_approving(false).await;
+    assert_eq!(reply.tool_results[0].1, "Error: The user refused this call");
+    assert!(!cwd.join("ran").exists());
+}
+
+#[tokio::test]
+async fn runs_only_what_the_lists_let_through() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({ "allow": ["echo", "git log"], "deny": ["echo secret"] })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    assert_eq!(output(&mut client, "echo 'hello  world'").await, "$ echo 'hello  world'\nexit code: 0\nstdout:\nhello  world");
+    // Refused without asking: `reply` fails on approval requests.
+    for (command, error) in [
+        ("rm -rf /", "Error: 'rm -rf /' is not allowed on this server; allowed are: echo, git log"),
+        ("git push", "Error: 'git push' is not allowed on this server; allowed are: echo, git log"),
+        ("/tmp/echo hi", "Error: '/tmp/echo hi' is not allowed on this server; allowed are: echo, git log"),
+        ("echo secret stuff", "Error: 'echo secret stuff' is not allowed on this server"),
+        ("echo hi | rm x", "Error: '|' needs a shell, and commands run without one; run one program at a time"),
+        ("echo $HOME", "Error: '$' needs a shell, and commands run without one; run one program at a time"),
+        ("echo 'unfinished", "Error: Unfinished quote in the command"),
+    ] {
+        run(&mut client, command).await;
+        assert_eq!(client.reply().await.tool_results.remove(0).1, error);
+    }
+}
+
+#[tokio::test]
+async fn refuses_wrappers_and_denied_words_anywhere() {
+    let dir = TempDir::new();
+    let allow = ["echo", "cargo", "git", "find", "env", "find -delete"];
+    let (server, _) = start(&dir, json!({ "allow": allow, "deny": ["rm", "cargo publish", "git push"] })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    // Refused without asking: `reply` fails on approval requests.
+    for (command, error) in [
+        ("cargo +stable publish", "Error: 'cargo +stable publish' is not allowed on this server"),
+        ("cargo --locked publish --dry-run", "Error: 'cargo --locked publish --dry-run' is not allowed on this server"),
+        ("git -C . push", "Error: 'git -C . push' is not allowed on this server"),
+        ("env rm -rf x", "Error: 'env rm -rf x' is not allowed on this server"),
+        ("env /bin/rm x", "Error: 'env /bin/rm x' is not allowed on this server"),
+        ("sh -c 'rm x'", "Error: 'sh' runs other programs, so it only runs if allowed by name"),
+        ("/usr/bin/env ls", "Error: '/usr/bin/env' runs other programs, so it only runs if allowed by name"),
+        ("xargs ls", "Error: 'xargs' runs other programs, so it only runs if allowed by name"),
+        ("find . -exec ls '{}' '+'", "Error: 'find' with -exec is not allowed on this server"),
+    ] {
+        run(&mut client, command).await;
+        assert_eq!(client.reply().await.tool_results.remove(0).1, error);
+    }
+
+    // Named wrappers and options pass.
+    assert_eq!(output(&mut client, "env echo hi").await, "$ env echo hi\nexit code: 0\nstdout:\nhi");
+    assert_eq!(output(&mut client, "find . -name nothing -delete").await, "$ find . -name nothing -delete\nexit code: 0\n(no output)");
+}
+
+#[tokio::test]
+async fn is_left_out_without_allowed_commands() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({ "deny": ["rm"] })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    run(&mut client, "ls").await;
+    assert_eq!(client.reply().await.tool_results.remove(0).1, "Error: Unknown tool 'run_command'");
+    let tools = server.backend.last_request()["tools"].clone();
+    assert_eq!(tools.as_array().unwrap().len(), 1, "{}", tools);
+}
+
+#[tokio::test]
+async fn reports_failures_and_cuts_long_output() {
+    let dir = TempDir::new();
+    let allow = ["sh", "echo", "true", "sleep", "no-such-program", "/bin/rm"];
+    let (server, _) = start(&dir, json!({ "allow": allow, "deny": ["rm"], "timeout_secs": 1, "max_output_bytes": 10 })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    assert_eq!(
+        output(&mut client, "sh -c 'echo oops >&2; exit 3'").await,
+        "$ sh -c 'echo oops >&2; exit 3'\nexit code: 3\nstderr:\noops"
+    );
+    assert_eq!(
+        output(&mut client, "echo 0123456789abcdefghij").await,
+        "$ echo 0123456789abcdefghij\nexit code: 0\nstdout:\n01234\n[… 11 bytes left out …]\nghij"
+    );
+    assert_eq!(output(&mut client, "true").await, "$ true\nexit code: 0\n(no output)");
+    assert_eq!(output(&mut client, "sleep 5").await, "Error: 'sleep 5' did not finish within 1 s and was stopped");
+    assert!(output(&mut client, "no-such-program").await.starts_with("Error: Cannot run 'no-such-program'"));
+
+    // Denied programs match by name, wherever they are.
+    run(&mut client, "/bin/rm -rf x").await;
+    assert_eq!(client.reply().await.tool_results.remove(0).1, "Error: '/bin/rm -rf x' is not allowed on this server");
+}
+
+#[tokio::test]
+async fn stops_commands_that_write_without_end() {
+    let dir = TempDir::new();
+    let (server, _) = start(&dir, json!({ "allow": ["yes"], "timeout_secs": 30, "max_output_bytes": 8 })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    let started = std::time::Instant::now();
+    let output = output(&mut client, "yes").await;
+    assert!(started.elapsed() < std::time::Duration::from_secs(20), "stopped by the timeout");
+    let (status, rest) = output.split_once("\nstdout:\n").unwrap();
+    assert_eq!(status, "$ yes\nexit code: none, stopped after 16.0 MiB of output");
+    let (head, tail) = rest.split_once(" bytes left out …]\n").unwrap();
+    assert!(head.starts_with("y\ny\n\n[… "), "{}", head);
+    assert_eq!(tail, "y\ny");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn stops_what_the_command_started() {
+    let dir = TempDir::new();
+    let (server, cwd) = start(&dir, json!({ "allow": ["sh"], "timeout_secs": 1 })).await;
+    let (mut client, _, _) = server.connect().await;
+
+    // The background `sleep` holds the output open after `sh` is killed.
+    let command = "sh -c 'sleep 30 & echo $! > sleep.pid; wait'";
+    let error = format!("Error: '{}' did not finish within 1 s and was stopped", command);
+    assert_eq!(output(&mut client, command).await, error);
+
+    let pid = std::fs::read_to_string(cwd.join("sleep.pid")).unwrap();
+    let stat = format!("/proc/{}/stat", pid.trim());
+    for _ in 0..50 {
+        // Gone, or waiting to be reaped after being killed.
+        match std::fs::read_to_string(&stat) {
+            Err(_) => return,
+            Ok(stat) if stat.rsplit(')').next().is_some_and(|rest| rest.trim_start().starts_with('Z')) => return,
+            Ok(_) => tokio::time::sleep(std::time::Duration::from_millis(50)).await,
+        }
+    }
+    panic!("sleep {} still runs", pid.trim());
+}
@@ -0,0 +1,59 @@
+//! Generation statistics sent with each reply.
+
+mod common;
+
+use common::{TempDir, TestServer, FAKE_PROMPT_TOKENS, FAKE_TIMINGS};
+use serde_json::json;
+use shared::{ClientMessage, ServerMessage};
+
+#[tokio::test]
+async fn reports_backend_usage_and_timings() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::Text("count these words".to_string())).await;
+    let reply = client.reply().await;
+    let stats = reply.stats.expect("no stats");
+
+    assert_eq!(server.backend.last_request()["stream_options"]["include_usage"], true);
+    assert_eq!(stats.prompt_tokens, Some(FAKE_PROMPT_TOKENS));
+    assert_eq!(stats.completion_tokens, Some(reply.tokens.len() as u64));
+    assert_eq!(stats.prompt_per_second, Some(FAKE_TIMINGS.prompt_per_second));
+    assert_eq!(stats.tokens_per_second, Some(FAKE_TIMINGS.predicted_per_second));
+    let ttft = stats.ttft_ms.expect("no time to first token");
+    assert!(ttft <= stats.total_ms, "{:?}", stats);
+}
+
+#[tokio::test]
+async fn stores_stats_with_the_message() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+    client.send(&ClientMessage::Text("hello".to_string())).await;
+    let stats = client.reply().await.stats;
+
+    let (_, history, _) = server.connect().await;
+    let reply = history.active_path().last().cloned().unwrap();
+    assert!(stats.is_some());
+    assert_eq!(reply.stats, stats);
+}
+
+#[tokio::test]
+async fn measures_speed_without_backend_timings() {
+    let dir = TempDir::new();
+    let models = json!({ "models": { "echo": { "backend": "mock", "mock": { "delay_ms": 10 } } } });
+    let server = TestServer::start_with(&dir, models).await;
+    let (mut client, _, _) = server.connect().await;
+    client.send(&ClientMessage::SetModel("echo".to_string())).await;
+    assert!(matches!(client.recv().await, ServerMessage::ModelChanged(_)));
+
+    client.send(&ClientMessage::Text("one two three four".to_string())).await;
+    let stats = client.reply().await.stats.expect("no stats");
+
+    // The mock counts words as tokens.
+    assert_eq!(stats.prompt_tokens, Some(4));
+    assert_eq!(stats.completion_tokens, Some(4));
+    assert_eq!(stats.prompt_per_second, None);
+    assert!(stats.tokens_per_second.is_some_and(|speed| speed > 0.0), "{:?}", stats);
+}
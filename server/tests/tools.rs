@@ -0,0 +1,111 @@
+//! Tool calls: offering tools to models that support them, running the
+//! calls and feeding the results back until the model answers.
+
+mod common;
+
+use common::{TempDir, TestServer, TOOL_PROMPT};
+use serde_json::json;
+use shared::{ClientMessage, Role, ServerMessage};
+
+/// A server whose default model `alpha` can call tools.
+async fn start(dir: &TempDir) -> TestServer {
+    TestServer::start_with(dir, json!({ "models": { "alpha": { "capabilities": ["tools"] } } })).await
+}
+
+#[tokio::test]
+async fn runs_tools_until_the_model_answers() {
+    let dir = TempDir::new();
+    let server = start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::Text(format!("{}current_time {{}}", TOOL_PROMPT))).await;
+    let reply = client.reply().await;
+    assert!(reply.errors.is_empty(), "{:?}", reply.errors);
+    assert_eq!(reply.tool_calls.len(), 1);
+    assert_eq!(reply.tool_calls[0].name, "current_time");
+    assert_eq!(reply.tool_calls[0].arguments, "{}", "streamed in two pieces");
+    let (call_id, time) = &reply.tool_results[0];
+    assert_eq!(call_id, &reply.tool_calls[0].id);
+    assert!(time.contains('T') && time.ends_with('Z'), "{}", time);
+    assert_eq!(reply.text(), format!("alpha: {}", time), "the model saw the result");
+
+    // Both requests offered the tool; the second carried the call and its result.
+    let requests = server.backend.requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0]["tools"][0]["function"]["name"], "current_time");
+    let messages = requests[1]["messages"].as_array().unwrap();
+    assert_eq!(messages[1]["tool_calls"][0]["function"]["name"], "current_time");
+    assert_eq!(messages[2]["role"], "tool");
+    assert_eq!(messages[2]["tool_call_id"], call_id.as_str());
+
+    // The history keeps every step.
+    let (_other, history, _) = server.connect().await;
+    let path = history.active_path();
+    let roles: Vec<Role> = path.iter().map(|m| m.role.clone()).collect();
+    assert_eq!(roles, [Role::User, Role::Assistant, Role::Tool, Role::Assistant]);
+    assert_eq!(path[1].tool_calls, reply.tool_calls);
+    assert!(path[1].stats.is_some());
+    assert_eq!(path[2].tool_call_id.as_deref(), Some(call_id.as_str()));
+    assert_eq!(path[2].content, *time);
+    assert_eq!(history.tool_call(call_id).unwrap().name, "current_time");
+}
+
+#[tokio::test]
+async fn tells_the_model_when_a_call_fails() {
+    let dir = TempDir::new();
+    let server = start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::Text(format!("{}rm_rf {{}}", TOOL_PROMPT))).await;
+    let reply = client.reply().await;
+    assert_eq!(reply.tool_results[0].1, "Error: Unknown tool 'rm_rf'");
+    assert_eq!(reply.text(), "alpha: Error: Unknown tool 'rm_rf'");
+
+    client.send(&ClientMessage::Text(format!("{}current_time {{oops", TOOL_PROMPT))).await;
+    let reply = client.reply().await;
+    assert!(reply.tool_results[0].1.starts_with("Error: Arguments are not valid JSON"), "{:?}", reply.tool_results);
+}
+
+#[tokio::test]
+async fn offers_no_tools_to_models_without_the_capability() {
+    let dir = TempDir::new();
+    let server = start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+    client.send(&ClientMessage::SetModel("beta".to_string())).await;
+    assert!(matches!(client.recv().await, ServerMessage::ModelChanged(_)));
+
+    client.send(&ClientMessage::Text(format!("{}current_time {{}}", TOOL_PROMPT))).await;
+    let reply = client.reply().await;
+    assert!(reply.tool_calls.is_empty(), "{:?}", reply.tool_calls);
+    assert!(server.backend.last_request().get("tools").is_none());
+}
+
+#[tokio::test]
+async fn makes_the_model_answer_after_the_last_round() {
+    let dir = TempDir::new();
+    let server = TestServer::start_with(&dir, json!({
+        "models": {
+            "caller": {
+                "backend": "mock",
+                "capabilities": ["tools"],
+                "mock": { "mode": "script", "script": [{ "tool": "current_time" }] },
+            },
+        },
+        "tools": { "max_rounds": 2 },
+    }))
+    .await;
+    let (mut client, _, _) = server.connect().await;
+    client.send(&ClientMessage::SetModel("caller".to_string())).await;
+    assert!(matches!(client.recv().await, ServerMessage::ModelChanged(_)));
+
+    // The mock asks for the tool every time; the third request offers none.
+    client.send(&ClientMessage::Text("What time is it?".to_string())).await;
+    let reply = client.reply().await;
+    assert_eq!(reply.tool_calls.len(), 2);
+    assert_eq!(reply.tool_results.len(), 2);
+
+    let (_other, history, _) = server.connect().await;
+    let last = history.active_path().last().cloned().unwrap();
+    assert_eq!(last.role, Role::Assistant);
+    assert!(last.tool_calls.is_empty());
+}
@@ -0,0 +1,260 @@
+//! End-to-end tests of the WebSocket protocol.
+
+mod common;
+
+use common::{TempDir, TestServer, FAIL_PROMPT};
+use serde_json::json;
+use server::store::ConversationStore;
+use shared::{ChatHistory, ClientMessage, Role, ServerMessage};
+
+#[tokio::test]
+async fn sends_stored_history_and_models_on_connect() {
+    let dir = TempDir::new();
+    let store = ConversationStore::open(dir.path().join("conversations")).unwrap();
+    let mut stored = ChatHistory::new("alpha");
+    stored.push(Role::User, "Hi".to_string());
+    stored.push(Role::Assistant, "Hello!".to_string());
+    store.save(&mut stored).unwrap();
+
+    let server = TestServer::start(&dir).await;
+    let (_client, history, models) = server.connect().await;
+
+    assert_eq!(history.id, stored.id);
+    let contents: Vec<&str> = history.active_path().iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(contents, ["Hi", "Hello!"]);
+    let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["alpha", "beta"]);
+}
+
+#[tokio::test]
+async fn streams_reply_tokens() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::Text("hello there world".to_string())).await;
+    let reply = client.reply().await;
+
+    assert!(reply.errors.is_empty(), "{:?}", reply.errors);
+    assert!(reply.tokens.len() > 1, "reply was not streamed: {:?}", reply.tokens);
+    assert_eq!(reply.text(), "alpha: hello there world");
+}
+
+#[tokio::test]
+async fn sends_the_active_branch_as_context() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::Text("first".to_string())).await;
+    client.reply().await;
+    client.send(&ClientMessage::Text("second".to_string())).await;
+    client.reply().await;
+
+    let request = server.backend.last_request();
+    let messages: Vec<(&str, &str)> = request["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| (m["role"].as_str().unwrap(), m["content"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        messages,
+        [("user", "first"), ("assistant", "alpha: first"), ("user", "second")]
+    );
+    assert_eq!(request["stream"], true);
+}
+
+#[tokio::test]
+async fn switches_models() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::SetModel("beta".to_string())).await;
+    match client.recv().await {
+        ServerMessage::ModelChanged(model) => assert_eq!(model, "beta"),
+        other => panic!("expected ModelChanged, got {:?}", other),
+    }
+
+    client.send(&ClientMessage::Text("hi".to_string())).await;
+    assert_eq!(client.reply().await.text(), "beta: hi");
+
+    // The choice and the model of each reply are kept with the conversation.
+    let (_other, history, _) = server.connect().await;
+    assert_eq!(history.current_model, "beta");
+    let reply = history.active_path().last().cloned().cloned().unwrap();
+    assert_eq!(reply.model.as_deref(), Some("beta"));
+}
+
+#[tokio::test]
+async fn reports_unknown_models() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::SetModel("gamma".to_string())).await;
+    match client.recv().await {
+        ServerMessage::Error(error) => assert!(error.contains("not found"), "{}", error),
+        other => panic!("expected Error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn reports_backend_errors_and_ends_the_message() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::Text(format!("{} now", FAIL_PROMPT))).await;
+    let reply = client.reply().await;
+
+    assert!(reply.tokens.is_empty());
+    assert_eq!(reply.errors.len(), 1);
+    assert!(reply.errors[0].contains("backend exploded"), "{}", reply.errors[0]);
+}
+
+#[tokio::test]
+async fn regenerating_adds_a_sibling_reply() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::Text("hi".to_string())).await;
+    client.reply().await;
+
+    client.send(&ClientMessage::Regenerate).await;
+    let rewound = match client.recv().await {
+        ServerMessage::History(history) => history,
+        other => panic!("expected History, got {:?}", other),
+    };
+    assert!(matches!(rewound.active_path().last().unwrap().role, Role::User));
+    assert_eq!(client.reply().await.text(), "alpha: hi");
+
+    let (_other, history, _) = server.connect().await;
+    let leaf = history.active_leaf.unwrap();
+    client.send(&ClientMessage::ListBranches(leaf)).await;
+    match client.recv().await {
+        ServerMessage::Branches { id, siblings } => {
+            assert_eq!(id, leaf);
+            assert_eq!(siblings.len(), 2);
+        }
+        other => panic!("expected Branches, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn persists_conversations_across_restarts() {
+    let dir = TempDir::new();
+    let id = {
+        let server = TestServer::start(&dir).await;
+        let (mut client, history, _) = server.connect().await;
+        client.send(&ClientMessage::Text("remember me".to_string())).await;
+        client.reply().await;
+        history.id
+    };
+
+    let server = TestServer::start(&dir).await;
+    let (_client, history, _) = server.connect().await;
+
+    assert_eq!(history.id, id);
+    let contents: Vec<&str> = history.active_path().iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(contents, ["remember me", "alpha: remember me"]);
+}
+
+#[tokio::test]
+async fn starts_and_lists_conversations() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, first, _) = server.connect().await;
+    client.send(&ClientMessage::Text("one".to_string())).await;
+    client.reply().await;
+
+    client.send(&ClientMessage::NewConversation).await;
+    let second = match client.recv().await {
+        ServerMessage::History(history) => history,
+        other => panic!("expected History, got {:?}", other),
+    };
+    assert_ne!(second.id, first.id);
+    assert!(second.messages.is_empty());
+    client.send(&ClientMessage::Text("two".to_string())).await;
+    client.reply().await;
+
+    client.send(&ClientMessage::ListConversations).await;
+    let ids: Vec<String> = match client.recv().await {
+        ServerMessage::Conversations(conversations) => conversations.into_iter().map(|c| c.id).collect(),
+        other => panic!("expected Conversations, got {:?}", other),
+    };
+    assert_eq!(ids.len(), 2);
+    assert!(ids.contains(&first.id) && ids.contains(&second.id));
+}
+
+#[tokio::test]
+async fn keeps_replies_in_the_conversation_they_started_in() {
+    let dir = TempDir::new();
+    let models = json!({ "models": { "slow": { "backend": "mock", "mock": { "delay_ms": 100 } } } });
+    let server = TestServer::start_with(&dir, models).await;
+    let (mut client, first, _) = server.connect().await;
+    client.send(&ClientMessage::SetModel("slow".to_string())).await;
+    assert!(matches!(client.recv().await, ServerMessage::ModelChanged(_)));
+    let (mut other, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::Text("one two three four five".to_string())).await;
+    assert!(matches!(client.recv().await, ServerMessage::Token(_)));
+    other.send(&ClientMessage::NewConversation).await;
+    let second = match other.recv().await {
+        ServerMessage::History(history) => history,
+        other => panic!("expected History, got {:?}", other),
+    };
+    client.reply().await;
+    // Shown the new conversation once the reply is complete.
+    assert!(matches!(client.recv().await, ServerMessage::History(history) if history.id == second.id));
+
+    let (_third, history, _) = server.connect().await;
+    assert_eq!(history.id, second.id);
+    assert!(history.messages.is_empty(), "{:?}", history.messages);
+    other.send(&ClientMessage::OpenConversation(first.id.clone())).await;
+    match other.recv().await {
+        ServerMessage::History(history) => {
+            let path: Vec<_> = history.active_path().iter().map(|m| (m.role.clone(), m.content.clone())).collect();
+            assert_eq!(path.len(), 2, "{:?}", path);
+            assert_eq!(path[0], (Role::User, "one two three four five".to_string()));
+            assert_eq!(path[1].0, Role::Assistant);
+        }
+        other => panic!("expected History, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn switches_every_client_to_the_opened_conversation() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&dir).await;
+    let (mut client, first, _) = server.connect().await;
+    client.send(&ClientMessage::Text("one".to_string())).await;
+    client.reply().await;
+    let (mut other, _, _) = server.connect().await;
+
+    client.send(&ClientMessage::NewConversation).await;
+    let second = match client.recv().await {
+        ServerMessage::History(history) => history,
+        other => panic!("expected History, got {:?}", other),
+    };
+    match other.recv().await {
+        ServerMessage::History(history) => assert_eq!(history.id, second.id),
+        other => panic!("expected History, got {:?}", other),
+    }
+
+    other.send(&ClientMessage::OpenConversation(first.id.clone())).await;
+    for client in [&mut client, &mut other] {
+        match client.recv().await {
+            ServerMessage::History(history) => assert_eq!(history.id, first.id),
+            other => panic!("expected History, got {:?}", other),
+        }
+    }
+    // Prompts go to the conversation every client was shown.
+    client.send(&ClientMessage::Text("where am I?".to_string())).await;
+    client.reply().await;
+    let (_third, history, _) = server.connect().await;
+    assert_eq!(history.id, first.id);
+    assert_eq!(history.active_path().len(), 4);
+}
@@ -0,0 +1,361 @@
+// Browser client speaking the same ClientMessage/ServerMessage protocol as the TUI.
+// Messages are serde's externally tagged enums: {"Text": "..."}, "EndOfMessage", ...
+// wrapped in frames ({"id": 1, "message": ...} and {"reply_to": 1, "message": ...})
+// once the Hello/Welcome handshake is done.
+"use strict";
+
+const PROTOCOL_VERSION = 3;
+
+const state = {
+  socket: null,
+  nextId: 1,
+  history: null,     // mirror of the server's ChatHistory tree
+  streaming: null,   // text of the reply being streamed
+  stats: null,       // GenerationStats of the reply being streamed
+  notices: [],       // local errors shown after the transcript
+  models: [],        // ModelDescriptors of the model list
+};
+
+const $ = (id) => document.getElementById(id);
+
+function connect() {
+  const socket = new WebSocket(`${location.protocol === "https:" ? "wss" : "ws"}://${location.host}/ws`);
+  socket.onopen = () => {
+    $("status").textContent = "Connected";
+    send({ Hello: { version: PROTOCOL_VERSION, client: "web", capabilities: [] } });
+    send("ListConversations");
+  };
+  socket.onclose = () => {
+    $("status").textContent = "Disconnected, retrying…";
+    setTimeout(connect, 2000);
+  };
+  // Rejections during the handshake arrive as bare messages.
+  socket.onmessage = (event) => {
+    const data = JSON.parse(event.data);
+    handle(data && data.message !== undefined ? data.message : data);
+  };
+  state.socket = socket;
+}
+
+function send(message) {
+  if (state.socket && state.socket.readyState === WebSocket.OPEN) {
+    state.socket.send(JSON.stringify({ id: state.nextId++, message }));
+  }
+}
+
+function handle(message) {
+  const [kind, body] = typeof message === "string" ? [message, null] : Object.entries(message)[0];
+  switch (kind) {
+    case "Welcome":
+      break;
+    case "History":
+      state.history = body;
+      state.notices = [];
+      $("models").value = body.current_model;
+      $("auto-approve").checked = !!body.auto_approve;
+      render();
+      break;
+    case "Token":
+      state.streaming = (state.streaming || "") + body;
+      render();
+      break;
+    case "Stats":
+      state.stats = body;
+      break;
+    case "EndOfMessage":
+      // The server appended the reply to the active branch; do the same.
+      push("Assistant", state.streaming || "", state.stats);
+      state.streaming = null;
+      state.stats = null;
+      send("ListConversations");
+      render();
+      break;
+    case "ToolCalls":
+      // The reply goes on after the results; stream its next part afresh.
+      push("Assistant", state.streaming || "", state.stats, { tool_calls: body });
+      state.streaming = "";
+      state.stats = null;
+      render();
+      break;
+    case "ToolApproval":
+      // Refusing keeps the reply going; Stop ends it.
+      send({ ApproveTool: { call_id: body.id, approved: confirm(`Allow ${body.name}(${body.arguments})?`) } });
+      break;
+    case "ToolResult":
+      push("Tool", body.content, null, { tool_call_id: body.call_id });
+      render();
+      break;
+    case "ModelChanged":
+      for (const model of state.models) {
+        if (model.name === body) model.state = "loaded";
+        else if (model.state === "loaded") model.state = "available";
+      }
+      renderModels(state.models);
+      $("models").value = body;
+      $("status").textContent = `Model: ${body}`;
+      $("usage").textContent = "";
+      break;
+    case "AvailableModels":
+      state.models = body;
+      renderModels(body);
+      break;
+    case "Conversations":
+      renderConversations(body);
+      break;
+    case "ConversationUpdated":
+      if (state.history && state.history.id === body.id) {
+        state.history.title = body.title;
+        render();
+      }
+      send("ListConversations");
+      break;
+    case "ResourceUsage":
+      $("usage").textContent = `llama-server: ${formatUsage(body)}`;
+      break;
+    case "Error":
+      state.notices.push(body);
+      render();
+      break;
+    default:
+      // Replies this page does not use (search results, exports, ...).
+      break;
+  }
+}
+
+// Tree helpers, matching shared::ChatHistory.
+function get(id) {
+  return state.history.messages.find((m) => m.id === id);
+}
+
+function activePath() {
+  const path = [];
+  let cursor = state.history.active_leaf != null ? get(state.history.active_leaf) : null;
+  while (cursor) {
+    path.unshift(cursor);
+    cursor = cursor.parent != null ? get(cursor.parent) : null;
+  }
+  return path;
+}
+
+function toolCall(id) {
+  for (const message of state.history.messages) {
+    const call = (message.tool_calls || []).find((c) => c.id === id);
+    if (call) return call;
+  }
+  return null;
+}
+
+function siblings(message) {
+  return state.history.messages.filter((m) => m.parent === message.parent);
+}
+
+function push(role, content, stats, extra) {
+  const id = state.history.messages.reduce((max, m) => Math.max(max, m.id), 0) + 1;
+  state.history.messages.push({
+    id,
+    parent: state.history.active_leaf,
+    role,
+    content,
+    model: role === "Assistant" ? state.history.current_model : undefined,
+    timestamp: Math.floor(Date.now() / 1000),
+    stats: stats || undefined,
+    ...extra,
+  });
+  state.history.active_leaf = id;
+}
+
+// Same fields as GenerationStats::summary.
+function formatStats(stats) {
+  const parts = [];
+  if (stats.completion_tokens != null) parts.push(`${stats.completion_tokens} tokens`);
+  if (stats.tokens_per_second != null) parts.push(`${stats.tokens_per_second.toFixed(1)} tok/s`);
+  if (stats.ttft_ms != null) parts.push(`TTFT ${stats.ttft_ms} ms`);
+  parts.push(`${(stats.total_ms / 1000).toFixed(1)} s`);
+  return parts.join(" · ");
+}
+
+function formatBytes(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let unit = 0;
+  while (bytes >= 1024 && unit < units.length - 1) {
+    bytes /= 1024;
+    unit++;
+  }
+  return unit === 0 ? `${bytes} B` : `${bytes.toFixed(1)} ${units[unit]}`;
+}
+
+function formatUsage(usage) {
+  const parts = [`${formatBytes(usage.rss_bytes)} RSS`];
+  if (usage.cpu_percent != null) parts.push(`${Math.round(usage.cpu_percent)}% CPU`);
+  parts.push(`${usage.threads} threads`);
+  return parts.join(" · ");
+}
+
+function render() {
+  if (!state.history) return;
+  $("title").textContent = state.history.title || "Chat";
+  const container = $("messages");
+  container.replaceChildren();
+
+  for (const message of activePath()) {
+    container.appendChild(renderMessage(message));
+  }
+  if (state.streaming !== null) {
+    container.appendChild(renderMessage({ role: "Assistant", content: state.streaming, model: state.history.current_model }));
+  }
+  for (const notice of state.notices) {
+    const div = document.createElement("div");
+    div.className = "message notice";
+    div.textContent = notice;
+    container.appendChild(div);
+  }
+  container.scrollTop = container.scrollHeight;
+}
+
+function renderMessage(message) {
+  const div = document.createElement("div");
+  div.className = `message ${message.role.toLowerCase()}`;
+
+  const meta = document.createElement("div");
+  meta.className = "meta";
+  const who = document.createElement("span");
+  if (message.role === "User") {
+    who.textContent = "You";
+  } else if (message.role === "Tool") {
+    const call = toolCall(message.tool_call_id);
+    who.textContent = `Tool${call ? ` (${call.name})` : ""}`;
+  } else {
+    who.textContent = `Assistant${message.model ? ` (${message.model})` : ""}`;
+  }
+  meta.appendChild(who);
+
+  if (message.stats) {
+    const stats = document.createElement("span");
+    stats.className = "stats";
+    stats.textContent = formatStats(message.stats);
+    meta.appendChild(stats);
+  }
+
+  if (message.id !== undefined) {
+    const alternatives = siblings(message);
+    if (alternatives.length > 1) {
+      const index = alternatives.findIndex((m) => m.id === message.id);
+      const branches = document.createElement("span");
+      branches.className = "branches";
+      const prev = document.createElement("button");
+      prev.textContent = "‹";
+      prev.disabled = index === 0;
+      prev.onclick = () => send({ SwitchBranch: alternatives[index - 1].id });
+      const next = document.createElement("button");
+      next.textContent = "›";
+      next.disabled = index === alternatives.length - 1;
+      next.onclick = () => send({ SwitchBranch: alternatives[index + 1].id });
+      branches.append(prev, ` ${index + 1}/${alternatives.length} `, next);
+      meta.appendChild(branches);
+    }
+  }
+
+  const content = document.createElement("div");
+  content.className = "content";
+  content.textContent = message.content;
+  div.append(meta, content);
+  for (const call of message.tool_calls || []) {
+    const line = document.createElement("div");
+    line.className = "call";
+    line.textContent = `Calling ${call.name}(${call.arguments})`;
+    div.appendChild(line);
+  }
+  return div;
+}
+
+function renderModels(models) {
+  const select = $("models");
+  select.replaceChildren();
+  const groups = {};
+  for (const model of models) {
+    const option = document.createElement("option");
+    option.value = model.name;
+    option.textContent = model.display_name || model.name;
+    if (model.state && model.state !== "available") option.textContent += ` (${model.state})`;
+    option.disabled = model.state === "loading";
+    option.title = formatModel(model);
+    if (!groups[model.backend]) {
+      groups[model.backend] = document.createElement("optgroup");
+      groups[model.backend].label = model.backend;
+      select.appendChild(groups[model.backend]);
+    }
+    groups[model.backend].appendChild(option);
+  }
+  if (state.history) select.value = state.history.current_model;
+}
+
+function formatModel(model) {
+  const parts = [model.name, ...(model.capabilities || [])];
+  if (model.description) parts.unshift(model.description);
+  if (model.tags && model.tags.length) parts.push(model.tags.map((t) => `[${t}]`).join(" "));
+  if (model.context_size) parts.push(`${model.context_size} ctx`);
+  if (model.file_size) parts.push(formatBytes(model.file_size));
+  if (model.metadata) parts.push(formatMetadata(model.metadata));
+  if (model.error) parts.push(model.error);
+  return parts.join(" · ");
+}
+
+function formatMetadata(metadata) {
+  const parts = [];
+  if (metadata.architecture) parts.push(metadata.architecture);
+  if (metadata.parameters) parts.push(`${(metadata.parameters / 1e9).toFixed(1)}B`);
+  if (metadata.quantization) parts.push(metadata.quantization);
+  return parts.join(" · ");
+}
+
+function renderConversations(conversations) {
+  const list = $("conversations");
+  list.replaceChildren();
+  for (const conversation of conversations) {
+    const item = document.createElement("li");
+    item.textContent = conversation.title || conversation.id;
+    item.title = conversation.summary || "";
+    if (state.history && conversation.id === state.history.id) item.className = "active";
+    item.onclick = () => send({ OpenConversation: conversation.id });
+    list.appendChild(item);
+  }
+}
+
+$("composer").onsubmit = (event) => {
+  event.preventDefault();
+  const text = $("input").value.trim();
+  if (!text || !state.history || state.streaming !== null) return;
+  push("User", text);
+  state.streaming = "";
+  send({ Text: text });
+  $("input").value = "";
+  render();
+};
+
+$("input").onkeydown = (event) => {
+  if (event.key === "Enter" && !event.shiftKey) {
+    event.preventDefault();
+    $("composer").requestSubmit();
+  }
+};
+
+$("regenerate").onclick = () => {
+  if (state.streaming !== null) return;
+  state.streaming = "";
+  send("Regenerate");
+};
+
+$("stop").onclick = () => {
+  if (state.streaming !== null) send("Stop");
+};
+
+$("models").onchange = (event) => {
+  $("status").textContent = `Loading ${event.target.value}…`;
+  send({ SetModel: event.target.value });
+};
+
+$("auto-approve").onchange = (event) => send({ SetAutoApprove: event.target.checked });
+
+$("new-conversation").onclick = () => send("NewConversation");
+
+connect();
@@ -1,35 +1,896 @@
 use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Identifier of a message node inside a [`ChatHistory`] tree.
+pub type MessageId = u64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum Role {
     User,
     Assistant,
+    /// Result of a tool the assistant asked to call, see [`Message::tool_calls`].
+    Tool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Message {
+    /// Unique id of this message within its conversation.
+    #[serde(default)]
+    pub id: MessageId,
+    /// The message this one replies to, `None` for a root message.
+    #[serde(default)]
+    pub parent: Option<MessageId>,
     pub role: Role,
     pub content: String,
+    /// Model that produced an assistant message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Creation time in seconds since the Unix epoch, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// How the reply was generated, for assistant messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<GenerationStats>,
+    /// Tools an assistant message asks to call; their results follow it as
+    /// `Role::Tool` messages.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// The call a `Role::Tool` message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// A model's request to run a tool.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ToolCall {
+    /// Id chosen by the model, repeated by the result.
+    pub id: String,
+    pub name: String,
+    /// Arguments as a JSON object, exactly as the model wrote them.
+    pub arguments: String,
+}
+
+impl ToolCall {
+    /// One-line form for transcripts, e.g. `current_time({})`.
+    pub fn summary(&self) -> String {
+        format!("{}({})", self.name, self.arguments)
+    }
+}
+
+/// Performance of one generated reply. Counts and speeds are those reported
+/// by the backend, when it reports them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GenerationStats {
+    /// Milliseconds from sending the request to the first token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttft_ms: Option<u64>,
+    /// Milliseconds from sending the request to the end of the reply.
+    pub total_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<u64>,
+    /// Prompt processing speed in tokens per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_per_second: Option<f64>,
+    /// Generation speed in tokens per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_second: Option<f64>,
+}
+
+impl GenerationStats {
+    /// One-line summary, e.g. `"42 tokens · 31.2 tok/s · TTFT 180 ms"`.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(tokens) = self.completion_tokens {
+            parts.push(format!("{} tokens", tokens));
+        }
+        if let Some(speed) = self.tokens_per_second {
+            parts.push(format!("{:.1} tok/s", speed));
+        }
+        if let Some(ttft) = self.ttft_ms {
+            parts.push(format!("TTFT {} ms", ttft));
+        }
+        if let (Some(tokens), Some(speed)) = (self.prompt_tokens, self.prompt_per_second) {
+            parts.push(format!("prompt {} @ {:.1} tok/s", tokens, speed));
+        }
+        parts.push(format!("{:.1} s", self.total_ms as f64 / 1000.0));
+        parts.join(" · ")
+    }
+}
+
+/// Resources used by the process of a backend, e.g. a managed llama-server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProcessUsage {
+    /// Model the process serves.
+    pub model: String,
+    pub pid: u32,
+    /// Resident memory.
+    pub rss_bytes: u64,
+    /// User plus system CPU time since the process started.
+    pub cpu_seconds: f64,
+    /// CPU usage since the previous sample; 100 per fully used core.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<f64>,
+    pub threads: u64,
+    /// Memory the machine has available for new allocations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_available_bytes: Option<u64>,
 }
 
+impl ProcessUsage {
+    /// One-line summary, e.g. `"4.1 GiB RSS · 350% CPU · 12 threads"`.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("{} RSS", format_bytes(self.rss_bytes))];
+        if let Some(cpu) = self.cpu_percent {
+            parts.push(format!("{:.0}% CPU", cpu));
+        }
+        parts.push(format!("{} threads", self.threads));
+        if let Some(available) = self.memory_available_bytes {
+            parts.push(format!("{} free", format_bytes(available)));
+        }
+        parts.join(" · ")
+    }
+}
+
+/// What a GGUF model file says about itself, read from its header.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GgufMetadata {
+    /// Model architecture, e.g. `"llama"` or `"qwen2"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    /// Name the model was published under.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Number of weights.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<u64>,
+    /// Quantization of most weights, e.g. `"Q4_K_M"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<String>,
+    /// Context length the model was trained with, in tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    /// Jinja chat template embedded in the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_template: Option<String>,
+    /// Tokenizer model, e.g. `"llama"` (SentencePiece) or `"gpt2"` (BPE).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vocab_size: Option<u64>,
+    /// Whether the model declares a pooling type, i.e. produces embeddings.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub embedding: bool,
+}
+
+impl GgufMetadata {
+    /// One-line summary, e.g. `"llama · 6.7B · Q4_K_M · 4096 ctx"`.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(architecture) = &self.architecture {
+            parts.push(architecture.clone());
+        }
+        if let Some(parameters) = self.parameters {
+            parts.push(format_parameters(parameters));
+        }
+        if let Some(quantization) = &self.quantization {
+            parts.push(quantization.clone());
+        }
+        if let Some(context) = self.context_length {
+            parts.push(format!("{} ctx", context));
+        }
+        parts.join(" · ")
+    }
+}
+
+/// Formats a parameter count the way models are named, e.g. `"6.7B"`.
+pub fn format_parameters(parameters: u64) -> String {
+    match parameters {
+        p if p >= 1_000_000_000 => format!("{:.1}B", p as f64 / 1e9),
+        p if p >= 1_000_000 => format!("{:.0}M", p as f64 / 1e6),
+        p => p.to_string(),
+    }
+}
+
+/// An entry of [`ServerMessage::AvailableModels`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ModelDescriptor {
+    /// Name to select the model with in `ClientMessage::SetModel`.
+    pub name: String,
+    /// Name to show: configured, else the one in the GGUF file, else `name`.
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Backend kind: `managed`, `external`, `openai`, `mock` or `replay`.
+    #[serde(default)]
+    pub backend: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<Capability>,
+    /// Context window the model is run with, in tokens, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_size: Option<u64>,
+    /// Size of the GGUF file, all shards together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size: Option<u64>,
+    #[serde(default)]
+    pub state: ModelState,
+    /// Why the last attempt to load the model failed, while `state` is `failed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Header of the model's GGUF file, if it has a readable one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<GgufMetadata>,
+}
+
+/// What a model can do besides chatting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    /// Accepts images, through a multimodal projector.
+    Vision,
+    /// Its chat template supports tool calls.
+    Tools,
+    /// Produces embeddings rather than text.
+    Embeddings,
+}
+
+impl Capability {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Capability::Vision => "vision",
+            Capability::Tools => "tools",
+            Capability::Embeddings => "embeddings",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ModelState {
+    /// Not running; selecting it starts it.
+    #[default]
+    Available,
+    /// Being started.
+    Loading,
+    /// Serving the chat.
+    Loaded,
+    /// The last attempt to load it failed.
+    Failed,
+}
+
+/// Formats a byte count with a binary unit, e.g. `"4.1 GiB"`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// A conversation stored as a tree of messages.
+///
+/// Regenerating a reply or editing a prompt adds a sibling node instead of
+/// overwriting the old one. `active_leaf` selects which branch is currently
+/// shown and sent to the model as context.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct ChatHistory {
+    /// Conversation id, assigned by the server's conversation store.
+    #[serde(default)]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// One-paragraph summary of the conversation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Set once the user edits the title or summary; they are then never
+    /// regenerated automatically.
+    #[serde(default)]
+    pub user_titled: bool,
+    /// Tool calls run without asking the user first, except those that
+    /// always need approval.
+    #[serde(default)]
+    pub auto_approve: bool,
+    /// Creation time in seconds since the Unix epoch, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// Every message of every branch, in creation order.
     pub messages: Vec<Message>,
+    /// Last message of the active branch, `None` for an empty conversation.
+    #[serde(default)]
+    pub active_leaf: Option<MessageId>,
     pub current_model: String,
 }
 
+impl ChatHistory {
+    /// Creates an empty conversation using `model`.
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            id: String::new(),
+            title: None,
+            summary: None,
+            user_titled: false,
+            auto_approve: false,
+            created_at: Some(unix_now()),
+            messages: Vec::new(),
+            active_leaf: None,
+            current_model: model.into(),
+        }
+    }
+
+    /// Describes this conversation for listings.
+    pub fn info(&self) -> ConversationInfo {
+        ConversationInfo {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            summary: self.summary.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at(),
+            message_count: self.messages.len(),
+        }
+    }
+
+    /// Time of the latest activity: the newest message, or the creation time.
+    pub fn updated_at(&self) -> Option<u64> {
+        self.messages
+            .iter()
+            .filter_map(|m| m.timestamp)
+            .chain(self.created_at)
+            .max()
+    }
+
+    /// Upgrades a history written before messages had ids.
+    ///
+    /// Legacy files contain a flat list; it is turned into a single branch
+    /// ending at the last message.
+    pub fn migrate_linear(&mut self) {
+        if self.active_leaf.is_some() || self.messages.is_empty() {
+            return;
+        }
+        let mut parent = None;
+        for (i, message) in self.messages.iter_mut().enumerate() {
+            message.id = i as MessageId + 1;
+            message.parent = parent;
+            parent = Some(message.id);
+        }
+        self.active_leaf = parent;
+    }
+
+    /// Looks up a message by id.
+    pub fn get(&self, id: MessageId) -> Option<&Message> {
+        self.messages.iter().find(|m| m.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: MessageId) -> Option<&mut Message> {
+        self.messages.iter_mut().find(|m| m.id == id)
+    }
+
+    /// Returns the messages from the root down to `id`, inclusive.
+    pub fn path_to(&self, id: MessageId) -> Vec<&Message> {
+        let mut path = Vec::new();
+        let mut cursor = self.get(id);
+        while let Some(message) = cursor {
+            path.push(message);
+            cursor = message.parent.and_then(|p| self.get(p));
+        }
+        path.reverse();
+        path
+    }
+
+    /// Returns the messages of the active branch, root first.
+    pub fn active_path(&self) -> Vec<&Message> {
+        self.active_leaf
+            .map(|leaf| self.path_to(leaf))
+            .unwrap_or_default()
+    }
+
+    /// Returns the direct replies to `parent` (roots for `None`), oldest first.
+    pub fn children(&self, parent: Option<MessageId>) -> Vec<&Message> {
+        self.messages.iter().filter(|m| m.parent == parent).collect()
+    }
+
+    /// Returns `id` and its alternative branches, oldest first.
+    pub fn siblings(&self, id: MessageId) -> Vec<&Message> {
+        match self.get(id) {
+            Some(message) => self.children(message.parent),
+            None => Vec::new(),
+        }
+    }
+
+    /// Appends a message as a reply to `parent` and makes it the active leaf.
+    pub fn add_child(
+        &mut self,
+        parent: Option<MessageId>,
+        role: Role,
+        content: impl Into<String>,
+    ) -> MessageId {
+        let id = self.messages.iter().map(|m| m.id).max().unwrap_or(0) + 1;
+        let model = match role {
+            Role::Assistant => Some(self.current_model.clone()),
+            Role::User | Role::Tool => None,
+        };
+        self.messages.push(Message {
+            id,
+            parent,
+            role,
+            content: content.into(),
+            model,
+            timestamp: Some(unix_now()),
+            stats: None,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        });
+        self.active_leaf = Some(id);
+        id
+    }
+
+    /// Appends a message to the end of the active branch.
+    pub fn push(&mut self, role: Role, content: impl Into<String>) -> MessageId {
+        self.add_child(self.active_leaf, role, content)
+    }
+
+    /// Appends the result of tool call `call_id` to the active branch.
+    pub fn push_tool_result(&mut self, call_id: impl Into<String>, content: impl Into<String>) -> MessageId {
+        let id = self.push(Role::Tool, content);
+        if let Some(message) = self.get_mut(id) {
+            message.tool_call_id = Some(call_id.into());
+        }
+        id
+    }
+
+    /// Looks up the call with id `call_id` among the assistant messages.
+    pub fn tool_call(&self, call_id: &str) -> Option<&ToolCall> {
+        self.messages.iter().flat_map(|m| &m.tool_calls).find(|call| call.id == call_id)
+    }
+
+    /// Makes the branch through `id` active.
+    ///
+    /// The new active leaf is found by following the most recent reply from
+    /// `id` downwards. Returns `false` if `id` does not exist.
+    pub fn switch_to(&mut self, id: MessageId) -> bool {
+        if self.get(id).is_none() {
+            return false;
+        }
+        let mut leaf = id;
+        while let Some(child) = self.children(Some(leaf)).last() {
+            leaf = child.id;
+        }
+        self.active_leaf = Some(leaf);
+        true
+    }
+}
+
+/// Listing entry for a stored conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ConversationInfo {
+    pub id: String,
+    pub title: Option<String>,
+    pub summary: Option<String>,
+    pub created_at: Option<u64>,
+    pub updated_at: Option<u64>,
+    /// Number of messages across all branches.
+    pub message_count: usize,
+}
+
+/// Current time in seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Output formats supported by conversation export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl ExportFormat {
+    /// File extension used for exported files.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// A full-text search over all stored conversations.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchQuery {
+    /// Words that must all occur; `"quoted text"` must occur as a phrase.
+    pub text: String,
+    pub role: Option<Role>,
+    pub model: Option<String>,
+    /// Only messages at or after this time (Unix seconds).
+    pub after: Option<u64>,
+    /// Only messages before this time (Unix seconds).
+    pub before: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+impl SearchQuery {
+    /// Parses a query such as `role:user model:llama after:2025-01-01 "exact phrase" words`.
+    ///
+    /// Unknown or malformed filters are kept as search text.
+    pub fn parse(input: &str) -> Self {
+        let mut query = SearchQuery::default();
+        let mut text = Vec::new();
+        let mut rest = input.trim();
+        while !rest.is_empty() {
+            let (token, tail) = if let Some(quoted) = rest.strip_prefix('"') {
+                match quoted.find('"') {
+                    Some(end) => (&rest[..end + 2], &quoted[end + 1..]),
+                    None => (rest, ""),
+                }
+            } else {
+                rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()))
+            };
+            rest = tail.trim_start();
+
+            let filter = token.split_once(':');
+            match filter {
+                Some(("role", "user")) => query.role = Some(Role::User),
+                Some(("role", "assistant")) => query.role = Some(Role::Assistant),
+                Some(("role", "tool")) => query.role = Some(Role::Tool),
+                Some(("model", model)) if !model.is_empty() => query.model = Some(model.to_string()),
+                Some(("after", date)) if parse_date(date).is_some() => query.after = parse_date(date),
+                Some(("before", date)) if parse_date(date).is_some() => query.before = parse_date(date),
+                _ => text.push(token),
+            }
+        }
+        query.text = text.join(" ");
+        query
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date as Unix seconds at midnight UTC.
+pub fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    u64::try_from(days * 86400).ok()
+}
+
+/// A message matching a [`SearchQuery`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub conversation_id: String,
+    pub conversation_title: Option<String>,
+    pub message_id: MessageId,
+    pub role: Role,
+    pub model: Option<String>,
+    pub timestamp: Option<u64>,
+    /// Excerpt of the message around the first match.
+    pub snippet: String,
+    /// Byte ranges of `snippet` that matched the query.
+    pub highlights: Vec<(usize, usize)>,
+}
+
+/// Version of the WebSocket protocol described by the types below. Client
+/// and server must speak the same version.
+///
+/// 2: `AvailableModels` carries [`ModelDescriptor`]s instead of bare names.
+/// 3: Replies may call tools: `Role::Tool`, `ToolCalls` and `ToolResult`.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Client-chosen id of a request, echoed on every response to it.
+pub type RequestId = u64;
+
+/// A message from the client. The first one on a connection must be
+/// `ClientMessage::Hello`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientFrame {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<RequestId>,
+    pub message: ClientMessage,
+}
+
+/// A message from the server.
+///
+/// Peers that fail the handshake are instead sent a bare
+/// `ServerMessage::Error`, readable by any version, before the server closes
+/// the connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerFrame {
+    /// The request this answers; `None` for events such as changes made by
+    /// other clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<RequestId>,
+    pub message: ServerMessage,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerMessage {
+    /// Accepts `ClientMessage::Hello`; followed by `History` and `AvailableModels`.
+    Welcome {
+        version: u32,
+        /// Server name and version, for logs.
+        server: String,
+        /// Optional features the server supports, e.g. `"stop"`.
+        capabilities: Vec<String>,
+    },
     History(ChatHistory),
     Token(String), // For streaming response
+    /// How the reply was generated; sent right before its `EndOfMessage`.
+    Stats(GenerationStats),
     EndOfMessage,
+    /// The text streamed so far ends an assistant message asking to call
+    /// these tools; clients append it with them, as on `EndOfMessage`. The
+    /// reply goes on once the results are in.
+    ToolCalls(Vec<ToolCall>),
+    /// Result of a tool call; clients append it as a `Role::Tool` message.
+    ToolResult {
+        call_id: String,
+        content: String,
+    },
+    /// The model wants to make this call, which has to be allowed first;
+    /// answer with `ClientMessage::ApproveTool`. Sent between `ToolCalls`
+    /// and the call's `ToolResult`.
+    ToolApproval(ToolCall),
     ModelChanged(String),
-    AvailableModels(Vec<String>),
+    /// Configured models, sorted by name.
+    AvailableModels(Vec<ModelDescriptor>),
+    /// Alternative branches of a message, answering `ClientMessage::ListBranches`.
+    Branches {
+        id: MessageId,
+        siblings: Vec<Message>,
+    },
+    /// Result of `ClientMessage::Export`: a file path when saved, the content otherwise.
+    Exported {
+        format: ExportFormat,
+        path: Option<String>,
+        content: Option<String>,
+    },
+    SearchResults(Vec<SearchHit>),
+    /// Stored conversations, most recently active first.
+    Conversations(Vec<ConversationInfo>),
+    /// A conversation's title or summary changed, e.g. after background generation.
+    ConversationUpdated(ConversationInfo),
+    /// Periodic sample of the backend process' resources, sent to every
+    /// client while a model runs in a process of this server.
+    ResourceUsage(ProcessUsage),
     Error(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientMessage {
+    /// Opens the handshake; answered with `ServerMessage::Welcome`.
+    Hello {
+        version: u32,
+        /// Client name and version, for logs.
+        client: String,
+        /// Optional features the client supports.
+        capabilities: Vec<String>,
+    },
     Text(String),
     SetModel(String),
+    /// Generate a new reply to the last prompt, keeping the previous one as a branch.
+    Regenerate,
+    /// Replace a user message with new content on a new branch and generate a reply.
+    Edit {
+        id: MessageId,
+        content: String,
+    },
+    ListBranches(MessageId),
+    /// Make the branch through the given message active.
+    SwitchBranch(MessageId),
+    /// Export the current conversation, or all of them, optionally saving to a file.
+    Export {
+        format: ExportFormat,
+        all: bool,
+        save: bool,
+    },
+    Search(SearchQuery),
+    /// Continue a stored conversation. Clients share the open conversation,
+    /// so every client is sent its `ServerMessage::History`.
+    OpenConversation(String),
+    /// Start an empty conversation, sent to every client like one opened.
+    NewConversation,
+    ListConversations,
+    /// Override a conversation's title and/or summary.
+    SetConversationInfo {
+        id: String,
+        title: Option<String>,
+        summary: Option<String>,
+    },
+    /// Cancel the reply being streamed. The partial reply is kept and
+    /// finished with `ServerMessage::EndOfMessage`; ignored when idle.
+    Stop,
+    /// Scan the models directories again; answered with `AvailableModels`,
+    /// which every client receives.
+    RescanModels,
+    /// Allows or refuses the call of a `ServerMessage::ToolApproval`. A
+    /// `Stop` instead refuses it and ends the reply.
+    ApproveTool {
+        call_id: String,
+        approved: bool,
+    },
+    /// Lets tool calls of the current conversation run without approval;
+    /// answered with `ServerMessage::History`.
+    SetAutoApprove(bool),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A conversation with two prompts on the first reply, the second
+    /// regenerated once:
+    ///
+    /// ```text
+    /// 1 user ── 2 assistant ─┬─ 3 user ── 4 assistant
+    ///                        └─ 5 user ─┬─ 6 assistant
+    ///                                   └─ 7 assistant
+    /// ```
+    fn tree() -> ChatHistory {
+        let mut history = ChatHistory::new("alpha");
+        history.push(Role::User, "hi");
+        let reply = history.push(Role::Assistant, "hello");
+        history.push(Role::User, "first question");
+        history.push(Role::Assistant, "first answer");
+        let second = history.add_child(Some(reply), Role::User, "second question");
+        history.push(Role::Assistant, "second answer");
+        history.add_child(Some(second), Role::Assistant, "second answer, again");
+        history
+    }
+
+    fn ids(messages: Vec<&Message>) -> Vec<MessageId> {
+        messages.iter().map(|m| m.id).collect()
+    }
+
+    fn legacy_message(role: Role, content: &str) -> Message {
+        Message {
+            id: 0,
+            parent: None,
+            role,
+            content: content.to_string(),
+            model: None,
+            timestamp: None,
+            stats: None,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    #[test]
+    fn branches_share_their_common_messages() {
+        let history = tree();
+        assert_eq!(history.active_leaf, Some(7));
+        assert_eq!(ids(history.active_path()), [1, 2, 5, 7]);
+        assert_eq!(ids(history.path_to(4)), [1, 2, 3, 4]);
+        assert_eq!(history.get(7).unwrap().model.as_deref(), Some("alpha"));
+        assert!(history.path_to(99).is_empty());
+        assert!(ChatHistory::new("alpha").active_path().is_empty());
+    }
+
+    #[test]
+    fn lists_siblings_oldest_first() {
+        let history = tree();
+        assert_eq!(ids(history.siblings(5)), [3, 5]);
+        assert_eq!(ids(history.siblings(6)), [6, 7]);
+        assert_eq!(ids(history.siblings(1)), [1]);
+        assert!(history.siblings(99).is_empty());
+    }
+
+    #[test]
+    fn switching_descends_to_the_newest_leaf() {
+        let mut history = tree();
+        assert!(history.switch_to(3));
+        assert_eq!(history.active_leaf, Some(4));
+        assert!(history.switch_to(2));
+        assert_eq!(history.active_leaf, Some(7), "the newest reply of the newest prompt");
+        assert!(history.switch_to(6));
+        assert_eq!(ids(history.active_path()), [1, 2, 5, 6]);
+
+        assert!(!history.switch_to(99));
+        assert_eq!(history.active_leaf, Some(6));
+    }
+
+    #[test]
+    fn parses_search_filters_and_phrases() {
+        let query = SearchQuery::parse(r#"role:assistant  model:qwen2.5 after:2025-01-01 before:2025-02-01 "exact phrase" words"#);
+        assert_eq!(query.role, Some(Role::Assistant));
+        assert_eq!(query.model.as_deref(), Some("qwen2.5"));
+        assert_eq!(query.after, Some(1735689600));
+        assert_eq!(query.before, Some(1738368000));
+        assert_eq!(query.text, r#""exact phrase" words"#);
+        assert_eq!(query.limit, None);
+
+        let query = SearchQuery::parse(r#"role:tool "unfinished phrase"#);
+        assert_eq!(query.role, Some(Role::Tool));
+        assert_eq!(query.text, r#""unfinished phrase"#);
+    }
+
+    #[test]
+    fn keeps_malformed_filters_as_text() {
+        let query = SearchQuery::parse("role:system model: before:2025-02-30 after:2025-13-01 before:yesterday key:value");
+        assert_eq!(query.role, None);
+        assert_eq!(query.model, None);
+        assert_eq!(query.after, None);
+        assert_eq!(query.before, None);
+        assert_eq!(query.text, "role:system model: before:2025-02-30 after:2025-13-01 before:yesterday key:value");
+        assert!(SearchQuery::parse("   ").text.is_empty());
+    }
+
+    #[test]
+    fn parses_dates_as_utc_midnight() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-03-01"), Some(951868800));
+        assert_eq!(parse_date("2024-02-29"), Some(1709164800));
+        assert_eq!(parse_date("2025-12-31"), Some(1767139200));
+        for invalid in ["2023-02-29", "2100-02-29", "2025-04-31", "2025-00-10", "2025-01-00", "2025-01", "2025-01-01T00", "1969-12-31", "date"] {
+            assert_eq!(parse_date(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn migrates_flat_histories_to_one_branch() {
+        let mut history = ChatHistory::new("alpha");
+        history.messages = vec![
+            legacy_message(Role::User, "hi"),
+            legacy_message(Role::Assistant, "hello"),
+            legacy_message(Role::User, "bye"),
+        ];
+        history.migrate_linear();
+        assert_eq!(history.active_leaf, Some(3));
+        let path: Vec<(MessageId, Option<MessageId>, &str)> =
+            history.active_path().iter().map(|m| (m.id, m.parent, m.content.as_str())).collect();
+        assert_eq!(path, [(1, None, "hi"), (2, Some(1), "hello"), (3, Some(2), "bye")]);
+
+        // Trees and empty histories are left alone.
+        let mut migrated = tree();
+        migrated.switch_to(4);
+        migrated.migrate_linear();
+        assert_eq!(migrated.active_leaf, Some(4));
+        assert_eq!(ids(migrated.path_to(7)), [1, 2, 5, 7]);
+        let mut empty = ChatHistory::new("alpha");
+        empty.migrate_linear();
+        assert_eq!(empty.active_leaf, None);
+    }
 }